@@ -1,92 +1,485 @@
-use std::env;
+use crate::logging::LogFormat;
+use clap::error::ErrorKind;
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use heline_indexer::config::Config;
+use heline_indexer::sink::BACKENDS;
 use std::path::PathBuf;
 
+/// Heline.dev indexer, turn source code to github like html syntax highlighted!
+#[derive(Parser)]
+#[command(name = "hli", version, author)]
 pub struct Arg {
-    pub index_file: PathBuf,
-    pub folder: PathBuf,
-    pub solr_url: String,
-    pub is_index_folder: bool,
-    pub with_delete_folder: bool,
+    /// Config file [default: ./heline.toml, then heline/heline.toml in the
+    /// user config directory]
+    #[arg(long, global = true, env = "HLI_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// State of the indexed repositories [default: hli-state.json]
+    #[arg(long, global = true, env = "HLI_STATE", value_name = "PATH")]
+    pub state: Option<PathBuf>,
+
+    /// Log more, -vv for trace logs
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Log less, -qq for errors only
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "verbose")]
+    pub quiet: u8,
+
+    /// Format of the logs written to stderr
+    #[arg(
+        long,
+        global = true,
+        env = "HLI_LOG_FORMAT",
+        value_enum,
+        default_value = "text"
+    )]
+    pub log_format: LogFormat,
+
+    #[command(flatten)]
+    pub backend: BackendArgs,
+
+    #[command(subcommand)]
+    pub command: Command,
 }
 
 impl Arg {
-    pub fn new() -> Self {
-        Self {
-            index_file: PathBuf::new(),
-            folder: PathBuf::new(),
-            solr_url: String::new(),
-            is_index_folder: false,
-            with_delete_folder: false,
-        }
+    /// Settings of the config file and the environment, overridden by the
+    /// command line.
+    pub fn config(&self) -> Result<Config, String> {
+        let mut config = Config::load(self.config.as_deref())?;
+        self.merge(&mut config);
+        config.validate()?;
+        Ok(config)
     }
 
-    // Parse to: hli index_file.json --folder some/path
-    pub fn parse(mut self) -> Result<Self, String> {
-        self.solr_url = match env::var("BASE_URL") {
-            Ok(val) => val,
-            Err(_) => "http://localhost:8984".to_string(),
-        };
+    // Clap already resolved the command line against the environment, so a
+    // value set there wins over the file.
+    fn merge(&self, config: &mut Config) {
+        let backend = &self.backend;
+        let target = &mut config.backend;
+        if let Some(kind) = &backend.backend {
+            target.kind = kind.clone();
+        }
+        if let Some(url) = &backend.url {
+            target.url = Some(url.clone());
+        }
+        if let Some(core) = &backend.core {
+            target.core = core.clone();
+        }
+        if let Some(api_key) = &backend.api_key {
+            target.api_key = Some(api_key.clone());
+        }
+        if let Some(index_dir) = &backend.index_dir {
+            target.index_dir = index_dir.clone();
+        }
+        if let Some(out_dir) = &backend.out_dir {
+            target.out_dir = out_dir.clone();
+        }
+        if let Some(gzip) = backend.gzip {
+            target.gzip = gzip;
+        }
+        if let Some(state_file) = &self.state {
+            config.index.state_file = state_file.clone();
+        }
 
-        let arg_input: Vec<String> = env::args().collect();
-        for input in &arg_input {
-            if input == "-h" || input == "--help" {
-                self.print_help();
-                std::process::exit(0);
+        if let Command::Index(index) | Command::Reindex(index) = &self.command {
+            if let Some(folder) = &index.folder {
+                config.clone.folder = folder.clone();
             }
-
-            if input == "--delete-dir" {
-                self.with_delete_folder = true;
+            if let Some(delete_dir) = index.delete_dir {
+                config.clone.delete_dir = delete_dir;
+            }
+            if let Some(config_set) = &index.config_set {
+                config.backend.config_set = config_set.clone();
+            }
+            if let Some(concurrency) = index.concurrency {
+                config.index.concurrency = concurrency as usize;
             }
         }
+        if let Command::Daemon(daemon) = &self.command {
+            if let Some(interval) = daemon.interval {
+                config.daemon.interval = interval;
+                config.daemon.cron = None;
+            }
+            if let Some(cron) = &daemon.cron {
+                config.daemon.cron = Some(cron.clone());
+            }
+            if let Some(concurrency) = daemon.concurrency {
+                config.index.concurrency = concurrency as usize;
+            }
+            if let Some(listen) = &daemon.metrics_listen {
+                config.daemon.listen = Some(listen.clone());
+            }
+        }
+        if let Command::Serve(serve) = &self.command {
+            if let Some(listen) = &serve.listen {
+                config.server.listen = listen.clone();
+            }
+            if let Some(workers) = serve.workers {
+                config.server.workers = workers as usize;
+            }
+            if let Some(manifest) = &serve.manifest {
+                config.server.manifest = Some(manifest.clone());
+            }
+        }
+    }
 
-        self.index_file = match arg_input.get(1) {
-            Some(file) => PathBuf::from(file),
-            None => {
-                return Err(format!("Index file path is required!"));
+    /// Exits with a usage error when a command does not support the
+    /// configured backend.
+    pub fn validate(&self, config: &Config) {
+        let arg = self;
+        let solr_only = match &arg.command {
+            Command::Index(index) | Command::Reindex(index) if index.swap_alias.is_some() => {
+                Some("--swap-alias")
             }
+            Command::Import { .. } => Some("import"),
+            _ => None,
         };
-
-        if !self.index_file.exists() {
-            return Err(format!(
-                "Please input the correct index file! Current file is not exists: {}",
-                self.index_file.display()
-            ));
+        if let Some(feature) = solr_only {
+            if config.backend.kind != "solr" {
+                let msg = format!("{} is only supported by the solr backend", feature);
+                Arg::command()
+                    .error(ErrorKind::ArgumentConflict, msg)
+                    .exit();
+            }
         }
+    }
+}
 
-        let option = match arg_input.get(2) {
-            Some(option) => option,
-            None => "",
-        };
+#[derive(Subcommand)]
+pub enum Command {
+    /// Clone and index every repository of a manifest
+    Index(IndexArgs),
+    /// Delete the documents of every repository of a manifest and index them again
+    Reindex(IndexArgs),
+    /// Delete the documents of repositories
+    Delete(ReposArgs),
+    /// Search the local tantivy index
+    Search(SearchArgs),
+    /// Show the number of indexed documents and the last run of repositories
+    Status(StatusArgs),
+    /// Create or verify the fields the indexer writes
+    Schema {
+        #[command(subcommand)]
+        action: SchemaAction,
+    },
+    /// Replay ndjson exports into Solr
+    Import {
+        /// Export files or directories holding them
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Run an http server indexing the repositories posted to it
+    Serve(ServeArgs),
+    /// Re-index the repositories of a manifest whenever their default branch
+    /// moves, checked on a schedule
+    Daemon(DaemonArgs),
+    /// Search a provider for repositories and add them to a manifest
+    Discover(DiscoverArgs),
+    /// Inspect or shrink the cache of rendered chunks
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Inspect the effective configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Print shell completions
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
 
-        self.is_index_folder = option == "--folder";
-        let mut repo_folder = PathBuf::new();
-        match arg_input.get(3) {
-            Some(folder) => repo_folder.push(folder),
-            None => repo_folder.push("repos"),
-        };
+#[derive(Subcommand, Clone, Copy)]
+pub enum SchemaAction {
+    /// Add missing fields and replace the ones that drifted
+    Apply,
+    /// Report drift, exits with 1 when there is any
+    Check,
+}
+
+#[derive(Subcommand)]
+pub enum CacheAction {
+    /// Show the number of entries and the size of the cache
+    Stats,
+    /// Remove the least recently used entries beyond a size
+    Prune(PruneArgs),
+}
+
+#[derive(Subcommand, Clone, Copy)]
+pub enum ConfigAction {
+    /// Print the settings after applying the config file, the environment
+    /// and the command line
+    Show,
+}
+
+// Options left unset fall back to the config file, then to the defaults of
+// `Config`.
+#[derive(Args)]
+pub struct BackendArgs {
+    /// Backend the documents are written to [default: solr]
+    #[arg(long, global = true, env = "BACKEND", value_parser = BACKENDS)]
+    pub backend: Option<String>,
+
+    /// Backend url [default: the backend's local port]
+    #[arg(long, global = true, env = "BASE_URL")]
+    pub url: Option<String>,
+
+    /// Solr core, collection or index name [default: heline]
+    #[arg(long, global = true, env = "SOLR_CORE")]
+    pub core: Option<String>,
+
+    /// Meilisearch or Typesense api key
+    #[arg(long, global = true, env = "API_KEY", hide_env_values = true)]
+    pub api_key: Option<String>,
+
+    /// Directory of the tantivy index [default: heline-index]
+    #[arg(long, global = true, env = "HLI_INDEX_DIR")]
+    pub index_dir: Option<PathBuf>,
+
+    /// Directory of the ndjson export [default: export]
+    #[arg(long, global = true, env = "HLI_OUT_DIR")]
+    pub out_dir: Option<PathBuf>,
+
+    /// Write the ndjson export as .ndjson.gz
+    #[arg(
+        long,
+        global = true,
+        env = "HLI_GZIP",
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub gzip: Option<bool>,
+}
 
-        self.folder = repo_folder;
+#[derive(Args)]
+pub struct IndexArgs {
+    /// Manifest, a json list of repository urls
+    #[arg(value_parser = existing_file)]
+    pub manifest: PathBuf,
+
+    /// Folder the repositories are cloned to [default: repos]
+    #[arg(long, env = "HLI_FOLDER")]
+    pub folder: Option<PathBuf>,
+
+    /// Delete the cloned repository after indexing
+    #[arg(
+        long,
+        env = "HLI_DELETE_DIR",
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub delete_dir: Option<bool>,
+
+    /// Number of repositories indexed at the same time [default: 1]
+    #[arg(long, env = "HLI_CONCURRENCY", value_parser = clap::value_parser!(u16).range(1..))]
+    pub concurrency: Option<u16>,
+
+    /// Index into a new collection and point ALIAS to it once the document
+    /// count is validated (solr only)
+    #[arg(long, env = "HLI_SWAP_ALIAS", value_name = "ALIAS")]
+    pub swap_alias: Option<String>,
+
+    /// Delete the collection ALIAS pointed to before once it is swapped, it
+    /// is kept to roll back to otherwise
+    #[arg(long, requires = "swap_alias")]
+    pub drop_previous: bool,
+
+    /// Clone and render the repositories, report what would be indexed
+    /// without writing to the backend
+    #[arg(long, conflicts_with = "swap_alias")]
+    pub dry_run: bool,
+
+    /// Skip the repositories already indexed, retry failed ones once their
+    /// backoff has passed
+    #[arg(long, conflicts_with = "swap_alias")]
+    pub resume: bool,
+
+    /// Write a json report of the run
+    #[arg(long, env = "HLI_REPORT", value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// Write a JUnit XML report of the run, one test case per repository
+    #[arg(long, env = "HLI_JUNIT", value_name = "PATH")]
+    pub junit: Option<PathBuf>,
+
+    /// Solr config set of the new collection [default: heline]
+    #[arg(long, env = "HLI_CONFIG_SET")]
+    pub config_set: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Address to listen on [default: 127.0.0.1:8080]
+    #[arg(long, env = "HLI_LISTEN", value_name = "ADDR")]
+    pub listen: Option<String>,
+
+    /// Number of jobs running at the same time [default: 2]
+    #[arg(long, env = "HLI_WORKERS", value_parser = clap::value_parser!(u16).range(1..))]
+    pub workers: Option<u16>,
+
+    /// Manifest of the repositories webhooks may index [default: any
+    /// repository]
+    #[arg(long, env = "HLI_MANIFEST", value_parser = existing_file)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct DaemonArgs {
+    /// Manifest, a json list of repository urls, reloaded when it changes or
+    /// on SIGHUP
+    #[arg(value_parser = existing_file)]
+    pub manifest: PathBuf,
+
+    /// Seconds between two checks of a repository [default: 3600]
+    #[arg(long, env = "HLI_INTERVAL", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "cron")]
+    pub interval: Option<u64>,
+
+    /// Cron expression of the checks, e.g. "0 3 * * *"
+    #[arg(long, env = "HLI_CRON")]
+    pub cron: Option<String>,
+
+    /// Number of repositories indexed at the same time [default: 1]
+    #[arg(long, env = "HLI_CONCURRENCY", value_parser = clap::value_parser!(u16).range(1..))]
+    pub concurrency: Option<u16>,
+
+    /// Address to serve Prometheus metrics on, as /metrics
+    #[arg(long, env = "HLI_METRICS_LISTEN", value_name = "ADDR")]
+    pub metrics_listen: Option<String>,
+}
+
+#[derive(Args)]
+pub struct DiscoverArgs {
+    /// Search query, e.g. "language:shell stars:>1000" on GitHub
+    pub query: String,
+
+    /// Manifest the repositories are added to, created when missing
+    #[arg(long, env = "HLI_MANIFEST")]
+    pub manifest: PathBuf,
+
+    /// Provider searched
+    #[arg(long, value_enum, default_value = "github")]
+    pub provider: Provider,
+
+    /// Number of search results, GitHub returns 1000 at most
+    #[arg(long, env = "HLI_LIMIT", default_value_t = 100, value_parser = clap::value_parser!(u16).range(1..))]
+    pub limit: u16,
+
+    /// Print the repositories that would be added without writing the
+    /// manifest
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Provider {
+    Github,
+    Gitlab,
+}
+
+#[derive(Args)]
+pub struct ReposArgs {
+    /// Repository urls or manifest files
+    #[arg(required = true, value_name = "REPO|MANIFEST")]
+    pub repos: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct StatusArgs {
+    /// Repository urls or manifest files [default: every repository of the
+    /// state]
+    #[arg(value_name = "REPO|MANIFEST")]
+    pub repos: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct PruneArgs {
+    /// Bytes kept at most [default: cache.max_size]
+    #[arg(long, value_name = "BYTES", conflicts_with = "all")]
+    pub max_size: Option<u64>,
+
+    /// Remove every entry
+    #[arg(long)]
+    pub all: bool,
+}
+
+#[derive(Args)]
+pub struct SearchArgs {
+    /// Query, field filters like `lang:Rust` are supported
+    pub query: String,
+
+    /// Number of results
+    #[arg(long, env = "HLI_LIMIT", default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..))]
+    pub limit: u16,
+}
+
+fn existing_file(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(format!("file does not exist: {}", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The config of `hli --config <file> index <file> <args>`, every test
+    // reads its own settings so they do not race on the environment.
+    fn config(name: &str, toml: &str, args: &[&str]) -> Config {
+        let path = std::env::temp_dir().join(format!("hli-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, toml).unwrap();
+        let file = path.to_str().unwrap();
+        let mut argv = vec!["hli", "--config", file, "index", file];
+        argv.extend(args);
+        let config = Arg::try_parse_from(argv).unwrap().config().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn falls_back_to_the_defaults() {
+        let config = config("defaults", "", &[]);
+        assert_eq!(config.backend.config_set, "heline");
+        assert_eq!(config.backend.commit_within, 1000);
+    }
+
+    #[test]
+    fn reads_the_config_file_over_the_defaults() {
+        let toml = "[backend]\nconfig_set = \"file\"\ncommit_within = 10\n";
+        let config = config("file", toml, &[]);
+        assert_eq!(config.backend.config_set, "file");
+        assert_eq!(config.backend.commit_within, 10);
+    }
 
-        Ok(self)
+    #[test]
+    fn reads_the_environment_over_the_config_file() {
+        std::env::set_var("HLI_FOLDER", "env");
+        std::env::set_var("GITHUB_TOKEN", "env");
+        let toml = "[clone]\nfolder = \"file\"\n\n[providers]\ngithub_token = \"file\"\n";
+        let config = config("env", toml, &[]);
+        std::env::remove_var("HLI_FOLDER");
+        std::env::remove_var("GITHUB_TOKEN");
+        assert_eq!(config.clone.folder, PathBuf::from("env"));
+        assert_eq!(config.providers.github_token.as_deref(), Some("env"));
     }
 
-    fn print_help(&self) {
-        let help_text = vec![
-            "hli 0.1.0",
-            "Ahmad Rosid <alahmadrosid@gmail.com>",
-            "",
-            "Heline.dev indexer, turn source code to github like html syntax highlighted!",
-            "",
-            "Usage :",
-            "    hli data.json --folder some/folder",
-            "",
-            "Options :",
-            "    <INDEX_FILE>    Index file with json format",
-            "    --folder        Custom folder to source code",
-            "    -h --help       Print help text",
-            "    --delete-dir    Delete directory after indexing.",
-            "",
-        ];
-        println!("{}", help_text.join("\n"));
+    #[test]
+    fn reads_the_command_line_over_the_environment() {
+        std::env::set_var("HLI_CONCURRENCY", "3");
+        let toml = "[index]\nconcurrency = 2\n";
+        let config = config("cli", toml, &["--concurrency", "4"]);
+        std::env::remove_var("HLI_CONCURRENCY");
+        assert_eq!(config.index.concurrency, 4);
     }
 }
@@ -0,0 +1,467 @@
+use crate::solr::client::GitFile;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The document holding the content of a file blob.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlobEntry {
+    /// Git blob hash of the file.
+    pub blob: String,
+    /// Id of the document.
+    pub file: String,
+    /// Repository of the document, e.g. `owner/name`.
+    pub repo: String,
+}
+
+/// A blob whose document is gone, moved to one of the documents referencing
+/// it. The content has to be stored on `holder` and `references` written
+/// again to point to it.
+#[derive(Clone, Debug)]
+pub struct Handover {
+    /// The document holding the blob now, without its content.
+    pub holder: GitFile,
+    /// The other documents of the blob, `duplicate_of` set to `holder`.
+    pub references: Vec<GitFile>,
+}
+
+// A line of the journal.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Entry(BlobEntry),
+    Reference { reference: GitFile },
+}
+
+// What a repository claimed since `start_repo`.
+#[derive(Default)]
+struct Claims {
+    /// Blob to the file holding it.
+    held: HashMap<String, String>,
+    /// Documents stored as a reference.
+    referenced: HashSet<String>,
+}
+
+/// Blobs already indexed, so identical files of forks and mirrors are stored
+/// as a reference to the first repository holding them. Kept in a json lines
+/// journal like the repository state, along with the references so the
+/// content moves to one of them once the document holding it is gone.
+///
+/// The blobs claimed while a repository is indexed are tracked until
+/// `finish_repo`, which moves or drops the ones its files no longer hold.
+pub struct BlobStore {
+    path: PathBuf,
+    blobs: Mutex<HashMap<String, BlobEntry>>,
+    /// Documents stored as a reference, per blob.
+    references: Mutex<HashMap<String, Vec<GitFile>>>,
+    /// Claimed by each repository since `start_repo`.
+    claims: Mutex<HashMap<String, Claims>>,
+}
+
+impl BlobStore {
+    /// Replay the journal at `path`, created when missing.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut blobs = HashMap::new();
+        let mut references: HashMap<String, Vec<GitFile>> = HashMap::new();
+        let file = match File::open(path) {
+            Ok(file) => Some(file),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        for line in file
+            .into_iter()
+            .flat_map(|file| BufReader::new(file).lines())
+        {
+            // A torn last line is skipped.
+            match serde_json::from_str::<Record>(&line?) {
+                Ok(Record::Entry(entry)) => {
+                    blobs.entry(entry.blob.to_string()).or_insert(entry);
+                }
+                Ok(Record::Reference { reference }) => {
+                    remove_reference(&mut references, &reference.id);
+                    references
+                        .entry(reference.blob.to_string())
+                        .or_default()
+                        .push(reference);
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            blobs: Mutex::new(blobs),
+            references: Mutex::new(references),
+            claims: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Record `file` of `repo` as holding `blob`, unless another repository
+    /// holds it already. Returns the document of that other repository.
+    pub fn claim(&self, blob: &str, file: &str, repo: &str) -> io::Result<Option<BlobEntry>> {
+        let mut blobs = self.blobs.lock().unwrap();
+        match blobs.get(blob) {
+            Some(entry) if entry.repo != repo => return Ok(Some(entry.clone())),
+            // Copies within a repository are all indexed.
+            Some(entry) => {
+                self.track(repo, blob, file, entry.file == file);
+                return Ok(None);
+            }
+            None => {}
+        }
+        let entry = BlobEntry {
+            blob: blob.to_string(),
+            file: file.to_string(),
+            repo: repo.to_string(),
+        };
+        self.append(&Record::Entry(entry.clone()))?;
+        blobs.insert(blob.to_string(), entry);
+        self.track(repo, blob, file, true);
+        Ok(None)
+    }
+
+    /// Record `document`, stored without content, as a reference to the
+    /// document holding its blob.
+    pub fn refer(&self, document: &GitFile) -> io::Result<()> {
+        let blobs = self.blobs.lock().unwrap();
+        let mut references = self.references.lock().unwrap();
+        let mut reference = document.clone();
+        reference.content = Vec::new();
+        let replaced = remove_reference(&mut references, &reference.id);
+        let record = Record::Reference {
+            reference: reference.clone(),
+        };
+        references
+            .entry(reference.blob.to_string())
+            .or_default()
+            .push(reference);
+        if let Some(claims) = self.claims.lock().unwrap().get_mut(&document.repo) {
+            claims.referenced.insert(document.id.to_string());
+        }
+        match replaced {
+            true => self.compact(&blobs, &references),
+            false => self.append(&record),
+        }
+    }
+
+    /// Start tracking the blobs claimed by `repo`, before it is indexed.
+    pub fn start_repo(&self, repo: &str) {
+        let mut claims = self.claims.lock().unwrap();
+        claims.insert(repo.to_string(), Claims::default());
+    }
+
+    /// Once `repo` is indexed, point its blobs to the files holding them now
+    /// and forget its files no longer there or changed. The blobs it no
+    /// longer holds are handed over to a document referencing them.
+    pub fn finish_repo(&self, repo: &str) -> io::Result<Vec<Handover>> {
+        let mut blobs = self.blobs.lock().unwrap();
+        let mut references = self.references.lock().unwrap();
+        let Some(claimed) = self.claims.lock().unwrap().remove(repo) else {
+            return Ok(Vec::new());
+        };
+        let before = references.values().map(Vec::len).sum::<usize>();
+        for documents in references.values_mut() {
+            documents.retain(|document| {
+                document.repo != repo || claimed.referenced.contains(&document.id)
+            });
+        }
+        references.retain(|_, documents| !documents.is_empty());
+        let mut changed = before != references.values().map(Vec::len).sum::<usize>();
+
+        let mut dropped = Vec::new();
+        for (blob, entry) in blobs.iter_mut() {
+            if entry.repo != repo {
+                continue;
+            }
+            match claimed.held.get(blob) {
+                Some(file) if *file != entry.file => {
+                    entry.file = file.to_string();
+                    changed = true;
+                }
+                Some(_) => {}
+                None => dropped.push(blob.to_string()),
+            }
+        }
+        let handovers = dropped
+            .iter()
+            .filter_map(|blob| hand_over(&mut blobs, &mut references, blob))
+            .collect();
+        if changed || !dropped.is_empty() {
+            self.compact(&blobs, &references)?;
+        }
+        Ok(handovers)
+    }
+
+    /// Forget `file` as holding or referencing `blob`, e.g. because it failed
+    /// to store or changed. The blob is handed over to a document
+    /// referencing it when `file` held it.
+    pub fn release(&self, blob: &str, file: &str) -> io::Result<Option<Handover>> {
+        let mut blobs = self.blobs.lock().unwrap();
+        let mut references = self.references.lock().unwrap();
+        if blobs.get(blob).is_none_or(|entry| entry.file != file) {
+            if remove_reference(&mut references, file) {
+                self.compact(&blobs, &references)?;
+            }
+            return Ok(None);
+        }
+        if let Some(entry) = blobs.get(blob) {
+            if let Some(claims) = self.claims.lock().unwrap().get_mut(&entry.repo) {
+                claims.held.remove(blob);
+            }
+        }
+        let handover = hand_over(&mut blobs, &mut references, blob);
+        self.compact(&blobs, &references)?;
+        Ok(handover)
+    }
+
+    /// Forget every blob held or referenced by `repo` once its documents are
+    /// deleted, the blobs it held are handed over to a document referencing
+    /// them.
+    pub fn release_repo(&self, repo: &str) -> io::Result<Vec<Handover>> {
+        let mut blobs = self.blobs.lock().unwrap();
+        let mut references = self.references.lock().unwrap();
+        let before = references.values().map(Vec::len).sum::<usize>();
+        for documents in references.values_mut() {
+            documents.retain(|document| document.repo != repo);
+        }
+        references.retain(|_, documents| !documents.is_empty());
+        let unreferenced = before != references.values().map(Vec::len).sum::<usize>();
+
+        let held: Vec<String> = blobs
+            .values()
+            .filter(|entry| entry.repo == repo)
+            .map(|entry| entry.blob.to_string())
+            .collect();
+        let handovers = held
+            .iter()
+            .filter_map(|blob| hand_over(&mut blobs, &mut references, blob))
+            .collect();
+        if unreferenced || !held.is_empty() {
+            self.compact(&blobs, &references)?;
+        }
+        Ok(handovers)
+    }
+
+    // Record `file` as holding `blob` for `finish_repo`, the file of the
+    // entry is kept when it is claimed again.
+    fn track(&self, repo: &str, blob: &str, file: &str, preferred: bool) {
+        let mut claims = self.claims.lock().unwrap();
+        let Some(claimed) = claims.get_mut(repo) else {
+            return;
+        };
+        if preferred {
+            claimed.held.insert(blob.to_string(), file.to_string());
+        } else {
+            claimed
+                .held
+                .entry(blob.to_string())
+                .or_insert_with(|| file.to_string());
+        }
+    }
+
+    fn append(&self, record: &Record) -> io::Result<()> {
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(journal, "{}", serde_json::to_string(record)?)
+    }
+
+    fn compact(
+        &self,
+        blobs: &HashMap<String, BlobEntry>,
+        references: &HashMap<String, Vec<GitFile>>,
+    ) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        for entry in blobs.values() {
+            writeln!(
+                file,
+                "{}",
+                serde_json::to_string(&Record::Entry(entry.clone()))?
+            )?;
+        }
+        for reference in references.values().flatten() {
+            let record = Record::Reference {
+                reference: reference.clone(),
+            };
+            writeln!(file, "{}", serde_json::to_string(&record)?)?;
+        }
+        file.sync_all()?;
+        fs::rename(tmp, &self.path)
+    }
+}
+
+// Drop the entry of `blob` and make its first reference the holder, the
+// others point to it from now on.
+fn hand_over(
+    blobs: &mut HashMap<String, BlobEntry>,
+    references: &mut HashMap<String, Vec<GitFile>>,
+    blob: &str,
+) -> Option<Handover> {
+    blobs.remove(blob);
+    let mut documents = references.remove(blob)?.into_iter();
+    let mut holder = documents.next()?;
+    holder.duplicate_of = String::new();
+    blobs.insert(
+        blob.to_string(),
+        BlobEntry {
+            blob: blob.to_string(),
+            file: holder.id.to_string(),
+            repo: holder.repo.to_string(),
+        },
+    );
+    let references_left: Vec<GitFile> = documents
+        .map(|mut document| {
+            document.duplicate_of = holder.id.to_string();
+            document
+        })
+        .collect();
+    if !references_left.is_empty() {
+        references.insert(blob.to_string(), references_left.clone());
+    }
+    Some(Handover {
+        holder,
+        references: references_left,
+    })
+}
+
+// Whether a reference of the document `id` was there to remove.
+fn remove_reference(references: &mut HashMap<String, Vec<GitFile>>, id: &str) -> bool {
+    let mut removed = false;
+    references.retain(|_, documents| {
+        let before = documents.len();
+        documents.retain(|document| document.id != id);
+        removed |= documents.len() != before;
+        !documents.is_empty()
+    });
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> (BlobStore, PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("hli-blobs-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        (BlobStore::open(&path).unwrap(), path)
+    }
+
+    #[test]
+    fn claims_a_blob_for_its_first_repository() {
+        let (blobs, path) = store("first");
+        assert!(blobs.claim("b1", "up/hl/a.rs", "up/hl").unwrap().is_none());
+        // Another copy within the repository is indexed too.
+        assert!(blobs.claim("b1", "up/hl/b.rs", "up/hl").unwrap().is_none());
+
+        let original = blobs
+            .claim("b1", "fork/hl/a.rs", "fork/hl")
+            .unwrap()
+            .unwrap();
+        assert_eq!(original.file, "up/hl/a.rs");
+        assert_eq!(original.repo, "up/hl");
+        fs::remove_file(path).unwrap();
+    }
+
+    fn document(id: &str, repo: &str, duplicate_of: &str) -> GitFile {
+        GitFile {
+            id: id.to_string(),
+            file_id: format!("github.com/{}", id),
+            owner_id: "0000".to_string(),
+            path: "hl".to_string(),
+            repo: repo.to_string(),
+            branch: "main".to_string(),
+            lang: "Rust".to_string(),
+            content: Vec::new(),
+            blob: "b1".to_string(),
+            duplicate_of: duplicate_of.to_string(),
+        }
+    }
+
+    #[test]
+    fn hands_a_deleted_original_to_its_references() {
+        let (blobs, path) = store("handover");
+        blobs.claim("b1", "up/hl/hl/a.rs", "up/hl").unwrap();
+        for repo in ["fork/hl", "mirror/hl"] {
+            let id = format!("{}/hl/a.rs", repo);
+            assert!(blobs.claim("b1", &id, repo).unwrap().is_some());
+            blobs.refer(&document(&id, repo, "up/hl/hl/a.rs")).unwrap();
+        }
+        drop(blobs);
+
+        // The references are kept across reopen.
+        let blobs = BlobStore::open(&path).unwrap();
+        let handovers = blobs.release_repo("up/hl").unwrap();
+        assert_eq!(handovers.len(), 1);
+        let handover = &handovers[0];
+        assert_eq!(handover.holder.id, "fork/hl/hl/a.rs");
+        assert_eq!(handover.holder.duplicate_of, "");
+        assert_eq!(handover.references.len(), 1);
+        assert_eq!(handover.references[0].id, "mirror/hl/hl/a.rs");
+        assert_eq!(handover.references[0].duplicate_of, "fork/hl/hl/a.rs");
+
+        let original = blobs
+            .claim("b1", "other/hl/hl/a.rs", "other/hl")
+            .unwrap()
+            .unwrap();
+        assert_eq!(original.file, "fork/hl/hl/a.rs");
+
+        // Once the new holder changes too the last reference holds it.
+        let handover = blobs.release("b1", "fork/hl/hl/a.rs").unwrap().unwrap();
+        assert_eq!(handover.holder.id, "mirror/hl/hl/a.rs");
+        assert!(handover.references.is_empty());
+        // A released reference hands nothing over.
+        assert!(blobs.release("b1", "other/hl/hl/a.rs").unwrap().is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_claims_across_reopen() {
+        let (blobs, path) = store("reopen");
+        blobs.claim("b1", "up/hl/a.rs", "up/hl").unwrap();
+        blobs.claim("b2", "up/hl/b.rs", "up/hl").unwrap();
+        blobs.release("b2", "up/hl/b.rs").unwrap();
+        drop(blobs);
+
+        let blobs = BlobStore::open(&path).unwrap();
+        let original = blobs
+            .claim("b1", "fork/hl/a.rs", "fork/hl")
+            .unwrap()
+            .unwrap();
+        assert_eq!(original.file, "up/hl/a.rs");
+        assert!(blobs
+            .claim("b2", "fork/hl/b.rs", "fork/hl")
+            .unwrap()
+            .is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn forgets_the_blobs_a_repository_no_longer_holds() {
+        let (blobs, path) = store("finish");
+        blobs.claim("b1", "up/hl/a.rs", "up/hl").unwrap();
+        blobs.claim("b2", "up/hl/b.rs", "up/hl").unwrap();
+
+        // `a.rs` was moved to `c.rs` and `b.rs` deleted.
+        blobs.start_repo("up/hl");
+        blobs.claim("b1", "up/hl/c.rs", "up/hl").unwrap();
+        assert!(blobs.finish_repo("up/hl").unwrap().is_empty());
+        drop(blobs);
+
+        let blobs = BlobStore::open(&path).unwrap();
+        let original = blobs
+            .claim("b1", "fork/hl/a.rs", "fork/hl")
+            .unwrap()
+            .unwrap();
+        assert_eq!(original.file, "up/hl/c.rs");
+        assert!(blobs
+            .claim("b2", "fork/hl/b.rs", "fork/hl")
+            .unwrap()
+            .is_none());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,322 @@
+use crate::chunk::Chunk;
+use crate::config::{CacheConfig, ChunkConfig};
+use crate::parser;
+use sha1::{Digest, Sha1};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Chunks rendered before, keyed by the git blob hash of the file, its
+/// language and the renderer version, so re-indexed repositories and forks
+/// are not highlighted again. Every entry is a json file, the least recently
+/// used ones are evicted once the cache outgrows `cache.max_size`.
+pub struct RenderCache {
+    dir: PathBuf,
+    max_size: u64,
+    /// Size of the entries, other processes sharing the directory are only
+    /// accounted for when the cache is opened.
+    size: Mutex<u64>,
+    writes: AtomicU64,
+}
+
+/// Entries and size of a cache directory.
+#[derive(Default, Debug)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    /// Last use of the least recently used entry.
+    pub oldest: Option<SystemTime>,
+    /// Last use of the most recently used entry.
+    pub newest: Option<SystemTime>,
+}
+
+/// Entries removed by a prune.
+#[derive(Default, Debug)]
+pub struct Pruned {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+struct Entry {
+    path: PathBuf,
+    bytes: u64,
+    used: SystemTime,
+}
+
+impl RenderCache {
+    /// Open the cache at `cache.dir`, created when missing, and evict the
+    /// entries beyond `cache.max_size`.
+    pub fn open(config: &CacheConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let mut size: u64 = entries(&config.dir)?.iter().map(|entry| entry.bytes).sum();
+        if size > config.max_size {
+            size -= prune(&config.dir, config.max_size)?.bytes;
+        }
+        Ok(Self {
+            dir: config.dir.clone(),
+            max_size: config.max_size,
+            size: Mutex::new(size),
+            writes: AtomicU64::new(0),
+        })
+    }
+
+    /// The chunks of `blob` rendered as `lang` with the same chunk options.
+    pub fn get(&self, blob: &str, lang: &str, options: &ChunkConfig) -> Option<Vec<Chunk>> {
+        let path = self.path(blob, lang, options);
+        let text = fs::read(&path).ok()?;
+        match serde_json::from_slice(&text) {
+            Ok(chunks) => {
+                // The modification time orders the entries for eviction.
+                if let Ok(file) = File::options().write(true).open(&path) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+                Some(chunks)
+            }
+            Err(e) => {
+                tracing::debug!("Ignoring cache entry '{}': {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Store the chunks of `blob`, evicting the least recently used entries
+    /// when the cache is full. Chunks larger than most of the cache are not
+    /// stored.
+    pub fn put(
+        &self,
+        blob: &str,
+        lang: &str,
+        options: &ChunkConfig,
+        chunks: &[Chunk],
+    ) -> io::Result<()> {
+        let text = serde_json::to_vec(chunks)?;
+        // Pruned down to 90% so the next entries do not evict again right
+        // away, an entry larger than that would only evict itself.
+        let limit = self.max_size / 10 * 9;
+        if text.len() as u64 > limit {
+            return Ok(());
+        }
+        let path = self.path(blob, lang, options);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let previous = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        // Written aside then renamed, a reader never sees half an entry.
+        let write = self.writes.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("{}-{}.tmp", std::process::id(), write));
+        File::create(&tmp)?.write_all(&text)?;
+        fs::rename(&tmp, &path)?;
+
+        let mut size = self.size.lock().unwrap();
+        *size = (*size + text.len() as u64).saturating_sub(previous);
+        if *size > self.max_size {
+            let pruned = prune(&self.dir, limit)?;
+            tracing::debug!(
+                "Evicted {} cache entries, {} bytes",
+                pruned.entries,
+                pruned.bytes
+            );
+            *size = size.saturating_sub(pruned.bytes);
+        }
+        Ok(())
+    }
+
+    fn path(&self, blob: &str, lang: &str, options: &ChunkConfig) -> PathBuf {
+        entry_path(&self.dir, blob, lang, parser::RENDER_VERSION, options)
+    }
+}
+
+// `ab/abcdef…` where the name hashes the parts of the key.
+fn entry_path(dir: &Path, blob: &str, lang: &str, version: u32, options: &ChunkConfig) -> PathBuf {
+    let key = format!(
+        "{}\0{}\0{}\0{}\0{}",
+        blob, lang, version, options.min_lines, options.max_chars
+    );
+    let name = hex::encode(Sha1::digest(key.as_bytes()));
+    dir.join(&name[..2]).join(name).with_extension("json")
+}
+
+/// Count the entries of the cache at `dir`.
+pub fn stats(dir: &Path) -> io::Result<CacheStats> {
+    let entries = entries(dir)?;
+    Ok(CacheStats {
+        entries: entries.len(),
+        bytes: entries.iter().map(|entry| entry.bytes).sum(),
+        oldest: entries.iter().map(|entry| entry.used).min(),
+        newest: entries.iter().map(|entry| entry.used).max(),
+    })
+}
+
+/// Remove the least recently used entries of the cache at `dir` until it
+/// holds at most `max_size` bytes.
+pub fn prune(dir: &Path, max_size: u64) -> io::Result<Pruned> {
+    let mut entries = entries(dir)?;
+    let mut size: u64 = entries.iter().map(|entry| entry.bytes).sum();
+    entries.sort_by_key(|entry| entry.used);
+    let mut pruned = Pruned::default();
+    for entry in entries {
+        if size <= max_size {
+            break;
+        }
+        match fs::remove_file(&entry.path) {
+            Ok(()) => {}
+            // Evicted by another process meanwhile.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        size -= entry.bytes;
+        pruned.entries += 1;
+        pruned.bytes += entry.bytes;
+    }
+    Ok(pruned)
+}
+
+fn entries(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let shards = match fs::read_dir(dir) {
+        Ok(shards) => shards,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+        Err(e) => return Err(e),
+    };
+    for shard in shards {
+        let shard = shard?;
+        if !shard.file_type()?.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(shard.path())? {
+            let path = file?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            // Evicted by another process meanwhile.
+            let Ok(meta) = fs::metadata(&path) else {
+                continue;
+            };
+            entries.push(Entry {
+                bytes: meta.len(),
+                used: meta.modified()?,
+                path,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::Lines;
+    use std::time::Duration;
+
+    fn config(name: &str, max_size: u64) -> CacheConfig {
+        let dir = format!("hli-cache-{}-{}", name, std::process::id());
+        let dir = std::env::temp_dir().join(dir);
+        let _ = fs::remove_dir_all(&dir);
+        CacheConfig {
+            enabled: true,
+            dir,
+            max_size,
+        }
+    }
+
+    fn chunks(html: &str) -> Vec<Chunk> {
+        vec![Chunk {
+            html: html.to_string(),
+            lines: Lines { start: 1, end: 3 },
+        }]
+    }
+
+    // Marks the entry of `blob` as last used `ago` seconds back.
+    fn used(cache: &RenderCache, blob: &str, ago: u64) {
+        let path = cache.path(blob, "rust", &ChunkConfig::default());
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(ago))
+            .unwrap();
+    }
+
+    #[test]
+    fn keys_entries_by_the_render_options() {
+        let config = config("key", 1 << 20);
+        let cache = RenderCache::open(&config).unwrap();
+        let options = ChunkConfig::default();
+        cache.put("1b4c2e", "rust", &options, &chunks("a")).unwrap();
+        let hit = cache.get("1b4c2e", "rust", &options).unwrap();
+        assert_eq!(hit[0].html, "a");
+
+        assert!(cache.get("1b4c2e", "toml", &options).is_none());
+        let min_lines = ChunkConfig {
+            min_lines: options.min_lines + 1,
+            ..options.clone()
+        };
+        assert!(cache.get("1b4c2e", "rust", &min_lines).is_none());
+        let max_chars = ChunkConfig {
+            max_chars: options.max_chars + 1,
+            ..options.clone()
+        };
+        assert!(cache.get("1b4c2e", "rust", &max_chars).is_none());
+
+        // Entries of an older renderer are not read.
+        let old = parser::RENDER_VERSION - 1;
+        let path = entry_path(&config.dir, "d0a1f3", "rust", old, &options);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, serde_json::to_vec(&chunks("old")).unwrap()).unwrap();
+        assert!(cache.get("d0a1f3", "rust", &options).is_none());
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entries() {
+        let entry = serde_json::to_vec(&chunks(&"x".repeat(100))).unwrap().len() as u64;
+        let config = config("evict", entry * 3);
+        let cache = RenderCache::open(&config).unwrap();
+        let options = ChunkConfig::default();
+        let html = "x".repeat(100);
+        for (blob, ago) in [("a", 30), ("b", 20), ("c", 10)] {
+            cache.put(blob, "rust", &options, &chunks(&html)).unwrap();
+            used(&cache, blob, ago);
+        }
+        // Reading `a` makes `b` the least recently used.
+        assert!(cache.get("a", "rust", &options).is_some());
+        cache.put("d", "rust", &options, &chunks(&html)).unwrap();
+
+        assert!(cache.get("b", "rust", &options).is_none());
+        for blob in ["a", "d"] {
+            assert!(cache.get(blob, "rust", &options).is_some(), "{}", blob);
+        }
+        assert!(stats(&config.dir).unwrap().bytes <= config.max_size / 10 * 9);
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_within_the_size_limit() {
+        let config = config("size", 1000);
+        let cache = RenderCache::open(&config).unwrap();
+        let options = ChunkConfig::default();
+        // Larger than 90% of the cache, it would only evict itself.
+        cache
+            .put("large", "rust", &options, &chunks(&"x".repeat(900)))
+            .unwrap();
+        assert!(cache.get("large", "rust", &options).is_none());
+
+        for (blob, ago) in [("a", 20), ("b", 10)] {
+            cache
+                .put(blob, "rust", &options, &chunks(&"x".repeat(300)))
+                .unwrap();
+            used(&cache, blob, ago);
+        }
+        drop(cache);
+        // A cache opened with a smaller limit evicts down to it.
+        let smaller = CacheConfig {
+            max_size: 500,
+            ..config.clone()
+        };
+        let cache = RenderCache::open(&smaller).unwrap();
+        assert!(cache.get("a", "rust", &options).is_none());
+        assert!(cache.get("b", "rust", &options).is_some());
+        assert_eq!(stats(&config.dir).unwrap().entries, 1);
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+}
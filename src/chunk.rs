@@ -0,0 +1,60 @@
+use crate::config::ChunkConfig;
+use crate::sink::Lines;
+use select::document::Document;
+use select::predicate::{Class, Name};
+use serde::{Deserialize, Serialize};
+
+/// Rows of the highlight table stored as one piece of a document.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Chunk {
+    pub html: String,
+    pub lines: Lines,
+}
+
+/// Split the html of `parser::render_html` into chunks of at least
+/// `min_lines` rows, a chunk keeps growing while its html is shorter than
+/// `max_chars`.
+pub fn split(html: &str, options: &ChunkConfig) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let document = Document::from(html);
+    let table = document.find(Class("highlight-table"));
+    if let Some(el) = table.last() {
+        let mut line = 0;
+        let mut index = 0;
+        let min_lines = options.min_lines.max(1);
+        let max_chars = options.max_chars;
+        let mut max_index = min_lines;
+        let mut child: String = String::new();
+        for td in el.find(Name("tr")) {
+            line += 1;
+            index += 1;
+            child.push_str(&td.html());
+            child.push('\n');
+            if index == max_index && child.len() < max_chars {
+                max_index += 1;
+            }
+            if index >= max_index {
+                let lines = Lines {
+                    start: line - index + 1,
+                    end: line,
+                };
+                index = 0;
+                max_index = min_lines;
+                chunks.push(Chunk {
+                    html: std::mem::take(&mut child),
+                    lines,
+                });
+            }
+        }
+
+        // Whatever is left is shorter than `min_lines`, keep it as the last chunk.
+        if index != 0 {
+            let lines = Lines {
+                start: line - index + 1,
+                end: line,
+            };
+            chunks.push(Chunk { html: child, lines });
+        }
+    }
+    chunks
+}
@@ -0,0 +1,53 @@
+use crate::arg::{CacheAction, PruneArgs};
+use chrono::{DateTime, SecondsFormat, Utc};
+use heline_indexer::cache;
+use heline_indexer::config::Config;
+use std::time::SystemTime;
+
+pub fn run(config: &Config, action: &CacheAction) -> Result<(), String> {
+    match action {
+        CacheAction::Stats => stats(config),
+        CacheAction::Prune(prune) => self::prune(config, prune),
+    }
+}
+
+fn stats(config: &Config) -> Result<(), String> {
+    let dir = &config.cache.dir;
+    let stats = cache::stats(dir)
+        .map_err(|e| format!("Failed to read the cache '{}': {}", dir.display(), e))?;
+    println!("Cache      {}", dir.display());
+    println!("Entries    {}", stats.entries);
+    println!(
+        "Size       {} of {} bytes ({:.1}%)",
+        stats.bytes,
+        config.cache.max_size,
+        stats.bytes as f64 * 100.0 / config.cache.max_size.max(1) as f64
+    );
+    if let (Some(oldest), Some(newest)) = (stats.oldest, stats.newest) {
+        println!("Oldest use {}", timestamp(oldest));
+        println!("Newest use {}", timestamp(newest));
+    }
+    Ok(())
+}
+
+fn prune(config: &Config, arg: &PruneArgs) -> Result<(), String> {
+    let dir = &config.cache.dir;
+    let max_size = match (arg.all, arg.max_size) {
+        (true, _) => 0,
+        (false, Some(max_size)) => max_size,
+        (false, None) => config.cache.max_size,
+    };
+    let pruned = cache::prune(dir, max_size)
+        .map_err(|e| format!("Failed to prune the cache '{}': {}", dir.display(), e))?;
+    println!(
+        "Removed {} entries, {} bytes, from '{}'",
+        pruned.entries,
+        pruned.bytes,
+        dir.display()
+    );
+    Ok(())
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
@@ -0,0 +1,11 @@
+use heline_indexer::config::Config;
+
+/// Print the effective configuration as toml, credentials are masked.
+pub fn show(config: &Config) -> Result<(), String> {
+    match &config.source {
+        Some(path) => println!("# Read from {}", path.display()),
+        None => println!("# No config file found"),
+    }
+    print!("{}", config.to_toml()?);
+    Ok(())
+}
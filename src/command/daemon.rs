@@ -0,0 +1,289 @@
+use crate::arg::DaemonArgs;
+use crate::command::index::{self, Run};
+use crate::server;
+use futures::stream::{self, StreamExt};
+use heline_indexer::blobs::BlobStore;
+use heline_indexer::cache::RenderCache;
+use heline_indexer::config::Config;
+use heline_indexer::git::{self, local::LocalRepo, RepoRef};
+use heline_indexer::manifest;
+use heline_indexer::progress::Progress;
+use heline_indexer::schedule::Schedule;
+use heline_indexer::sink::Sink;
+use heline_indexer::state::{self, RepoStatus, StateStore};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tracing::Instrument;
+
+/// How often the manifest is checked for changes while idle.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Check the repositories of the manifest on their schedule and index the
+/// files changed in the ones whose default branch moved, until SIGINT or
+/// SIGTERM.
+pub async fn run(config: &Config, arg: &DaemonArgs) -> Result<(), String> {
+    let backend = &config.backend;
+    let sink = Sink::new(&backend.options(&backend.location(), &backend.core))?;
+    let state_file = &config.index.state_file;
+    let state = StateStore::open(state_file)
+        .map_err(|e| format!("Failed to open state '{}': {}", state_file.display(), e))?;
+    let blobs = index::open_blobs(config)?;
+    let cache = index::open_cache(config)?;
+    let signals = Signals::listen()?;
+    if let Some(listen) = &config.daemon.listen {
+        server::serve_metrics(listen).await?;
+    }
+
+    let mut manifest = Manifest::new(&arg.manifest);
+    manifest.load(config).await?;
+    while !signals.stopping() {
+        if signals.reload.swap(false, Ordering::Relaxed) || manifest.changed() {
+            if let Err(e) = manifest.load(config).await {
+                tracing::error!("Keeping the previous manifest: {}", e);
+            }
+        }
+
+        let now = SystemTime::now();
+        let due = manifest.due(now);
+        if due.is_empty() {
+            let wait = manifest.next_run().duration_since(now).unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep(wait.min(POLL_INTERVAL)) => {}
+                _ = signals.notify.notified() => {}
+            }
+            continue;
+        }
+
+        let stale = stale_repos(&state, due, config.index.concurrency).await;
+        if !stale.is_empty() {
+            let (blobs, cache) = (blobs.as_ref(), cache.as_ref());
+            index_stale(config, &sink, &state, blobs, cache, &signals, stale).await;
+        }
+    }
+
+    tracing::info!("Stopped");
+    Ok(())
+}
+
+// The repositories whose remote HEAD, or the ref they link to, is not the
+// indexed commit, or whose last run failed and is due for a retry. Local
+// repositories are compared by their HEAD, changes to a working tree that
+// are not committed go unnoticed.
+async fn stale_repos(state: &StateStore, urls: Vec<String>, concurrency: usize) -> Vec<String> {
+    stream::iter(urls)
+        .map(|url| async move {
+            match remote_head(&url).await {
+                Some(head) if is_stale(state, &url, &head) => Some(url),
+                Some(_) => {
+                    tracing::debug!("'{}' is up to date", url);
+                    None
+                }
+                None => {
+                    tracing::warn!("Failed to read the HEAD of '{}'", url);
+                    None
+                }
+            }
+        })
+        .buffer_unordered(concurrency)
+        .filter_map(|url| async { url })
+        .collect()
+        .await
+}
+
+// The commit `url` would be indexed at, an entry linking into the tree
+// follows its own ref.
+async fn remote_head(url: &str) -> Option<String> {
+    match LocalRepo::parse(url) {
+        Some(local) => local.ok()?.head().await,
+        None => {
+            let repo = RepoRef::parse(url).ok()?;
+            let git_ref = repo.git_ref.as_deref().unwrap_or("HEAD");
+            git::ls_remote(&repo.ssh_url(), git_ref, repo.tree_path.as_deref()).await
+        }
+    }
+}
+
+fn is_stale(state: &StateStore, url: &str, head: &str) -> bool {
+    match state.get(url) {
+        None => true,
+        // A commit of the manifest may be abbreviated.
+        Some(repo) if !repo.commit.as_deref().is_some_and(|c| c.starts_with(head)) => true,
+        Some(repo) => repo.status != RepoStatus::Indexed && repo.retry_at() <= state::now(),
+    }
+}
+
+// Index the files changed since the indexed commit of each repository,
+// several at once: the sink keeps its buffers per repository.
+async fn index_stale(
+    config: &Config,
+    sink: &Sink,
+    state: &StateStore,
+    blobs: Option<&BlobStore>,
+    cache: Option<&RenderCache>,
+    signals: &Signals,
+    urls: Vec<String>,
+) {
+    tracing::info!("Indexing {} repositories", urls.len());
+    let progress = Progress::new(urls.len());
+    let run = Run {
+        config,
+        sink,
+        progress: &progress,
+        state: Some(state),
+        blobs,
+        cache,
+        reindex: false,
+        incremental: true,
+        fetch: true,
+    };
+    let summaries: Vec<_> = stream::iter(urls)
+        .map(|git_url| async {
+            // Repositories not started yet wait for the next run.
+            if signals.stopping() {
+                return None;
+            }
+            let span = tracing::info_span!("repo", url = %git_url);
+            Some(run.index_repo(git_url).instrument(span).await)
+        })
+        .buffered(config.index.concurrency)
+        .filter_map(|summary| async { summary })
+        .collect()
+        .await;
+    progress.finish();
+
+    let failed = summaries
+        .iter()
+        .filter(|(_, summary)| summary.failure.is_some() || summary.failed > 0)
+        .count();
+    tracing::info!(
+        "Indexed {} repositories, {} failed",
+        summaries.len(),
+        failed
+    );
+}
+
+struct Signals {
+    stopping: AtomicBool,
+    reload: AtomicBool,
+    notify: Notify,
+}
+
+impl Signals {
+    // SIGINT and SIGTERM let the repositories being indexed finish, a second
+    // one exits right away. SIGHUP reloads the manifest.
+    fn listen() -> Result<Arc<Self>, String> {
+        let signals = Arc::new(Signals {
+            stopping: AtomicBool::new(false),
+            reload: AtomicBool::new(false),
+            notify: Notify::new(),
+        });
+        let error = |e: std::io::Error| format!("Failed to listen for signals: {}", e);
+        let mut interrupt = signal(SignalKind::interrupt()).map_err(error)?;
+        let mut terminate = signal(SignalKind::terminate()).map_err(error)?;
+        let mut hangup = signal(SignalKind::hangup()).map_err(error)?;
+
+        let listener = signals.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = interrupt.recv() => listener.stop(),
+                    _ = terminate.recv() => listener.stop(),
+                    _ = hangup.recv() => {
+                        tracing::info!("Reloading the manifest");
+                        listener.reload.store(true, Ordering::Relaxed);
+                        listener.notify.notify_one();
+                    }
+                }
+            }
+        });
+        Ok(signals)
+    }
+
+    fn stop(&self) {
+        if self.stopping.swap(true, Ordering::Relaxed) {
+            tracing::warn!("Stopping now, the repositories being indexed are left as running");
+            std::process::exit(130);
+        }
+        tracing::info!("Stopping once the repositories being indexed are done");
+        self.notify.notify_one();
+    }
+
+    fn stopping(&self) -> bool {
+        self.stopping.load(Ordering::Relaxed)
+    }
+}
+
+struct Manifest {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    repos: BTreeMap<String, Entry>,
+}
+
+struct Entry {
+    schedule: Schedule,
+    next: SystemTime,
+}
+
+impl Manifest {
+    fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            modified: None,
+            repos: BTreeMap::new(),
+        }
+    }
+
+    // Repositories new to the manifest are checked right away, the others
+    // keep their next run.
+    async fn load(&mut self, config: &Config) -> Result<(), String> {
+        self.modified = modified(&self.path);
+        let now = SystemTime::now();
+        let mut repos = BTreeMap::new();
+        for url in manifest::load(&self.path, &config.providers).await? {
+            let schedule = config.daemon.schedule(&url)?;
+            let next = self.repos.get(&url).map(|entry| entry.next).unwrap_or(now);
+            repos.insert(url, Entry { schedule, next });
+        }
+        tracing::info!(
+            "Watching {} repositories of '{}'",
+            repos.len(),
+            self.path.display()
+        );
+        self.repos = repos;
+        Ok(())
+    }
+
+    fn changed(&self) -> bool {
+        modified(&self.path) != self.modified
+    }
+
+    /// The repositories due at `now`, scheduled for their next run.
+    fn due(&mut self, now: SystemTime) -> Vec<String> {
+        let mut due = Vec::new();
+        for (url, entry) in self.repos.iter_mut() {
+            if entry.next <= now {
+                entry.next = entry.schedule.next(now);
+                due.push(url.to_string());
+            }
+        }
+        due
+    }
+
+    fn next_run(&self) -> SystemTime {
+        self.repos
+            .values()
+            .map(|entry| entry.next)
+            .min()
+            .unwrap_or_else(|| SystemTime::now() + POLL_INTERVAL)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
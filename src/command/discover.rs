@@ -0,0 +1,61 @@
+use crate::arg::{DiscoverArgs, Provider};
+use heline_indexer::config::Config;
+use heline_indexer::git::{github, gitlab};
+use heline_indexer::manifest;
+use std::collections::HashSet;
+
+/// Search a provider for repositories and add the ones not listed yet to the
+/// manifest.
+pub async fn run(config: &Config, arg: &DiscoverArgs) -> Result<(), String> {
+    let limit = arg.limit as usize;
+    let providers = &config.providers;
+    let (provider, found) = match arg.provider {
+        Provider::Github => (
+            "github",
+            github::search_repos(&arg.query, limit, providers).await?,
+        ),
+        Provider::Gitlab => (
+            "gitlab",
+            gitlab::search_repos(&arg.query, limit, providers).await?,
+        ),
+    };
+
+    // Owner entries of the manifest count as listed too.
+    let listed: HashSet<String> = match arg.manifest.is_file() {
+        true => manifest::load(&arg.manifest, providers)
+            .await?
+            .iter()
+            .map(|url| manifest::repo_key(url))
+            .collect(),
+        false => HashSet::new(),
+    };
+    let total = found.len();
+    let repos: Vec<(String, String)> = found
+        .into_iter()
+        .filter(|repo| !listed.contains(&manifest::repo_key(&repo.url)))
+        .map(|repo| {
+            let reason = format!("{} search '{}', {} stars", provider, arg.query, repo.stars);
+            (repo.url, reason)
+        })
+        .collect();
+
+    if arg.dry_run {
+        for (url, reason) in &repos {
+            println!("{}  ({})", url, reason);
+        }
+        println!("Would add {} of {} repositories found", repos.len(), total);
+        return Ok(());
+    }
+
+    let added = manifest::merge(&arg.manifest, repos)?;
+    for url in &added {
+        println!("{}", url);
+    }
+    println!(
+        "Added {} of {} repositories found to {}",
+        added.len(),
+        total,
+        arg.manifest.display()
+    );
+    Ok(())
+}
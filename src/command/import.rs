@@ -0,0 +1,198 @@
+use heline_indexer::config::Config;
+use heline_indexer::export::{self, Record};
+use heline_indexer::solr;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+/// Records sent to Solr in one request.
+const BATCH_SIZE: usize = 100;
+
+/// Replay ndjson exports into Solr.
+pub async fn run(config: &Config, inputs: &[PathBuf]) -> Result<(), String> {
+    let backend = &config.backend;
+    let base_url = backend.location();
+    let core = &backend.core;
+    let files = export::reader::list_files(inputs).map_err(|e| e.to_string())?;
+
+    let mut failed = 0;
+    for file in files {
+        let records = match export::reader::read_records(&file) {
+            Ok(records) => records,
+            Err(e) => {
+                tracing::error!("Failed to read {}: {}", file.display(), e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        // An export holds every document of its repositories, the documents
+        // indexed before are replaced rather than kept next to them.
+        let repos: BTreeSet<&str> = records
+            .iter()
+            .filter_map(|record| match record {
+                Record::Insert(data) => Some(&data.repo[..]),
+                _ => None,
+            })
+            .collect();
+        let mut cleared = Ok(());
+        for repo in repos {
+            let query = solr::collections::repo_query(repo);
+            cleared = solr::collections::delete_documents(&base_url, core, &query).await;
+            if cleared.is_err() {
+                break;
+            }
+        }
+        if let Err(e) = cleared {
+            tracing::error!("{}: {}", file.display(), e);
+            failed += records.len();
+            continue;
+        }
+
+        let mut total = 0;
+        for run in records.chunk_by(same_op) {
+            for batch in run.chunks(BATCH_SIZE) {
+                match send(batch, config).await {
+                    Ok(()) => total += batch.len(),
+                    Err(e) => {
+                        tracing::error!("{}: {}", file.display(), e);
+                        failed += batch.len();
+                    }
+                }
+            }
+        }
+        println!(
+            "Imported {} of {} records from {}",
+            total,
+            records.len(),
+            file.display()
+        );
+    }
+
+    if failed > 0 {
+        return Err(format!("{} records failed to import", failed));
+    }
+    Ok(())
+}
+
+fn same_op(a: &Record, b: &Record) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+// Records of the same operation in one request.
+async fn send(batch: &[Record], config: &Config) -> Result<(), String> {
+    let backend = &config.backend;
+    let base_url = backend.location();
+    let core = &backend.core;
+    match &batch[0] {
+        Record::Insert(_) => {
+            let docs: Vec<_> = batch
+                .iter()
+                .filter_map(|record| match record {
+                    Record::Insert(data) => Some(data.clone()),
+                    _ => None,
+                })
+                .collect();
+            solr::client::insert_all(&docs, &base_url, core, backend.commit_within)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+        Record::Update(_) => {
+            let updates: Vec<_> = batch
+                .iter()
+                .filter_map(|record| match record {
+                    Record::Update(update) => Some(update.clone()),
+                    _ => None,
+                })
+                .collect();
+            solr::client::append_all(&updates, &base_url, core)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+        Record::Delete(_) => {
+            let ids: Vec<_> = batch
+                .iter()
+                .filter_map(|record| match record {
+                    Record::Delete(deleted) => Some(deleted.id.clone()),
+                    _ => None,
+                })
+                .collect();
+            let query = solr::collections::ids_query(&ids);
+            solr::collections::delete_documents(&base_url, core, &query).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use heline_indexer::export::writer::ExportWriter;
+    use heline_indexer::solr::client::GitFile;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    async fn update(
+        State(requests): State<Arc<Mutex<Vec<Value>>>>,
+        Json(body): Json<Value>,
+    ) -> Json<Value> {
+        requests.lock().unwrap().push(body);
+        Json(json!({ "responseHeader": { "status": 0 } }))
+    }
+
+    fn file(path: &str) -> GitFile {
+        GitFile {
+            id: format!("heline/heline.dev/{}", path),
+            file_id: path.to_string(),
+            owner_id: "heline".to_string(),
+            path: path.to_string(),
+            repo: "heline/heline.dev".to_string(),
+            branch: "main".to_string(),
+            lang: "rust".to_string(),
+            content: vec!["fn main() {}".to_string()],
+            blob: String::new(),
+            duplicate_of: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn replaces_the_documents_of_the_repository_in_batches() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route("/solr/heline/update", post(update))
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let dir = std::env::temp_dir().join(format!("hli-import-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let writer = ExportWriter::new(dir.clone(), false).unwrap();
+        writer.insert(&file("src/main.rs")).unwrap();
+        writer.insert(&file("src/lib.rs")).unwrap();
+        writer.update(&file("src/main.rs")).unwrap();
+        writer
+            .delete_files("heline/heline.dev", &[file("README.md").id])
+            .unwrap();
+        writer.flush("heline/heline.dev").unwrap();
+
+        let mut config = Config::default();
+        config.backend.url = Some(format!("http://{}", addr));
+        run(&config, std::slice::from_ref(&dir)).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let requests = requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 4, "{:?}", requests);
+        assert_eq!(requests[0]["delete"]["query"], "repo:\"heline/heline.dev\"");
+        assert_eq!(requests[1].as_array().unwrap().len(), 2);
+        assert_eq!(requests[1][1]["path"], "src/lib.rs");
+        assert_eq!(requests[2][0]["content"]["add"][0], "fn main() {}");
+        assert_eq!(
+            requests[3]["delete"]["query"],
+            "id:(\"heline/heline.dev/README.md\")"
+        );
+    }
+}
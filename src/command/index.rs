@@ -0,0 +1,384 @@
+use crate::arg::IndexArgs;
+use futures::stream::{self, StreamExt};
+use heline_indexer::blobs::BlobStore;
+use heline_indexer::cache::RenderCache;
+use heline_indexer::config::Config;
+use heline_indexer::git;
+use heline_indexer::indexer::{Indexer, Summary};
+use heline_indexer::manifest;
+use heline_indexer::progress::Progress;
+use heline_indexer::report::RunReport;
+use heline_indexer::sink::Sink;
+use heline_indexer::solr::{collections, schema};
+use heline_indexer::state::{self, RepoStatus, StateStore};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
+
+/// Index every repository of the manifest, `reindex` deletes the documents
+/// of a repository before indexing it again. Fails when any repository did
+/// not index completely.
+pub async fn run(config: &Config, arg: &IndexArgs, reindex: bool) -> Result<(), String> {
+    let started = SystemTime::now();
+    let backend = &config.backend;
+    let base_url = backend.location();
+    let state_file = &config.index.state_file;
+    let state = StateStore::open(state_file)
+        .map_err(|e| format!("Failed to open state '{}': {}", state_file.display(), e))?;
+    let mut blobs = open_blobs(config)?;
+    // Opening the cache evicts entries, a dry run does not touch it.
+    let cache = if arg.dry_run {
+        None
+    } else {
+        open_cache(config)?
+    };
+
+    let mut value = manifest::load(&arg.manifest, &config.providers).await?;
+    if arg.resume {
+        value.retain(|git_url| is_pending(&state, git_url));
+    }
+
+    // Created last so a failure above does not leave it behind.
+    let collection = match &arg.swap_alias {
+        Some(alias) => create_staging_collection(&base_url, alias, &backend.config_set).await?,
+        None => backend.core.clone(),
+    };
+    // The blobs of the live collection are not in the new one, it gets a
+    // journal of its own that replaces the current one once swapped.
+    if arg.swap_alias.is_some() && blobs.is_some() {
+        let path = staging_blobs_file(config, &collection);
+        blobs = match BlobStore::open(&path) {
+            Ok(staging) => Some(staging),
+            Err(e) => {
+                let _ = collections::delete(&base_url, &collection).await;
+                return Err(format!("Failed to open blobs '{}': {}", path.display(), e));
+            }
+        };
+    }
+    let sink = if arg.dry_run {
+        Sink::DryRun
+    } else {
+        Sink::new(&backend.options(&base_url, &collection))?
+    };
+    let progress = Progress::new(value.len());
+    let run = Run {
+        config,
+        sink: &sink,
+        progress: &progress,
+        // A dry run leaves the state of the repositories as it is.
+        state: if arg.dry_run { None } else { Some(&state) },
+        blobs: blobs.as_ref().filter(|_| !arg.dry_run),
+        cache: cache.as_ref(),
+        reindex,
+        incremental: false,
+        fetch: false,
+    };
+    let summaries: Vec<(String, Summary)> = stream::iter(value)
+        .map(|git_url| {
+            let span = tracing::info_span!("repo", url = %git_url);
+            run.index_repo(git_url).instrument(span)
+        })
+        .buffered(config.index.concurrency)
+        .collect()
+        .await;
+    progress.finish();
+
+    if arg.dry_run {
+        print_dry_run(&summaries);
+    } else {
+        print_summary(&summaries);
+    }
+
+    let mut report = RunReport::new(&backend.kind, &collection, arg.dry_run, started, &summaries);
+    let mut swapped = Ok(());
+    if let Some(alias) = &arg.swap_alias {
+        let indexed = summaries.iter().map(|(_, s)| s.indexed).sum();
+        swapped = swap_alias(&base_url, alias, &collection, indexed, arg.drop_previous).await;
+        if blobs.is_some() {
+            keep_staging_blobs(config, &collection, swapped.is_ok());
+        }
+        report.swapped(alias, &swapped);
+    }
+
+    // Written before a failed swap returns, CI reads them then the most.
+    if let Some(path) = &arg.report {
+        report.write_json(path)?;
+    }
+    if let Some(path) = &arg.junit {
+        report.write_junit(path)?;
+    }
+    swapped?;
+
+    match report.failures() {
+        0 => Ok(()),
+        failures => Err(format!(
+            "{} of {} repositories failed",
+            failures,
+            summaries.len()
+        )),
+    }
+}
+
+// `--resume` skips the repositories indexed completely, and the failed ones
+// until their backoff has passed.
+fn is_pending(state: &StateStore, git_url: &str) -> bool {
+    let repo = match state.get(git_url) {
+        Some(repo) => repo,
+        None => return true,
+    };
+    match repo.status {
+        RepoStatus::Indexed => {
+            tracing::info!(
+                "Skip '{}', already indexed at {}",
+                git_url,
+                repo.commit.as_deref().unwrap_or("unknown commit")
+            );
+            false
+        }
+        RepoStatus::Failed | RepoStatus::Partial if repo.retry_at() > state::now() => {
+            tracing::info!(
+                "Skip '{}', failed {} times, retrying in {}s",
+                git_url,
+                repo.attempts,
+                repo.retry_at() - state::now()
+            );
+            false
+        }
+        _ => true,
+    }
+}
+
+/// The blobs indexed so far, when `index.dedupe` is on.
+pub fn open_blobs(config: &Config) -> Result<Option<BlobStore>, String> {
+    if !config.index.dedupe {
+        return Ok(None);
+    }
+    let blobs_file = &config.index.blobs_file;
+    BlobStore::open(blobs_file)
+        .map(Some)
+        .map_err(|e| format!("Failed to open blobs '{}': {}", blobs_file.display(), e))
+}
+
+// `hli-blobs.<collection>.json` next to `index.blobs_file`.
+fn staging_blobs_file(config: &Config, collection: &str) -> PathBuf {
+    let blobs_file = &config.index.blobs_file;
+    blobs_file.with_extension(format!("{}.json", collection))
+}
+
+// Replace the journal of the blobs with the one of the staging collection
+// once it is live, or drop it with the collection.
+fn keep_staging_blobs(config: &Config, collection: &str, swapped: bool) {
+    let staging = staging_blobs_file(config, collection);
+    let kept = match swapped {
+        true if staging.exists() => fs::rename(&staging, &config.index.blobs_file),
+        // No blob was claimed, the new collection holds none.
+        true => fs::remove_file(&config.index.blobs_file),
+        false => fs::remove_file(&staging),
+    };
+    match kept {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => tracing::warn!("Failed to record the blobs: {}", e),
+    }
+}
+
+/// The render cache, when `cache.enabled` is on.
+pub fn open_cache(config: &Config) -> Result<Option<RenderCache>, String> {
+    if !config.cache.enabled {
+        return Ok(None);
+    }
+    RenderCache::open(&config.cache).map(Some).map_err(|e| {
+        format!(
+            "Failed to open the cache '{}': {}",
+            config.cache.dir.display(),
+            e
+        )
+    })
+}
+
+/// Indexes the repositories of a run, recording their state and progress.
+pub struct Run<'a> {
+    pub config: &'a Config,
+    pub sink: &'a Sink,
+    pub progress: &'a Progress,
+    pub state: Option<&'a StateStore>,
+    pub blobs: Option<&'a BlobStore>,
+    pub cache: Option<&'a RenderCache>,
+    /// Delete the documents of a repository before indexing it.
+    pub reindex: bool,
+    /// Index only the files changed since the commit indexed last, a
+    /// repository without one is re-indexed.
+    pub incremental: bool,
+    /// Fetch the ref the url links to, or the default branch, before
+    /// indexing, a clone is indexed as it is otherwise.
+    pub fetch: bool,
+}
+
+impl Run<'_> {
+    pub async fn index_repo(&self, git_url: String) -> (String, Summary) {
+        self.progress.start_repo(&git_url);
+        // Read before the state is reset to running.
+        let base = match (self.incremental, self.state) {
+            (true, Some(state)) => state.indexed_commit(&git_url),
+            _ => None,
+        };
+        if let Some(state) = self.state {
+            if let Err(e) = state.start(&git_url) {
+                tracing::warn!("Failed to record the state: {}", e);
+            }
+        }
+
+        let summary = self.index(&git_url, base.as_deref()).await;
+
+        if let Some(state) = self.state {
+            if let Err(e) = state.finish(&git_url, &summary) {
+                tracing::warn!("Failed to record the state: {}", e);
+            }
+        }
+        self.progress.finish_repo(&git_url);
+        (git_url, summary)
+    }
+
+    async fn index(&self, git_url: &str, base: Option<&str>) -> Summary {
+        let resolving = Instant::now();
+        let resolved = git::get_repo(git_url, &self.config.providers).await;
+        let resolve = resolving.elapsed();
+        if let Err(e) = resolved {
+            tracing::error!("Failed to resolve: {}", e);
+            let mut summary = Summary::failed(format!("Failed to resolve: {}", e));
+            summary.phases.resolve = resolve;
+            return summary;
+        }
+        let mut indexer = match Indexer::new(git_url, self.sink, self.config) {
+            Ok(indexer) => indexer.with_progress(self.progress),
+            Err(e) => return Summary::failed(e),
+        };
+        if let Some(blobs) = self.blobs {
+            indexer = indexer.with_blobs(blobs);
+        }
+        if let Some(cache) = self.cache {
+            indexer = indexer.with_cache(cache);
+        }
+        // The documents of files deleted since an unknown commit would stay.
+        if self.reindex || (self.incremental && base.is_none()) {
+            if let Err(e) = indexer.remove_repo().await {
+                tracing::error!("Failed to delete documents: {}", e);
+                return Summary::failed(format!("Failed to delete documents: {}", e));
+            }
+        }
+        if self.fetch {
+            let git_ref = indexer.git_ref.clone();
+            indexer = indexer.with_ref(git_ref.as_deref().unwrap_or("HEAD"));
+        }
+        if let Some(base) = base {
+            indexer = indexer.with_base(base);
+        }
+        let mut summary = indexer.process().await;
+        summary.phases.resolve += resolve;
+        summary
+    }
+}
+
+fn print_summary(summaries: &[(String, Summary)]) {
+    let mut total = Summary::default();
+    for (git_url, summary) in summaries {
+        total.add(summary);
+        if let Some(failure) = &summary.failure {
+            println!("{}: {}", git_url, failure);
+        } else if summary.failed > 0 {
+            println!("{}: {} documents failed", git_url, summary.failed);
+        }
+    }
+    println!(
+        "Indexed {} documents from {} repositories, {} failed, {} skipped, {} duplicates, {} cached",
+        total.indexed,
+        summaries.len(),
+        total.failed,
+        total.skipped,
+        total.duplicates,
+        total.cached
+    );
+}
+
+fn print_dry_run(summaries: &[(String, Summary)]) {
+    let mut total = Summary::default();
+    for (git_url, summary) in summaries {
+        total.add(summary);
+        println!("{}", git_url);
+        print_counts(summary);
+    }
+    println!("Total of {} repositories", summaries.len());
+    print_counts(&total);
+}
+
+fn print_counts(summary: &Summary) {
+    println!(
+        "  would index {} files as {} chunks",
+        summary.indexed, summary.chunks
+    );
+    let skips = summary
+        .skips
+        .iter()
+        .map(|(skip, files)| format!("{} {}", files, skip))
+        .collect::<Vec<_>>();
+    if skips.is_empty() {
+        println!("  skipped {} files", summary.skipped);
+    } else {
+        println!("  skipped {} files ({})", summary.skipped, skips.join(", "));
+    }
+    let languages = summary
+        .languages
+        .iter()
+        .map(|(lang, files)| format!("{} {}", lang, files))
+        .collect::<Vec<_>>();
+    if !languages.is_empty() {
+        println!("  languages: {}", languages.join(", "));
+    }
+}
+
+async fn create_staging_collection(
+    base_url: &str,
+    alias: &str,
+    config_set: &str,
+) -> Result<String, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let name = format!("{}_{}", alias, timestamp);
+    tracing::info!("Creating collection '{}' for alias '{}'", name, alias);
+    collections::create(base_url, &name, config_set)
+        .await
+        .map_err(|e| format!("Failed to create collection '{}': {}", name, e))?;
+    if let Err(e) = schema::apply(base_url, &name).await {
+        let _ = collections::delete(base_url, &name).await;
+        return Err(e);
+    }
+    Ok(name)
+}
+
+// Point `alias` to the freshly built collection, or drop it and keep the
+// previous one live when the indexed documents are not all there.
+async fn swap_alias(
+    base_url: &str,
+    alias: &str,
+    collection: &str,
+    stored: usize,
+    drop_previous: bool,
+) -> Result<(), String> {
+    let swapped = collections::swap_alias(base_url, alias, collection, stored, drop_previous);
+    match swapped.await? {
+        Some(previous) if drop_previous => println!(
+            "Alias '{}' swapped from '{}' to '{}', '{}' deleted",
+            alias, previous, collection, previous
+        ),
+        Some(previous) => println!(
+            "Alias '{}' swapped from '{}' to '{}', '{}' kept",
+            alias, previous, collection, previous
+        ),
+        None => println!("Alias '{}' created for '{}'", alias, collection),
+    }
+    Ok(())
+}
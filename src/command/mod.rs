@@ -0,0 +1,17 @@
+pub mod cache;
+pub mod config;
+pub mod daemon;
+pub mod discover;
+pub mod import;
+pub mod index;
+pub mod repos;
+pub mod schema;
+pub mod search;
+
+use crate::arg::Arg;
+use clap::CommandFactory;
+
+pub fn completions(shell: clap_complete::Shell) {
+    let mut command = Arg::command();
+    clap_complete::generate(shell, &mut command, "hli", &mut std::io::stdout());
+}
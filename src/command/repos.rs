@@ -0,0 +1,108 @@
+use crate::arg::{ReposArgs, StatusArgs};
+use crate::command::index;
+use heline_indexer::config::Config;
+use heline_indexer::git::RepoRef;
+use heline_indexer::indexer::Indexer;
+use heline_indexer::manifest;
+use heline_indexer::sink::Sink;
+use heline_indexer::state::StateStore;
+use std::path::Path;
+
+// Arguments are repository urls or manifests listing them.
+async fn resolve(repos: &[String], config: &Config) -> Result<Vec<String>, String> {
+    let mut urls = Vec::new();
+    for repo in repos {
+        let path = Path::new(repo);
+        if path.is_file() {
+            urls.extend(manifest::load(path, &config.providers).await?);
+        } else {
+            urls.push(repo.to_string());
+        }
+    }
+    Ok(urls)
+}
+
+pub async fn delete(config: &Config, arg: &ReposArgs) -> Result<(), String> {
+    let backend = &config.backend;
+    let base_url = backend.location();
+    let sink = Sink::new(&backend.options(&base_url, &backend.core))?;
+    let blobs = index::open_blobs(config)?;
+
+    let mut failed = 0;
+    for git_url in resolve(&arg.repos, config).await? {
+        let mut indexer = match Indexer::new(&git_url, &sink, config) {
+            Ok(indexer) => indexer,
+            Err(e) => {
+                tracing::error!("{}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        if let Some(blobs) = &blobs {
+            indexer = indexer.with_blobs(blobs);
+        }
+        match indexer.remove_repo().await {
+            Ok(_) => println!("Deleted documents of '{}'", indexer.repo.path()),
+            Err(e) => {
+                tracing::error!("{}: Failed to delete documents {}", git_url, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("Failed to delete {} repositories", failed));
+    }
+    Ok(())
+}
+
+/// Print the document count, the state of the last run and its commit, for
+/// the given repositories or every repository of the state.
+pub async fn status(config: &Config, arg: &StatusArgs) -> Result<(), String> {
+    let backend = &config.backend;
+    let base_url = backend.location();
+    let sink = Sink::new(&backend.options(&base_url, &backend.core))?;
+    let state_file = &config.index.state_file;
+    let state = StateStore::open(state_file)
+        .map_err(|e| format!("Failed to open state '{}': {}", state_file.display(), e))?;
+
+    let urls = if arg.repos.is_empty() {
+        state.all().into_iter().map(|repo| repo.url).collect()
+    } else {
+        resolve(&arg.repos, config).await?
+    };
+
+    for git_url in urls {
+        let repo = match RepoRef::parse(&git_url) {
+            Ok(repo) => repo.path(),
+            Err(e) => {
+                tracing::error!("{}", e);
+                continue;
+            }
+        };
+        let mut notes = Vec::new();
+        let total = match sink.count_repo(&repo).await {
+            Ok(total) => total.to_string(),
+            Err(e) => {
+                notes.push(e.to_string());
+                "-".to_string()
+            }
+        };
+        let (status, commit) = match state.get(&git_url) {
+            Some(last) => {
+                notes.extend(last.failure);
+                let mut commit = last.commit.unwrap_or_default();
+                commit.truncate(10);
+                (last.status.to_string(), commit)
+            }
+            None => ("-".to_string(), String::new()),
+        };
+
+        let mut line = format!("{:>8}  {:<8}  {:<10}  {}", total, status, commit, repo);
+        if !notes.is_empty() {
+            line.push_str(&format!(" ({})", notes.join(", ")));
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
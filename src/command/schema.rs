@@ -0,0 +1,48 @@
+use crate::arg::SchemaAction;
+use heline_indexer::config::Config;
+use heline_indexer::{meilisearch, opensearch, solr, typesense};
+
+pub async fn run(config: &Config, action: SchemaAction) -> Result<(), String> {
+    let backend = &config.backend;
+    let base_url = backend.location();
+    let core = &backend.core;
+    let api_key = backend.api_key.as_deref();
+    let apply = matches!(action, SchemaAction::Apply);
+    let drifts = match (&backend.kind[..], apply) {
+        ("solr", true) => solr::schema::apply(&base_url, core).await.map(describe),
+        ("solr", false) => solr::schema::check(&base_url, core).await.map(describe),
+        ("opensearch" | "elasticsearch", true) => {
+            opensearch::template::apply(&base_url, core).await
+        }
+        ("opensearch" | "elasticsearch", false) => {
+            opensearch::template::check(&base_url, core).await
+        }
+        ("meilisearch", true) => meilisearch::settings::apply(&base_url, core, api_key).await,
+        ("meilisearch", false) => meilisearch::settings::check(&base_url, core, api_key).await,
+        ("typesense", true) => typesense::schema::apply(&base_url, core, api_key)
+            .await
+            .map(describe),
+        ("typesense", false) => typesense::schema::check(&base_url, core, api_key)
+            .await
+            .map(describe),
+        (backend, _) => Err(format!("The {} backend has no schema to manage", backend)),
+    }?;
+
+    if drifts.is_empty() {
+        println!("Schema of '{}' is up to date!", core);
+        return Ok(());
+    }
+    for drift in &drifts {
+        println!("{}: {}", core, drift);
+    }
+    if apply {
+        println!("Applied {} schema changes to '{}'", drifts.len(), core);
+        Ok(())
+    } else {
+        Err(format!("Schema of '{}' has drifted!", core))
+    }
+}
+
+fn describe<T: ToString>(drifts: Vec<T>) -> Vec<String> {
+    drifts.iter().map(|drift| drift.to_string()).collect()
+}
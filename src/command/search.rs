@@ -0,0 +1,20 @@
+use crate::arg::SearchArgs;
+use heline_indexer::config::Config;
+use heline_indexer::local;
+
+pub fn run(config: &Config, arg: &SearchArgs) -> Result<(), String> {
+    let hits = local::search::search(&config.backend.index_dir, &arg.query, arg.limit as usize)?;
+    for hit in &hits {
+        let copies = match hit.copies {
+            0 => String::new(),
+            1 => ", also in 1 repository".to_string(),
+            copies => format!(", also in {} repositories", copies),
+        };
+        println!(
+            "{:.2}  {}:{}-{}  ({}{})",
+            hit.score, hit.file_id, hit.start_line, hit.end_line, hit.lang, copies
+        );
+    }
+    println!("Found {} results for '{}'", hits.len(), arg.query);
+    Ok(())
+}
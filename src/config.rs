@@ -0,0 +1,381 @@
+use crate::schedule::Schedule;
+use crate::sink::{SinkOptions, BACKENDS};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FILE_NAME: &str = "heline.toml";
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/51.0.2704.103 Safari/537.36";
+
+/// Settings of the indexer, the defaults overridden by `heline.toml`, then
+/// by the environment and the command line.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub backend: BackendConfig,
+    pub clone: CloneConfig,
+    pub index: IndexConfig,
+    pub chunk: ChunkConfig,
+    pub cache: CacheConfig,
+    pub filters: FilterConfig,
+    pub providers: ProviderConfig,
+    pub server: ServerConfig,
+    pub daemon: DaemonConfig,
+    /// The file the settings were read from.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    pub kind: String,
+    /// Server url, defaults to the backend's local port.
+    pub url: Option<String>,
+    /// Solr core, collection or index name.
+    pub core: String,
+    /// Meilisearch or Typesense api key.
+    pub api_key: Option<String>,
+    /// Solr `commitWithin` of inserted documents, in milliseconds.
+    pub commit_within: u64,
+    /// Solr config set of the collections created by `--swap-alias`.
+    pub config_set: String,
+    pub index_dir: PathBuf,
+    pub out_dir: PathBuf,
+    pub gzip: bool,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        Self {
+            kind: "solr".to_string(),
+            url: None,
+            core: "heline".to_string(),
+            api_key: None,
+            commit_within: 1000,
+            config_set: "heline".to_string(),
+            index_dir: PathBuf::from("heline-index"),
+            out_dir: PathBuf::from("export"),
+            gzip: false,
+        }
+    }
+}
+
+impl BackendConfig {
+    /// Server url, or the output directory for file based backends.
+    pub fn location(&self) -> String {
+        match &self.kind[..] {
+            "tantivy" => return self.index_dir.display().to_string(),
+            "ndjson" => return self.out_dir.display().to_string(),
+            _ => {}
+        }
+        match &self.url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => match &self.kind[..] {
+                "opensearch" | "elasticsearch" => "http://localhost:9200".to_string(),
+                "meilisearch" => "http://localhost:7700".to_string(),
+                "typesense" => "http://localhost:8108".to_string(),
+                _ => "http://localhost:8984".to_string(),
+            },
+        }
+    }
+
+    pub fn options<'a>(&'a self, location: &'a str, collection: &'a str) -> SinkOptions<'a> {
+        SinkOptions {
+            backend: &self.kind,
+            location,
+            collection,
+            compress: self.gzip,
+            api_key: self.api_key.as_deref(),
+            commit_within: self.commit_within,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CloneConfig {
+    /// Folder the repositories are cloned to, as `<host>/<owner>/<name>`.
+    pub folder: PathBuf,
+    /// Delete the cloned repository after indexing.
+    pub delete_dir: bool,
+}
+
+impl Default for CloneConfig {
+    fn default() -> Self {
+        Self {
+            folder: PathBuf::from("repos"),
+            delete_dir: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexConfig {
+    /// Number of repositories cloned and indexed at the same time.
+    pub concurrency: usize,
+    /// Journal of the state of every repository, read by `--resume` and
+    /// `status`.
+    pub state_file: PathBuf,
+    /// Store files already indexed for another repository, e.g. in forks and
+    /// mirrors, as a reference instead of their content. Off by default.
+    pub dedupe: bool,
+    /// Journal of the indexed blobs and the documents holding them.
+    pub blobs_file: PathBuf,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            state_file: PathBuf::from("hli-state.json"),
+            dedupe: false,
+            blobs_file: PathBuf::from("hli-blobs.json"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChunkConfig {
+    /// Lines a chunk holds at least.
+    pub min_lines: usize,
+    /// A chunk grows line by line until its html reaches this size.
+    pub max_chars: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            min_lines: 3,
+            max_chars: 2000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Reuse the chunks rendered for a blob before instead of highlighting
+    /// it again.
+    pub enabled: bool,
+    /// Directory of the rendered chunks.
+    pub dir: PathBuf,
+    /// Bytes the cache holds at most, the least recently used entries are
+    /// evicted beyond.
+    pub max_size: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: PathBuf::from("hli-cache"),
+            max_size: 1 << 30,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// File names that are never indexed.
+    pub ignore_files: Vec<String>,
+    /// Files larger than this many bytes are skipped.
+    pub max_file_size: Option<u64>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            ignore_files: vec!["package-lock.json".to_string(), "yarn.lock".to_string()],
+            max_file_size: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProviderConfig {
+    pub user_agent: String,
+    pub github_token: Option<String>,
+    pub gitlab_token: Option<String>,
+    /// Hosts of Gitea instances, their repositories are cloned without
+    /// being looked up.
+    pub gitea_hosts: Vec<String>,
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: USER_AGENT.to_string(),
+            github_token: None,
+            gitlab_token: None,
+            gitea_hosts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address `hli serve` listens on.
+    pub listen: String,
+    /// Jobs running at the same time.
+    pub workers: usize,
+    /// Finished jobs kept for `GET /jobs`.
+    pub history: usize,
+    /// Bearer token required by the api when set.
+    pub token: Option<String>,
+    /// Secret the forges sign their webhooks with, webhooks are refused
+    /// when unset.
+    pub webhook_secret: Option<String>,
+    /// Repositories webhooks may index, any repository of a supported host
+    /// when unset.
+    pub manifest: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:8080".to_string(),
+            workers: 2,
+            history: 1000,
+            token: None,
+            webhook_secret: None,
+            manifest: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Seconds between two checks of a repository.
+    pub interval: u64,
+    /// Cron expression of the checks, used instead of `interval`.
+    pub cron: Option<String>,
+    /// Schedules of single repositories.
+    pub repos: Vec<RepoSchedule>,
+    /// Address `/metrics` is served on, not served when unset.
+    pub listen: Option<String>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            interval: 3600,
+            cron: None,
+            repos: Vec::new(),
+            listen: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RepoSchedule {
+    pub url: String,
+    pub interval: Option<u64>,
+    pub cron: Option<String>,
+}
+
+impl DaemonConfig {
+    /// The schedule of `url`, its own when it has one.
+    pub fn schedule(&self, url: &str) -> Result<Schedule, String> {
+        match self.repos.iter().find(|repo| repo.url == url) {
+            Some(repo) if repo.interval.is_some() || repo.cron.is_some() => {
+                Schedule::new(repo.interval.unwrap_or(self.interval), repo.cron.as_deref())
+            }
+            _ => Schedule::new(self.interval, self.cron.as_deref()),
+        }
+    }
+}
+
+impl Config {
+    /// Read the config file, `path` or the first `heline.toml` found, then
+    /// apply the provider tokens of the environment.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let mut config = match discover(path)? {
+            Some(path) => {
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+                let mut config: Config = toml::from_str(&text)
+                    .map_err(|e| format!("Invalid config '{}': {}", path.display(), e))?;
+                config.source = Some(path);
+                config
+            }
+            None => Config::default(),
+        };
+
+        if let Ok(token) = env::var("GITHUB_TOKEN") {
+            config.providers.github_token = Some(token);
+        }
+        if let Ok(token) = env::var("GITLAB_TOKEN") {
+            config.providers.gitlab_token = Some(token);
+        }
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !BACKENDS.contains(&&self.backend.kind[..]) {
+            return Err(format!(
+                "Invalid backend '{}', expected one of: {}",
+                self.backend.kind,
+                BACKENDS.join(", ")
+            ));
+        }
+        if self.index.concurrency == 0 {
+            return Err("index.concurrency must be at least 1".to_string());
+        }
+        if self.server.workers == 0 {
+            return Err("server.workers must be at least 1".to_string());
+        }
+        Schedule::new(self.daemon.interval, self.daemon.cron.as_deref())
+            .map_err(|e| format!("daemon: {}", e))?;
+        for repo in &self.daemon.repos {
+            self.daemon
+                .schedule(&repo.url)
+                .map_err(|e| format!("daemon.repos '{}': {}", repo.url, e))?;
+        }
+        Ok(())
+    }
+
+    /// The config as toml, with the credentials masked.
+    pub fn to_toml(&self) -> Result<String, String> {
+        let mut masked = self.clone();
+        for secret in [
+            &mut masked.backend.api_key,
+            &mut masked.providers.github_token,
+            &mut masked.providers.gitlab_token,
+            &mut masked.server.token,
+            &mut masked.server.webhook_secret,
+        ] {
+            if secret.is_some() {
+                *secret = Some("********".to_string());
+            }
+        }
+        toml::to_string_pretty(&masked).map_err(|e| e.to_string())
+    }
+}
+
+// `--config` must exist, otherwise the first `heline.toml` found in the
+// working directory or the user config directory is used.
+fn discover(explicit: Option<&Path>) -> Result<Option<PathBuf>, String> {
+    if let Some(path) = explicit {
+        if !path.is_file() {
+            return Err(format!("Config file not found: {}", path.display()));
+        }
+        return Ok(Some(path.to_path_buf()));
+    }
+
+    let local = PathBuf::from(FILE_NAME);
+    if local.is_file() {
+        return Ok(Some(local));
+    }
+    Ok(dirs::config_dir()
+        .map(|dir| dir.join("heline").join(FILE_NAME))
+        .filter(|path| path.is_file()))
+}
@@ -0,0 +1,20 @@
+pub mod reader;
+pub mod writer;
+
+use crate::solr::client::{GitFile, GithubFileUpdate};
+use serde::{Deserialize, Serialize};
+
+/// One line of an export, the exact document the indexer sends to Solr.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", content = "doc", rename_all = "lowercase")]
+pub enum Record {
+    Insert(GitFile),
+    Update(GithubFileUpdate),
+    /// The file was removed, or only partly stored.
+    Delete(DeletedFile),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeletedFile {
+    pub id: String,
+}
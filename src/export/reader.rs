@@ -0,0 +1,52 @@
+use crate::export::Record;
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Expand the given paths into export files, directories are read one level deep.
+pub fn list_files(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = Vec::new();
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?.path();
+                let name = entry.to_str().unwrap_or_default();
+                if name.ends_with(".ndjson") || name.ends_with(".ndjson.gz") {
+                    entries.push(entry);
+                }
+            }
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+/// Read every record of an `.ndjson` or `.ndjson.gz` export.
+pub fn read_records(path: &Path) -> io::Result<Vec<Record>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Box::new(MultiGzDecoder::new(file)),
+        _ => Box::new(file),
+    };
+
+    let mut records = Vec::new();
+    for (number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str::<Record>(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path.display(), number + 1, e),
+            )
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
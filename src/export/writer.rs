@@ -0,0 +1,192 @@
+use crate::export::{reader, DeletedFile, Record};
+use crate::solr::client::{GitFile, GithubFileUpdate};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+enum Output {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Output {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Output::Plain(file) => file,
+            Output::Gzip(encoder) => encoder,
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut file) => file.flush(),
+            Output::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+/// Writes the records of every repository to `<dir>/<owner>_<repo>.ndjson`,
+/// or `.ndjson.gz` when compressed. Other characters than letters, digits,
+/// `-` and `.` are escaped, so no two repositories share a file.
+pub struct ExportWriter {
+    dir: PathBuf,
+    compress: bool,
+    outputs: Mutex<HashMap<String, Output>>,
+    /// Repositories whose file was created by this writer, written again
+    /// after a flush they are appended to instead of truncated.
+    created: Mutex<HashSet<String>>,
+}
+
+impl ExportWriter {
+    pub fn new(dir: PathBuf, compress: bool) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            compress,
+            outputs: Mutex::new(HashMap::new()),
+            created: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub fn insert(&self, data: &GitFile) -> io::Result<()> {
+        self.write(&data.repo, &Record::Insert(data.clone()))
+    }
+
+    pub fn update(&self, data: &GitFile) -> io::Result<()> {
+        self.write(&data.repo, &Record::Update(GithubFileUpdate::from(data)))
+    }
+
+    pub fn delete_files(&self, repo: &str, ids: &[String]) -> io::Result<()> {
+        for id in ids {
+            let file = DeletedFile { id: id.to_string() };
+            self.write(repo, &Record::Delete(file))?;
+        }
+        Ok(())
+    }
+
+    /// Close the file of `repo`.
+    pub fn flush(&self, repo: &str) -> io::Result<()> {
+        let output = self.outputs.lock().unwrap().remove(repo);
+        match output {
+            Some(output) => output.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn delete_repo(&self, repo: &str) -> io::Result<()> {
+        for path in self.paths(repo) {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of documents exported for `repo`.
+    pub fn count_repo(&self, repo: &str) -> io::Result<u64> {
+        let mut total = 0;
+        for path in self.paths(repo) {
+            if path.exists() {
+                let mut ids = HashSet::new();
+                for record in reader::read_records(&path)? {
+                    match record {
+                        Record::Insert(data) => ids.insert(data.id),
+                        Record::Delete(file) => ids.remove(&file.id),
+                        Record::Update(_) => false,
+                    };
+                }
+                total += ids.len() as u64;
+            }
+        }
+        Ok(total)
+    }
+
+    fn paths(&self, repo: &str) -> [PathBuf; 2] {
+        let name = file_name(repo);
+        [
+            self.dir.join(format!("{}.ndjson", name)),
+            self.dir.join(format!("{}.ndjson.gz", name)),
+        ]
+    }
+
+    fn write(&self, repo: &str, record: &Record) -> io::Result<()> {
+        let mut outputs = self.outputs.lock().unwrap();
+        if !outputs.contains_key(repo) {
+            let output = self.create(repo)?;
+            outputs.insert(repo.to_string(), output);
+        }
+
+        let writer = outputs.get_mut(repo).unwrap().writer();
+        serde_json::to_writer(&mut *writer, record)?;
+        writer.write_all(b"\n")
+    }
+
+    fn create(&self, repo: &str) -> io::Result<Output> {
+        let [plain, compressed] = self.paths(repo);
+        let path = if self.compress { compressed } else { plain };
+        // Gzip members appended to a file read back as one stream.
+        let file = if self.created.lock().unwrap().insert(repo.to_string()) {
+            File::create(path)?
+        } else {
+            File::options().append(true).open(path)?
+        };
+        if self.compress {
+            let encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+            Ok(Output::Gzip(encoder))
+        } else {
+            Ok(Output::Plain(BufWriter::new(file)))
+        }
+    }
+}
+
+// `/` becomes `_` and every other byte but letters, digits, `-` and `.` is
+// percent-escaped, `a_b/c` and `a/b_c` are `a%5Fb_c` and `a_b%5Fc`.
+fn file_name(repo: &str) -> String {
+    let mut name = String::with_capacity(repo.len());
+    for byte in repo.bytes() {
+        match byte {
+            b'/' => name.push('_'),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' => name.push(byte as char),
+            _ => name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gives_every_repository_its_own_file() {
+        assert_eq!(file_name("heline/heline.dev"), "heline_heline.dev");
+        assert_eq!(file_name("a_b/c"), "a%5Fb_c");
+        assert_eq!(file_name("a/b_c"), "a_b%5Fc");
+        assert_eq!(file_name("group/sub/repo"), "group_sub_repo");
+        assert_eq!(file_name("group_sub/repo"), "group%5Fsub_repo");
+        assert_eq!(file_name("a%2F/b"), "a%252F_b");
+    }
+
+    #[test]
+    fn keeps_repositories_with_similar_names_apart() {
+        let dir = std::env::temp_dir().join(format!("hli-export-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let writer = ExportWriter::new(dir.clone(), false).unwrap();
+        for repo in ["a_b/c", "a/b_c"] {
+            writer
+                .delete_files(repo, &[format!("{}/README.md", repo)])
+                .unwrap();
+            writer.flush(repo).unwrap();
+        }
+        assert!(writer.paths("a_b/c")[0].exists());
+        assert!(writer.paths("a/b_c")[0].exists());
+        writer.delete_repo("a_b/c").unwrap();
+        assert!(!writer.paths("a_b/c")[0].exists());
+        assert!(writer.paths("a/b_c")[0].exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,46 +1,228 @@
-use reqwest::Response;
+use crate::config::ProviderConfig;
+use crate::git::{self, RemoteRepo};
+use crate::metrics;
+use reqwest::{RequestBuilder, Response};
+use serde::Deserialize;
 use std::collections::HashMap;
 
-pub async fn get_user_id(username: &str) -> Result<String, String> {
-    let user_agent = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/51.0.2704.103 Safari/537.36".to_owned();
+#[derive(Deserialize)]
+struct Repo {
+    html_url: String,
+    fork: bool,
+    archived: bool,
+    stargazers_count: u64,
+    #[serde(default)]
+    topics: Vec<String>,
+    language: Option<String>,
+    pushed_at: Option<String>,
+}
+
+impl From<Repo> for RemoteRepo {
+    fn from(repo: Repo) -> Self {
+        Self {
+            url: repo.html_url,
+            fork: repo.fork,
+            archived: repo.archived,
+            stars: repo.stargazers_count,
+            topics: repo.topics,
+            language: repo.language,
+            pushed_at: repo.pushed_at,
+        }
+    }
+}
 
+pub async fn get_user_id(username: &str, providers: &ProviderConfig) -> Result<String, String> {
     let url = format!("https://api.github.com/users/{}", username);
-    let client = reqwest::Client::new();
-    return match client
-        .get(url)
-        .header("User-Agent", user_agent)
-        .send()
-        .await
-    {
+    match request(&url, providers).send().await {
         Ok(res) => extract_id(res).await,
         Err(e) => Err(e.to_string()),
-    };
+    }
 }
 
-pub async fn get_repo(repo: &str) -> Result<String, String> {
-    let user_agent = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/51.0.2704.103 Safari/537.36".to_owned();
-
+pub async fn get_repo(repo: &str, providers: &ProviderConfig) -> Result<String, String> {
     let url = format!("https://api.github.com/repos/{}", repo);
-    let client = reqwest::Client::new();
-    return match client
-        .get(url)
-        .header("User-Agent", user_agent)
-        .send()
-        .await
-    {
+    match request(&url, providers).send().await {
         Ok(res) => extract_id(res).await,
         Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Public repositories of an organization, or owned by a user when `user`.
+pub async fn list_repos(
+    owner: &str,
+    user: bool,
+    providers: &ProviderConfig,
+) -> Result<Vec<RemoteRepo>, String> {
+    let url = match user {
+        true => format!(
+            "https://api.github.com/users/{}/repos?type=owner&per_page=100",
+            owner
+        ),
+        false => format!(
+            "https://api.github.com/orgs/{}/repos?type=public&per_page=100",
+            owner
+        ),
     };
+    list_pages(url, owner, providers).await
+}
+
+// Every page from `url` on, following the `Link` header.
+async fn list_pages(
+    url: String,
+    owner: &str,
+    providers: &ProviderConfig,
+) -> Result<Vec<RemoteRepo>, String> {
+    let mut next = Some(url);
+    let mut repos = Vec::new();
+    while let Some(url) = next {
+        let res = request(&url, providers)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        record_rate_limit(&res);
+        if !res.status().is_success() {
+            return Err(format!("GitHub answered {} for '{}'", res.status(), owner));
+        }
+        next = git::next_page(&res);
+        let page: Vec<Repo> = res.json().await.map_err(|e| e.to_string())?;
+        repos.extend(page.into_iter().map(RemoteRepo::from));
+    }
+    Ok(repos)
+}
+
+#[derive(Deserialize)]
+struct SearchPage {
+    items: Vec<Repo>,
+}
+
+/// The first `limit` repositories matching a search `query`, most starred
+/// first. GitHub returns at most 1000 results.
+pub async fn search_repos(
+    query: &str,
+    limit: usize,
+    providers: &ProviderConfig,
+) -> Result<Vec<RemoteRepo>, String> {
+    let url = reqwest::Url::parse_with_params(
+        "https://api.github.com/search/repositories?sort=stars&order=desc&per_page=100",
+        &[("q", query)],
+    )
+    .map_err(|e| e.to_string())?;
+    let mut next = Some(url.to_string());
+    let mut repos = Vec::new();
+    while let Some(url) = next.filter(|_| repos.len() < limit) {
+        let res = request(&url, providers)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        record_rate_limit(&res);
+        if !res.status().is_success() {
+            return Err(format!("GitHub answered {} for '{}'", res.status(), query));
+        }
+        next = git::next_page(&res);
+        let page: SearchPage = res.json().await.map_err(|e| e.to_string())?;
+        repos.extend(page.items.into_iter().map(RemoteRepo::from));
+    }
+    repos.truncate(limit);
+    Ok(repos)
+}
+
+fn request(url: &str, providers: &ProviderConfig) -> RequestBuilder {
+    let client = reqwest::Client::new();
+    let request = client.get(url).header("User-Agent", &providers.user_agent);
+    match &providers.github_token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+fn record_rate_limit(res: &Response) {
+    if let Some(remaining) = git::rate_limit(res, "x-ratelimit-remaining") {
+        metrics::record_rate_limit("github", remaining);
+    }
 }
 
 async fn extract_id(res: Response) -> Result<String, String> {
-    return match res.json::<HashMap<String, serde_json::Value>>().await {
-        Ok(json) => {
-            return match json.get("id") {
-                Some(id) => Ok(id.to_string()),
-                _ => Err("Not found!".to_string()),
+    record_rate_limit(&res);
+    match res.json::<HashMap<String, serde_json::Value>>().await {
+        Ok(json) => match json.get("id") {
+            Some(id) => Ok(id.to_string()),
+            _ => Err("Not found!".to_string()),
+        },
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Query, State};
+    use axum::http::header::LINK;
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use serde_json::json;
+
+    // Two pages of an organization, the first links to the second.
+    async fn repos(
+        State(base): State<String>,
+        Query(params): Query<HashMap<String, String>>,
+    ) -> impl IntoResponse {
+        let repo = |name: &str, fork: bool, archived: bool| {
+            json!({
+                "html_url": format!("https://github.com/heline/{}", name),
+                "fork": fork,
+                "archived": archived,
+                "stargazers_count": 12,
+                "topics": ["search"],
+                "language": "Rust",
+                "pushed_at": "2024-05-01T10:00:00Z",
+            })
+        };
+        match params.get("page").map(String::as_str) {
+            Some("2") => (
+                [(LINK, String::new())],
+                Json(json!([repo("old", false, true)])),
+            ),
+            _ => {
+                let link = format!(
+                    "<{0}/orgs/heline/repos?page=2>; rel=\"next\", <{0}/orgs/heline/repos?page=2>; rel=\"last\"",
+                    base
+                );
+                let page = json!([repo("heline.dev", false, false), repo("fork", true, false)]);
+                ([(LINK, link)], Json(page))
             }
         }
-        Err(e) => Err(e.to_string()),
-    };
+    }
+
+    #[tokio::test]
+    async fn follows_the_link_header_across_pages() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new()
+            .route("/orgs/heline/repos", get(repos))
+            .with_state(base.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let url = format!("{}/orgs/heline/repos", base);
+        let found = list_pages(url, "heline", &ProviderConfig::default())
+            .await
+            .unwrap();
+        let urls: Vec<&str> = found.iter().map(|repo| &repo.url[..]).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://github.com/heline/heline.dev",
+                "https://github.com/heline/fork",
+                "https://github.com/heline/old",
+            ]
+        );
+        // What the owner filters of a manifest match against.
+        assert!(!found[0].fork && !found[0].archived);
+        assert!(found[1].fork);
+        assert!(found[2].archived);
+        assert_eq!(found[0].stars, 12);
+        assert_eq!(found[0].topics, ["search"]);
+        assert_eq!(found[0].language.as_deref(), Some("Rust"));
+        assert_eq!(found[0].pushed_at.as_deref(), Some("2024-05-01T10:00:00Z"));
+    }
 }
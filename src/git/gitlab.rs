@@ -1,3 +1,256 @@
-pub async fn get_repo(_repo: &str) -> Result<String, String> {
-    Ok(String::new())
+use crate::config::ProviderConfig;
+use crate::git::{self, RemoteRepo};
+use crate::metrics;
+use reqwest::{RequestBuilder, Response};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+struct Project {
+    id: u64,
+    web_url: String,
+    /// Only set on forks.
+    forked_from_project: Option<serde_json::Value>,
+    #[serde(default)]
+    archived: bool,
+    #[serde(default)]
+    star_count: u64,
+    #[serde(default)]
+    topics: Vec<String>,
+    last_activity_at: Option<String>,
+}
+
+impl Project {
+    fn into_remote(self, language: Option<String>) -> RemoteRepo {
+        RemoteRepo {
+            url: self.web_url,
+            fork: self.forked_from_project.is_some(),
+            archived: self.archived,
+            stars: self.star_count,
+            topics: self.topics,
+            language,
+            pushed_at: self.last_activity_at,
+        }
+    }
+}
+
+/// Look up the project id, repositories are not checked without a token.
+pub async fn get_repo(repo: &str, providers: &ProviderConfig) -> Result<String, String> {
+    if providers.gitlab_token.is_none() {
+        return Ok(String::new());
+    }
+
+    let url = format!(
+        "https://gitlab.com/api/v4/projects/{}",
+        repo.replace('/', "%2F")
+    );
+    let res = request(&url, providers)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    record_rate_limit(&res);
+    match res.json::<HashMap<String, serde_json::Value>>().await {
+        Ok(json) => match json.get("id") {
+            Some(id) => Ok(id.to_string()),
+            _ => Err("Not found!".to_string()),
+        },
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Public projects of a group and its subgroups, or of a user when `user`.
+/// The primary language is looked up per project when `languages` is set.
+pub async fn list_repos(
+    owner: &str,
+    user: bool,
+    languages: bool,
+    providers: &ProviderConfig,
+) -> Result<Vec<RemoteRepo>, String> {
+    let owner_id = owner.replace('/', "%2F");
+    let url = match user {
+        true => format!(
+            "https://gitlab.com/api/v4/users/{}/projects?per_page=100&order_by=id&sort=asc",
+            owner_id
+        ),
+        false => format!(
+            "https://gitlab.com/api/v4/groups/{}/projects?include_subgroups=true&visibility=public&per_page=100&order_by=id&sort=asc",
+            owner_id
+        ),
+    };
+    list_pages(url, owner, languages, providers).await
+}
+
+// Every page from `url` on, following the `Link` header.
+async fn list_pages(
+    url: String,
+    owner: &str,
+    languages: bool,
+    providers: &ProviderConfig,
+) -> Result<Vec<RemoteRepo>, String> {
+    let mut next = Some(url);
+    let mut repos = Vec::new();
+    while let Some(url) = next {
+        let res = request(&url, providers)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        record_rate_limit(&res);
+        if !res.status().is_success() {
+            return Err(format!("GitLab answered {} for '{}'", res.status(), owner));
+        }
+        next = git::next_page(&res);
+        let page: Vec<Project> = res.json().await.map_err(|e| e.to_string())?;
+        for project in page {
+            let language = match languages {
+                true => get_language(project.id, providers).await?,
+                false => None,
+            };
+            repos.push(project.into_remote(language));
+        }
+    }
+    Ok(repos)
+}
+
+/// The first `limit` public projects matching `query`, most starred first.
+pub async fn search_repos(
+    query: &str,
+    limit: usize,
+    providers: &ProviderConfig,
+) -> Result<Vec<RemoteRepo>, String> {
+    let url = reqwest::Url::parse_with_params(
+        "https://gitlab.com/api/v4/projects?visibility=public&order_by=star_count&sort=desc&per_page=100",
+        &[("search", query)],
+    )
+    .map_err(|e| e.to_string())?;
+    let mut next = Some(url.to_string());
+    let mut repos = Vec::new();
+    while let Some(url) = next.filter(|_| repos.len() < limit) {
+        let res = request(&url, providers)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        record_rate_limit(&res);
+        if !res.status().is_success() {
+            return Err(format!("GitLab answered {} for '{}'", res.status(), query));
+        }
+        next = git::next_page(&res);
+        let page: Vec<Project> = res.json().await.map_err(|e| e.to_string())?;
+        repos.extend(page.into_iter().map(|project| project.into_remote(None)));
+    }
+    repos.truncate(limit);
+    Ok(repos)
+}
+
+// The language with the largest share of the project.
+async fn get_language(id: u64, providers: &ProviderConfig) -> Result<Option<String>, String> {
+    let url = format!("https://gitlab.com/api/v4/projects/{}/languages", id);
+    let res = request(&url, providers)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    record_rate_limit(&res);
+    let shares: HashMap<String, f64> = res.json().await.map_err(|e| e.to_string())?;
+    Ok(shares
+        .into_iter()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(language, _)| language))
+}
+
+fn request(url: &str, providers: &ProviderConfig) -> RequestBuilder {
+    let client = reqwest::Client::new();
+    let request = client.get(url).header("User-Agent", &providers.user_agent);
+    match &providers.gitlab_token {
+        Some(token) => request.header("PRIVATE-TOKEN", token),
+        None => request,
+    }
+}
+
+fn record_rate_limit(res: &Response) {
+    if let Some(remaining) = git::rate_limit(res, "ratelimit-remaining") {
+        metrics::record_rate_limit("gitlab", remaining);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Query, State};
+    use axum::http::header::LINK;
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use serde_json::json;
+
+    // Two pages of a group, the first links to the second.
+    async fn projects(
+        State(base): State<String>,
+        Query(params): Query<HashMap<String, String>>,
+    ) -> impl IntoResponse {
+        match params.get("page").map(String::as_str) {
+            Some("2") => {
+                // Fields GitLab leaves out of a project are defaults.
+                let page = json!([{
+                    "id": 3,
+                    "web_url": "https://gitlab.com/heline/old",
+                    "archived": true,
+                }]);
+                ([(LINK, String::new())], Json(page))
+            }
+            _ => {
+                let link = format!(
+                    "<{0}/groups/heline/projects?page=1>; rel=\"first\", <{0}/groups/heline/projects?page=2>; rel=\"next\"",
+                    base
+                );
+                let page = json!([
+                    {
+                        "id": 1,
+                        "web_url": "https://gitlab.com/heline/heline.dev",
+                        "archived": false,
+                        "star_count": 12,
+                        "topics": ["search"],
+                        "last_activity_at": "2024-05-01T10:00:00Z",
+                    },
+                    {
+                        "id": 2,
+                        "web_url": "https://gitlab.com/heline/fork",
+                        "forked_from_project": { "id": 1 },
+                    },
+                ]);
+                ([(LINK, link)], Json(page))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_the_link_header_across_pages() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new()
+            .route("/groups/heline/projects", get(projects))
+            .with_state(base.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let url = format!("{}/groups/heline/projects", base);
+        let found = list_pages(url, "heline", false, &ProviderConfig::default())
+            .await
+            .unwrap();
+        let urls: Vec<&str> = found.iter().map(|repo| &repo.url[..]).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://gitlab.com/heline/heline.dev",
+                "https://gitlab.com/heline/fork",
+                "https://gitlab.com/heline/old",
+            ]
+        );
+        // What the owner filters of a manifest match against.
+        assert!(!found[0].fork && !found[0].archived);
+        assert_eq!(found[0].stars, 12);
+        assert_eq!(found[0].topics, ["search"]);
+        assert_eq!(found[0].pushed_at.as_deref(), Some("2024-05-01T10:00:00Z"));
+        assert!(found[1].fork);
+        assert_eq!(found[1].stars, 0);
+        assert!(found[2].archived);
+        assert_eq!(found[2].language, None);
+    }
 }
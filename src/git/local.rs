@@ -0,0 +1,298 @@
+use crate::git;
+use crate::utils;
+use reqwest::Url;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Owner of local repositories that do not name one.
+const DEFAULT_OWNER: &str = "local";
+
+/// A directory or git repository on disk, indexed under a repository name and
+/// owner of its own. Manifests and the state refer to it by its `file://` url,
+/// e.g. `file:///srv/fixtures?name=fixtures&owner=acme&commit=v1`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalRepo {
+    pub path: PathBuf,
+    pub name: String,
+    pub owner: String,
+    /// Commit to index instead of the working tree.
+    pub commit: Option<String>,
+}
+
+impl LocalRepo {
+    /// `None` when `url` is not a `file://` url.
+    pub fn parse(url: &str) -> Option<Result<Self, String>> {
+        if !url.starts_with("file://") {
+            return None;
+        }
+        let parsed = match Url::parse(url) {
+            Ok(parsed) => parsed,
+            Err(e) => return Some(Err(format!("Invalid url '{}': {}", url, e))),
+        };
+        let path = match parsed.to_file_path() {
+            Ok(path) => path,
+            Err(_) => return Some(Err(format!("Invalid file url '{}'", url))),
+        };
+        let mut repo = Self::at(path);
+        for (key, value) in parsed.query_pairs() {
+            match &key[..] {
+                "name" => repo.name = value.to_string(),
+                "owner" => repo.owner = value.to_string(),
+                "commit" => repo.commit = Some(value.to_string()),
+                _ => return Some(Err(format!("Unknown parameter '{}' in '{}'", key, url))),
+            }
+        }
+        Some(repo.validate())
+    }
+
+    /// A manifest object with a `path`, relative to the manifest directory
+    /// `dir`, or a `file://` url, and an optional `name`, `owner` and `commit`.
+    pub fn from_entry(entry: &Value, dir: &Path) -> Result<Self, String> {
+        let mut repo = match (entry["path"].as_str(), entry["url"].as_str()) {
+            (Some(path), _) => {
+                let path = std::path::absolute(dir.join(path)).map_err(|e| e.to_string())?;
+                Self::at(path)
+            }
+            (None, Some(url)) => Self::parse(url).ok_or("expected a file:// url")??,
+            (None, None) => return Err("expected a path or a url".to_string()),
+        };
+        if let Some(name) = entry["name"].as_str() {
+            repo.name = name.to_string();
+        }
+        if let Some(owner) = entry["owner"].as_str() {
+            repo.owner = owner.to_string();
+        }
+        if let Some(commit) = entry["commit"].as_str() {
+            repo.commit = Some(commit.to_string());
+        }
+        repo.validate()
+    }
+
+    // Named after the directory, without the `.git` suffix of bare
+    // repositories.
+    fn at(path: PathBuf) -> Self {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default()
+            .trim_end_matches(".git")
+            .to_string();
+        Self {
+            path,
+            name,
+            owner: DEFAULT_OWNER.to_string(),
+            commit: None,
+        }
+    }
+
+    fn validate(self) -> Result<Self, String> {
+        let valid = |part: &str| !part.is_empty() && !part.contains('/');
+        if !valid(&self.name) {
+            return Err(format!("Invalid repository name '{}'", self.name));
+        }
+        if !valid(&self.owner) {
+            return Err(format!("Invalid repository owner '{}'", self.owner));
+        }
+        Ok(self)
+    }
+
+    /// The `file://` url the repository is listed under.
+    pub fn url(&self) -> String {
+        let mut url = match Url::from_file_path(&self.path) {
+            Ok(url) => url,
+            Err(_) => return format!("file://{}", self.path.display()),
+        };
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("name", &self.name);
+            query.append_pair("owner", &self.owner);
+            if let Some(commit) = &self.commit {
+                query.append_pair("commit", commit);
+            }
+        }
+        url.to_string()
+    }
+
+    /// `owner/name`, in place of the path of a hosted repository.
+    pub fn repo_path(&self) -> String {
+        format!("{}/{}", self.owner, self.name)
+    }
+
+    /// Whether the working tree is indexed where it is. A commit, or a bare
+    /// repository, is checked out to the clone folder first.
+    pub fn in_place(&self) -> bool {
+        self.commit.is_none() && !self.is_bare()
+    }
+
+    fn is_bare(&self) -> bool {
+        !self.path.join(".git").exists()
+            && self.path.join("HEAD").is_file()
+            && self.path.join("objects").is_dir()
+    }
+
+    /// Check out the commit, or the default branch of a bare repository, to
+    /// `dir`. Cloning from a path hardlinks the objects instead of copying
+    /// them.
+    pub async fn checkout(&self, dir: &Path) -> bool {
+        if let Err(e) = git::clone_repo(dir, &self.path.to_string_lossy()).await {
+            tracing::error!("{}", e);
+            return false;
+        }
+        let commit = self.commit.as_deref().unwrap_or("origin/HEAD");
+        tracing::info!("Checking out '{}' in {}", commit, dir.display());
+        utils::exec_command(
+            Command::new("git")
+                .current_dir(dir)
+                .args(["fetch", "--quiet", "origin"]),
+        )
+        .await
+            && utils::exec_command(
+                Command::new("git")
+                    .current_dir(dir)
+                    .args(["checkout", "--quiet", "--detach", commit]),
+            )
+            .await
+    }
+
+    /// Commit the repository would be indexed at, `None` for a directory
+    /// that is not a git repository.
+    pub async fn head(&self) -> Option<String> {
+        git::rev_parse(&self.path, self.commit.as_deref().unwrap_or("HEAD")).await
+    }
+
+    /// Branch recorded for the files of the checkout in `dir`, the commit
+    /// when one is given.
+    pub async fn branch(&self, dir: &Path) -> String {
+        match &self.commit {
+            Some(commit) => commit.to_string(),
+            None if self.in_place() => git::get_branch_name(dir),
+            None => git::remote_head(dir)
+                .await
+                .unwrap_or_else(|| git::get_branch_name(dir)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::export::writer::ExportWriter;
+    use crate::indexer::Indexer;
+    use crate::sink::Sink;
+    use serde_json::json;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=hli", "-c", "user.email=hli@localhost"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    // A repository on `main` with one commit of `a.rs`.
+    fn work_tree(root: &Path) -> PathBuf {
+        let dir = root.join("app");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.rs"), "fn a() {\n    println!(\"a\");\n}\n").unwrap();
+        git(&dir, &["init", "--quiet", "--initial-branch=main"]);
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "--quiet", "-m", "first"]);
+        dir
+    }
+
+    #[test]
+    fn parses_urls_and_manifest_entries() {
+        let repo = LocalRepo::parse("file:///srv/fixtures?name=app&owner=acme&commit=v1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(repo.path, PathBuf::from("/srv/fixtures"));
+        assert_eq!(repo.repo_path(), "acme/app");
+        assert_eq!(repo.commit.as_deref(), Some("v1"));
+        assert_eq!(LocalRepo::parse(&repo.url()).unwrap(), Ok(repo));
+
+        // Named after the directory, a bare repository without `.git`.
+        let bare = LocalRepo::parse("file:///srv/app.git").unwrap().unwrap();
+        assert_eq!(bare.repo_path(), "local/app");
+        assert!(LocalRepo::parse("https://github.com/acme/app").is_none());
+        assert!(LocalRepo::parse("file:///srv/app?branch=main")
+            .unwrap()
+            .is_err());
+        assert!(LocalRepo::parse("file:///srv/app?owner=a/b")
+            .unwrap()
+            .is_err());
+
+        let entry = json!({ "path": "fixtures/app", "owner": "acme" });
+        let repo = LocalRepo::from_entry(&entry, Path::new("/srv")).unwrap();
+        assert_eq!(repo.path, PathBuf::from("/srv/fixtures/app"));
+        assert_eq!(repo.repo_path(), "acme/app");
+        assert!(LocalRepo::from_entry(&json!({ "name": "app" }), Path::new("/srv")).is_err());
+    }
+
+    #[tokio::test]
+    async fn indexes_a_working_tree_in_place() {
+        let root = std::env::temp_dir().join(format!("hli-in-place-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = work_tree(&root);
+        let commit = git(&dir, &["rev-parse", "HEAD"]);
+        // Files not committed yet are indexed too.
+        std::fs::write(dir.join("b.rs"), "fn b() {\n    println!(\"b\");\n}\n").unwrap();
+
+        let url = format!("file://{}?owner=acme", dir.display());
+        let local = LocalRepo::parse(&url).unwrap().unwrap();
+        assert!(local.in_place());
+        assert_eq!(local.head().await.as_deref(), Some(&commit[..]));
+        assert_eq!(local.branch(&dir).await, "main");
+
+        let mut config = Config::default();
+        config.clone.folder = root.join("repos");
+        config.clone.delete_dir = true;
+        let writer = ExportWriter::new(root.join("export"), false).unwrap();
+        let sink = Sink::Ndjson(writer);
+        let summary = Indexer::new(&url, &sink, &config).unwrap().process().await;
+        assert_eq!((summary.indexed, summary.failed), (2, 0));
+        assert_eq!(summary.commit.as_deref(), Some(&commit[..]));
+        assert_eq!(sink.count_repo("acme/app").await.unwrap(), 2);
+        // Neither cloned nor deleted.
+        assert!(!config.clone.folder.exists());
+        assert!(dir.join("b.rs").is_file());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn checks_out_commits_and_bare_repositories() {
+        let root = std::env::temp_dir().join(format!("hli-checkout-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = work_tree(&root);
+        let first = git(&dir, &["rev-parse", "HEAD"]);
+        std::fs::write(dir.join("a.rs"), "fn a() {}\n").unwrap();
+        git(&dir, &["commit", "--quiet", "--all", "-m", "second"]);
+
+        let url = format!("file://{}?commit={}", dir.display(), first);
+        let at_commit = LocalRepo::parse(&url).unwrap().unwrap();
+        assert!(!at_commit.in_place());
+        assert_eq!(at_commit.head().await.as_deref(), Some(&first[..]));
+        let checkout = root.join("checkout");
+        assert!(at_commit.checkout(&checkout).await);
+        let source = std::fs::read_to_string(checkout.join("a.rs")).unwrap();
+        assert!(source.contains("println"));
+        assert_eq!(at_commit.branch(&checkout).await, first);
+
+        let bare = root.join("app.git");
+        git(&root, &["clone", "--quiet", "--bare", "app", "app.git"]);
+        let bare = LocalRepo::parse(&format!("file://{}", bare.display()))
+            .unwrap()
+            .unwrap();
+        assert!(!bare.in_place());
+        let checkout = root.join("bare");
+        assert!(bare.checkout(&checkout).await);
+        let source = std::fs::read_to_string(checkout.join("a.rs")).unwrap();
+        assert_eq!(source, "fn a() {}\n");
+        assert_eq!(bare.branch(&checkout).await, "main");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
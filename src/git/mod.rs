@@ -1,13 +1,34 @@
 pub mod github;
 pub mod gitlab;
+pub mod local;
+mod repo_ref;
 
+pub use repo_ref::{RepoRef, LOCAL_HOST};
+
+use crate::config::ProviderConfig;
 use crate::utils;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
 use std::path::Path;
-use std::process::Command;
+use std::process::Stdio;
+
+/// A repository listed by a provider api.
+#[derive(Clone, Debug, Default)]
+pub struct RemoteRepo {
+    pub url: String,
+    pub fork: bool,
+    pub archived: bool,
+    pub stars: u64,
+    pub topics: Vec<String>,
+    /// Primary language, only looked up on GitLab when asked for.
+    pub language: Option<String>,
+    /// Time of the last push, ISO 8601.
+    pub pushed_at: Option<String>,
+}
 
 pub fn get_branch_name(dir: &Path) -> String {
     let file_path = dir.join(".git/HEAD");
-    return match std::fs::read_to_string(file_path) {
+    match std::fs::read_to_string(file_path) {
         Ok(file) => file
             .split("/")
             .last()
@@ -16,43 +37,394 @@ pub fn get_branch_name(dir: &Path) -> String {
             .trim_end_matches('\n')
             .to_string(),
         Err(_) => "master".to_string(),
+    }
+}
+
+/// Fetch `git_ref` from origin and check it out, `HEAD` is the default
+/// branch of the remote.
+pub async fn checkout(dir: &Path, git_ref: &str) -> bool {
+    tracing::info!("Checking out '{}' in {}", git_ref, dir.display());
+    utils::exec_command(
+        tokio::process::Command::new("git")
+            .current_dir(dir)
+            .args(["fetch", "--quiet", "origin", git_ref]),
+    )
+    .await
+        && utils::exec_command(tokio::process::Command::new("git").current_dir(dir).args([
+            "checkout",
+            "--quiet",
+            "--detach",
+            "FETCH_HEAD",
+        ]))
+        .await
+}
+
+/// The ref a link into the tree points to, from its first segment and the
+/// rest of the link, e.g. `release/1.0` for `release` and `1.0/src` when
+/// origin has a `release/1.0` branch. Branches and tags may have a `/` in
+/// their name as paths do, the longest branch or tag the link starts with is
+/// taken, or else its first segment, e.g. a commit.
+pub async fn resolve_ref(dir: &Path, git_ref: &str, tree_path: Option<&str>) -> String {
+    let Some(tree_path) = tree_path else {
+        return git_ref.to_string();
+    };
+    let output = tokio::process::Command::new("git")
+        .current_dir(dir)
+        .args(["ls-remote", "--heads", "--tags", "origin"])
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await;
+    // 1b4c2e...	refs/heads/release/1.0
+    let stdout = match output {
+        Ok(output) if output.status.success() => output.stdout,
+        _ => return git_ref.to_string(),
+    };
+    let stdout = String::from_utf8_lossy(&stdout);
+    let names = stdout
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(_, name)| ref_name(name.trim_end_matches("^{}")));
+    longest_ref(names, git_ref, Some(tree_path))
+        .unwrap_or(git_ref)
+        .to_string()
+}
+
+// The longest of the branch or tag `names` a link into the tree starts
+// with, whole segments only.
+fn longest_ref<'a>(
+    names: impl Iterator<Item = &'a str>,
+    git_ref: &str,
+    tree_path: Option<&str>,
+) -> Option<&'a str> {
+    let tree = match tree_path {
+        Some(tree_path) => format!("{}/{}", git_ref, tree_path),
+        None => git_ref.to_string(),
     };
+    names
+        .filter(|name| *name == tree || tree.starts_with(&format!("{}/", name)))
+        .max_by_key(|name| name.len())
+}
+
+/// Default branch of the `origin` remote of the clone in `dir`.
+pub async fn remote_head(dir: &Path) -> Option<String> {
+    let output = tokio::process::Command::new("git")
+        .current_dir(dir)
+        .args(["ls-remote", "--symref", "origin", "HEAD"])
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    // ref: refs/heads/main	HEAD
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().find(|line| line.starts_with("ref: "))?;
+    let git_ref = line.trim_start_matches("ref: ").split('\t').next()?;
+    Some(ref_name(git_ref).to_string())
 }
 
-pub fn clone_repo(cwd: &Path, ssh_url: &str, repo_name: &str) -> bool {
-    if !cwd.exists() {
-        std::fs::create_dir(cwd).expect(&format!("Failed to create directory: {}", cwd.display()));
+/// Commit `git_ref` points to in the remote repository at `url`, without
+/// cloning it. `tree_path` is the rest of a link into the tree, as for
+/// `resolve_ref`, and a ref that is no branch or tag is taken for a commit.
+pub async fn ls_remote(url: &str, git_ref: &str, tree_path: Option<&str>) -> Option<String> {
+    let output = tokio::process::Command::new("git")
+        .args(["ls-remote", url])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
     }
+    // 1b4c2e...	HEAD, annotated tags are followed by the commit as `<tag>^{}`.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut commits: HashMap<&str, &str> = HashMap::new();
+    for (commit, name) in stdout.lines().filter_map(|line| line.split_once('\t')) {
+        match name.strip_suffix("^{}") {
+            Some(tag) => {
+                commits.insert(ref_name(tag), commit);
+            }
+            None => {
+                commits.entry(ref_name(name)).or_insert(commit);
+            }
+        }
+    }
+    match longest_ref(commits.keys().copied(), git_ref, tree_path) {
+        Some(name) => Some(commits[name].to_string()),
+        None if git_ref.bytes().all(|b| b.is_ascii_hexdigit()) => Some(git_ref.to_string()),
+        None => None,
+    }
+}
+
+/// Branch or tag name of a ref, e.g. `main` for `refs/heads/main`.
+pub fn ref_name(git_ref: &str) -> &str {
+    git_ref
+        .strip_prefix("refs/heads/")
+        .or_else(|| git_ref.strip_prefix("refs/tags/"))
+        .unwrap_or(git_ref)
+}
+
+/// Whether `git_ref` is the full hash of a commit rather than a name.
+pub fn is_commit(git_ref: &str) -> bool {
+    git_ref.len() == 40 && git_ref.bytes().all(|b| b.is_ascii_hexdigit())
+}
 
-    let cloned_repo_dir = cwd.join(Path::new(repo_name));
-    if cloned_repo_dir.exists() {
-        print!("Repository already cloned: {}\n", cloned_repo_dir.display());
-        return true;
+/// Hash of the commit checked out in `dir`.
+pub async fn get_commit(dir: &Path) -> Option<String> {
+    rev_parse(dir, "HEAD").await
+}
+
+/// Hash of the commit `rev` names in the repository at `dir`.
+pub async fn rev_parse(dir: &Path, rev: &str) -> Option<String> {
+    let output = tokio::process::Command::new("git")
+        .current_dir(dir)
+        .args(["rev-parse", "--verify", "--quiet"])
+        .arg(format!("{}^{{commit}}", rev))
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
     }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
 
-    print!(
-        "{}\n",
-        format!("Cloning '{}' to {}/{}", ssh_url, cwd.display(), repo_name)
-    );
-    utils::exec_command(
-        Command::new("git")
-            .current_dir(cwd)
-            .arg("clone")
-            .arg(ssh_url)
-            .arg(repo_name),
-    )
+/// A file changed between two commits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    /// Path from the root of the repository.
+    pub path: String,
+    /// Blob of the file at the base commit, `None` when it was added.
+    pub old_blob: Option<String>,
+    pub deleted: bool,
+}
+
+/// Files changed between `base` and the commit checked out in `dir`, `None`
+/// when `base` is not in the repository, e.g. after a force push.
+pub async fn diff(dir: &Path, base: &str) -> Option<Vec<Change>> {
+    let output = tokio::process::Command::new("git")
+        .current_dir(dir)
+        .args([
+            "diff",
+            "--raw",
+            "--no-renames",
+            "--no-abbrev",
+            "-z",
+            base,
+            "HEAD",
+        ])
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_raw_diff(&String::from_utf8_lossy(&output.stdout))
 }
 
-pub async fn get_repo(git_url: &str) -> Result<String, String> {
-    match utils::get_url_host(git_url) {
-        None => Err(format!("Invalid git url {}", git_url)),
-        Some(host) => {
-            let git_repo = utils::get_git_repo_path(git_url);
-            return match &host[..] {
-                "github.com" => github::get_repo(&git_repo).await,
-                "gitlab.com" => gitlab::get_repo(&git_repo).await,
-                _ => Err(format!("Unsupported git host: {}", host)),
-            };
+// `:100644 100644 <old blob> <new blob> M\0<path>\0` for every file.
+fn parse_raw_diff(raw: &str) -> Option<Vec<Change>> {
+    let mut changes = Vec::new();
+    let mut fields = raw.split('\0').filter(|field| !field.is_empty());
+    while let Some(meta) = fields.next() {
+        let path = fields.next()?;
+        let parts: Vec<&str> = meta.trim_start_matches(':').split(' ').collect();
+        let [_, _, old_blob, _, status] = parts[..] else {
+            return None;
+        };
+        changes.push(Change {
+            path: path.to_string(),
+            old_blob: Some(old_blob)
+                .filter(|blob| blob.bytes().any(|b| b != b'0'))
+                .map(str::to_string),
+            deleted: status == "D",
+        });
+    }
+    Some(changes)
+}
+
+/// Content of `blob` in the repository at `dir`, `None` when it does not
+/// have the blob.
+pub async fn cat_blob(dir: &Path, blob: &str) -> Option<Vec<u8>> {
+    let output = tokio::process::Command::new("git")
+        .current_dir(dir)
+        .args(["cat-file", "blob", blob])
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    output.status.success().then_some(output.stdout)
+}
+
+/// Git blob hash of the file at `path`, as `git hash-object` computes it.
+pub fn blob_sha(path: &Path) -> std::io::Result<String> {
+    let content = std::fs::read(path)?;
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", content.len()));
+    hasher.update(&content);
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Clone `url` to `dir`, kept as it is when it exists already.
+pub async fn clone_repo(dir: &Path, url: &str) -> Result<(), String> {
+    if dir.exists() {
+        tracing::info!("Repository already cloned: {}", dir.display());
+        return Ok(());
+    }
+    if let Some(parent) = dir.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create directory {}: {}", parent.display(), e))?;
+    }
+
+    tracing::info!("Cloning '{}' to {}", url, dir.display());
+    let clone = tokio::process::Command::new("git")
+        .arg("clone")
+        .arg(url)
+        .arg(dir)
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await;
+    match clone.is_ok_and(|status| status.success()) {
+        true => Ok(()),
+        false => Err(format!("Failed to clone '{}' to {}", url, dir.display())),
+    }
+}
+
+/// Whether repositories of `host` can be indexed.
+pub fn is_supported_host(host: &str, providers: &ProviderConfig) -> bool {
+    matches!(host, "github.com" | "gitlab.com") || providers.gitea_hosts.iter().any(|h| h == host)
+}
+
+pub async fn get_repo(git_url: &str, providers: &ProviderConfig) -> Result<String, String> {
+    if let Some(local) = local::LocalRepo::parse(git_url) {
+        let local = local?;
+        return match local.path.exists() {
+            true => Ok(String::new()),
+            false => Err(format!(
+                "Local repository not found: {}",
+                local.path.display()
+            )),
+        };
+    }
+    let repo = RepoRef::parse(git_url)?;
+    match &repo.host[..] {
+        "github.com" => github::get_repo(&repo.path(), providers).await,
+        "gitlab.com" => gitlab::get_repo(&repo.path(), providers).await,
+        host if is_supported_host(host, providers) => Ok(String::new()),
+        host => Err(format!("Unsupported git host: {}", host)),
+    }
+}
+
+/// Requests left in the rate limit window of a provider api, from the
+/// `header` of `res`.
+pub fn rate_limit(res: &reqwest::Response, header: &str) -> Option<i64> {
+    res.headers().get(header)?.to_str().ok()?.parse().ok()
+}
+
+/// The `rel="next"` url of the `Link` header of a paginated response.
+pub fn next_page(res: &reqwest::Response) -> Option<String> {
+    let link = res.headers().get("link")?.to_str().ok()?;
+    link.split(',').find_map(|part| {
+        let (url, rel) = part.split_once(';')?;
+        if rel.trim() != "rel=\"next\"" {
+            return None;
+        }
+        let url = url.trim().trim_start_matches('<').trim_end_matches('>');
+        Some(url.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=hli", "-c", "user.email=hli@localhost"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn resolves_refs_with_slashes() {
+        let root = std::env::temp_dir().join(format!("hli-resolve-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let origin = root.join("origin");
+        std::fs::create_dir_all(&origin).unwrap();
+        git(&origin, &["init", "--quiet"]);
+        git(
+            &origin,
+            &["commit", "--quiet", "--allow-empty", "-m", "init"],
+        );
+        git(&origin, &["branch", "release/1.0"]);
+        git(&origin, &["tag", "-a", "v2/rc", "-m", "rc"]);
+        git(&root, &["clone", "--quiet", "origin", "clone"]);
+
+        let dir = root.join("clone");
+        let cases = [
+            ("main", None, "main"),
+            ("release", Some("1.0"), "release/1.0"),
+            ("release", Some("1.0/src/lib.rs"), "release/1.0"),
+            ("release", Some("docs"), "release"),
+            ("v2", Some("rc/README.md"), "v2/rc"),
+            ("1b4c2e0", Some("src"), "1b4c2e0"),
+        ];
+        for (git_ref, tree_path, resolved) in cases {
+            assert_eq!(
+                resolve_ref(&dir, git_ref, tree_path).await,
+                resolved,
+                "ref of {} {:?}",
+                git_ref,
+                tree_path
+            );
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_the_commit_of_a_remote_ref() {
+        let root = std::env::temp_dir().join(format!("hli-ls-remote-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        git(&root, &["init", "--quiet"]);
+        git(
+            &root,
+            &["commit", "--quiet", "--allow-empty", "-m", "first"],
+        );
+        let first = git(&root, &["rev-parse", "HEAD"]);
+        git(&root, &["branch", "release/1.0"]);
+        git(&root, &["tag", "-a", "v1", "-m", "v1"]);
+        git(
+            &root,
+            &["commit", "--quiet", "--allow-empty", "-m", "second"],
+        );
+        let second = git(&root, &["rev-parse", "HEAD"]);
+
+        let url = root.display().to_string();
+        let cases = [
+            ("HEAD", None, Some(&second[..])),
+            ("release", Some("1.0/src"), Some(&first[..])),
+            // Annotated tags are read as their commit.
+            ("v1", None, Some(&first[..])),
+            ("1b4c2e0", Some("src"), Some("1b4c2e0")),
+            ("nightly", None, None),
+        ];
+        for (git_ref, tree_path, commit) in cases {
+            let head = ls_remote(&url, git_ref, tree_path).await;
+            assert_eq!(head.as_deref(), commit, "commit of {}", git_ref);
         }
+        std::fs::remove_dir_all(&root).unwrap();
     }
 }
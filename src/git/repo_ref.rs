@@ -0,0 +1,512 @@
+use crate::git::local::LocalRepo;
+use std::fmt;
+use std::str::FromStr;
+
+/// Host of the repositories of `file://` urls.
+pub const LOCAL_HOST: &str = "local";
+
+/// A repository parsed from any of the urls people paste: web urls with or
+/// without a scheme, `www.` or a port, clone urls, SSH urls like
+/// `git@github.com:owner/name.git` and links into the tree such as
+/// `/tree/main/src`. Git hosts ignore the case of owners and names, so they
+/// are lowercased and every form of a url yields the same ids.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RepoRef {
+    pub host: String,
+    /// The namespace of the repository, GitLab subgroups included.
+    pub owner: String,
+    pub name: String,
+    /// Branch, tag or commit a link into the tree points to, only its first
+    /// segment when the link goes on with a path, as a `/` may be part of
+    /// either.
+    pub git_ref: Option<String>,
+    /// The rest of a link into the tree after `git_ref`, the path it points
+    /// to unless the ref has a `/` in its name, see `git::resolve_ref`.
+    pub tree_path: Option<String>,
+    /// Port of `ssh://` urls, cloned from instead of the default SSH port.
+    pub ssh_port: Option<u16>,
+}
+
+impl RepoRef {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let url = url.trim();
+        if let Some(local) = LocalRepo::parse(url) {
+            let local = local?;
+            return Ok(Self {
+                host: LOCAL_HOST.to_string(),
+                owner: local.owner,
+                name: local.name,
+                git_ref: local.commit,
+                tree_path: None,
+                ssh_port: None,
+            });
+        }
+        let invalid = |reason: &str| format!("Invalid repository url '{}': {}", url, reason);
+
+        // Queries and fragments never name the repository.
+        let rest = url.split(['?', '#']).next().unwrap_or_default();
+        let (authority, path, ssh) = match rest.split_once("://") {
+            Some((scheme, rest)) => {
+                let ssh = match &scheme.to_lowercase()[..] {
+                    "https" | "http" | "git" => false,
+                    "ssh" | "git+ssh" => true,
+                    _ => return Err(invalid(&format!("unsupported scheme '{}'", scheme))),
+                };
+                let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+                (authority, path, ssh)
+            }
+            // `host:443/owner/name` has a port, `git@host:owner/name` is SSH.
+            None => match rest.split_once(['/', ':']) {
+                Some((host, path)) if rest[host.len()..].starts_with(':') && !has_port(path) => {
+                    (host, path, true)
+                }
+                _ => {
+                    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+                    (authority, path, false)
+                }
+            },
+        };
+
+        let host_port = authority.rsplit('@').next().unwrap_or_default();
+        let (host, port) = match host_port.split_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| invalid(&format!("invalid port '{}'", port)))?;
+                (host, Some(port))
+            }
+            None => (host_port, None),
+        };
+        let host = host.to_lowercase();
+        let host = host.strip_prefix("www.").unwrap_or(&host).to_string();
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let (repo, rest) = segments.split_at(repo_len(&host, &segments));
+        if repo.len() < 2 {
+            return Err(invalid("missing owner or name"));
+        }
+        let (owner, name) = repo.split_at(repo.len() - 1);
+        let name = name[0];
+        let name = name.strip_suffix(".git").unwrap_or(name).to_lowercase();
+        if name.is_empty() {
+            return Err(invalid("missing name"));
+        }
+
+        let (git_ref, tree_path) = match tree_ref(rest) {
+            Some((git_ref, tree_path)) => (Some(git_ref), tree_path),
+            None => (None, None),
+        };
+        Ok(Self {
+            host,
+            owner: owner.join("/").to_lowercase(),
+            name,
+            git_ref,
+            tree_path,
+            ssh_port: port.filter(|port| ssh && *port != 22),
+        })
+    }
+
+    /// `owner/name`, the repository the documents belong to.
+    pub fn path(&self) -> String {
+        format!("{}/{}", self.owner, self.name)
+    }
+
+    /// `host/owner/name`, the same for every url of the repository.
+    pub fn key(&self) -> String {
+        format!("{}/{}", self.host, self.path())
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.host == LOCAL_HOST
+    }
+
+    /// The SSH url the repository is cloned from.
+    pub fn ssh_url(&self) -> String {
+        match self.ssh_port {
+            Some(port) => format!("ssh://git@{}:{}/{}.git", self.host, port, self.path()),
+            None => format!("git@{}:{}.git", self.host, self.path()),
+        }
+    }
+}
+
+impl FromStr for RepoRef {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        Self::parse(url)
+    }
+}
+
+impl fmt::Display for RepoRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "https://{}/{}", self.host, self.path())
+    }
+}
+
+fn has_port(path: &str) -> bool {
+    let port = path.split('/').next().unwrap_or_default();
+    !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())
+}
+
+// How many segments of the path name the repository. GitLab nests groups and
+// puts `-` between a project and its pages, elsewhere it is `owner/name`.
+fn repo_len(host: &str, segments: &[&str]) -> usize {
+    if let Some(dash) = segments.iter().position(|s| *s == "-") {
+        return dash;
+    }
+    match host {
+        "gitlab.com" => segments
+            .iter()
+            .skip(2)
+            .position(|s| matches!(*s, "tree" | "blob" | "commit" | "raw"))
+            .map_or(segments.len(), |i| i + 2),
+        _ => segments.len().min(2),
+    }
+}
+
+// The ref and path of `tree/<ref>/<path>` and the like, or Gitea's
+// `src/branch/<ref>/<path>`. A commit link names nothing but the commit.
+fn tree_ref(rest: &[&str]) -> Option<(String, Option<String>)> {
+    let rest = rest.strip_prefix(&["-"]).unwrap_or(rest);
+    match rest {
+        ["src", "commit", commit, ..] | ["commit", commit, ..] => Some((commit.to_string(), None)),
+        ["src", "branch" | "tag", git_ref, path @ ..]
+        | ["tree" | "blob" | "raw", git_ref, path @ ..] => Some((
+            git_ref.to_string(),
+            Some(path.join("/")).filter(|path| !path.is_empty()),
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_url_forms() {
+        let cases = [
+            (
+                "https://github.com/ahmadrosid/hl",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "https://github.com/ahmadrosid/hl/",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "https://github.com/ahmadrosid/hl.git",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "http://github.com/ahmadrosid/hl",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "github.com/ahmadrosid/hl",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "https://www.github.com/ahmadrosid/hl",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "https://GitHub.com/AhmadRosid/HL",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "https://github.com:443/ahmadrosid/hl",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "github.com:443/ahmadrosid/hl",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "https://github.com/ahmadrosid/hl?tab=readme#usage",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "git@github.com:ahmadrosid/hl.git",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "git@github.com:ahmadrosid/hl",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "ssh://git@github.com/ahmadrosid/hl.git",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "git://github.com/ahmadrosid/hl.git",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "  https://github.com/ahmadrosid/hl\n",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "https://github.com/ahmadrosid/hl/tree/main",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                Some("main"),
+            ),
+            (
+                "https://github.com/ahmadrosid/hl/tree/v1.0/src/lib.rs",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                Some("v1.0"),
+            ),
+            (
+                "https://github.com/ahmadrosid/hl/blob/main/README.md",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                Some("main"),
+            ),
+            (
+                "https://github.com/ahmadrosid/hl/tree/release/1.0",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                Some("release"),
+            ),
+            (
+                "https://github.com/ahmadrosid/hl/commit/1b4c2e0",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                Some("1b4c2e0"),
+            ),
+            (
+                "https://github.com/ahmadrosid/hl/issues/1",
+                "github.com",
+                "ahmadrosid",
+                "hl",
+                None,
+            ),
+            (
+                "https://gitlab.com/gitlab-org/gitlab",
+                "gitlab.com",
+                "gitlab-org",
+                "gitlab",
+                None,
+            ),
+            (
+                "https://gitlab.com/gitlab-org/security/gitlab",
+                "gitlab.com",
+                "gitlab-org/security",
+                "gitlab",
+                None,
+            ),
+            (
+                "https://gitlab.com/gitlab-org/gitlab/-/tree/master/app",
+                "gitlab.com",
+                "gitlab-org",
+                "gitlab",
+                Some("master"),
+            ),
+            (
+                "https://gitlab.com/gitlab-org/gitlab/tree/master",
+                "gitlab.com",
+                "gitlab-org",
+                "gitlab",
+                Some("master"),
+            ),
+            (
+                "git@gitlab.com:gitlab-org/security/gitlab.git",
+                "gitlab.com",
+                "gitlab-org/security",
+                "gitlab",
+                None,
+            ),
+            (
+                "https://gitea.com/gitea/tea/src/branch/main/cmd",
+                "gitea.com",
+                "gitea",
+                "tea",
+                Some("main"),
+            ),
+            (
+                "https://git.example.com:3000/team/tool",
+                "git.example.com",
+                "team",
+                "tool",
+                None,
+            ),
+        ];
+        for (url, host, owner, name, git_ref) in cases {
+            let repo = RepoRef::parse(url).unwrap_or_else(|e| panic!("{}: {}", url, e));
+            assert_eq!(repo.host, host, "host of {}", url);
+            assert_eq!(repo.owner, owner, "owner of {}", url);
+            assert_eq!(repo.name, name, "name of {}", url);
+            assert_eq!(repo.git_ref.as_deref(), git_ref, "ref of {}", url);
+        }
+    }
+
+    #[test]
+    fn splits_links_into_the_tree() {
+        let cases = [
+            ("https://github.com/ahmadrosid/hl", None, None),
+            (
+                "https://github.com/ahmadrosid/hl/tree/main",
+                Some("main"),
+                None,
+            ),
+            (
+                "https://github.com/ahmadrosid/hl/tree/release/1.0",
+                Some("release"),
+                Some("1.0"),
+            ),
+            (
+                "https://github.com/ahmadrosid/hl/blob/v1.0/src/lib.rs",
+                Some("v1.0"),
+                Some("src/lib.rs"),
+            ),
+            (
+                "https://github.com/ahmadrosid/hl/commit/1b4c2e0/src",
+                Some("1b4c2e0"),
+                None,
+            ),
+            (
+                "https://gitlab.com/gitlab-org/gitlab/-/tree/master/app/",
+                Some("master"),
+                Some("app"),
+            ),
+            (
+                "https://gitea.com/gitea/tea/src/branch/main/cmd",
+                Some("main"),
+                Some("cmd"),
+            ),
+        ];
+        for (url, git_ref, tree_path) in cases {
+            let repo = RepoRef::parse(url).unwrap();
+            assert_eq!(repo.git_ref.as_deref(), git_ref, "ref of {}", url);
+            assert_eq!(repo.tree_path.as_deref(), tree_path, "path of {}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        let cases = [
+            "",
+            "https://github.com",
+            "https://github.com/ahmadrosid",
+            "https://github.com/ahmadrosid/.git",
+            "ftp://github.com/ahmadrosid/hl",
+            "https://github.com:https/ahmadrosid/hl",
+            "/home/ahmad/hl",
+        ];
+        for url in cases {
+            assert!(RepoRef::parse(url).is_err(), "{} should not parse", url);
+        }
+    }
+
+    #[test]
+    fn derives_ids_and_clone_urls() {
+        let cases = [
+            (
+                "https://github.com/AhmadRosid/hl/",
+                "ahmadrosid/hl",
+                "github.com/ahmadrosid/hl",
+                "git@github.com:ahmadrosid/hl.git",
+            ),
+            (
+                "git@github.com:ahmadrosid/hl.git",
+                "ahmadrosid/hl",
+                "github.com/ahmadrosid/hl",
+                "git@github.com:ahmadrosid/hl.git",
+            ),
+            (
+                "ssh://git@git.example.com:2222/team/tool.git",
+                "team/tool",
+                "git.example.com/team/tool",
+                "ssh://git@git.example.com:2222/team/tool.git",
+            ),
+            (
+                "ssh://git@github.com:22/ahmadrosid/hl.git",
+                "ahmadrosid/hl",
+                "github.com/ahmadrosid/hl",
+                "git@github.com:ahmadrosid/hl.git",
+            ),
+            (
+                "https://git.example.com:3000/team/tool",
+                "team/tool",
+                "git.example.com/team/tool",
+                "git@git.example.com:team/tool.git",
+            ),
+            (
+                "https://gitlab.com/gitlab-org/security/gitlab/-/blob/main/a.rb",
+                "gitlab-org/security/gitlab",
+                "gitlab.com/gitlab-org/security/gitlab",
+                "git@gitlab.com:gitlab-org/security/gitlab.git",
+            ),
+        ];
+        for (url, path, key, ssh_url) in cases {
+            let repo: RepoRef = url.parse().unwrap();
+            assert_eq!(repo.path(), path, "path of {}", url);
+            assert_eq!(repo.key(), key, "key of {}", url);
+            assert_eq!(repo.ssh_url(), ssh_url, "ssh url of {}", url);
+        }
+    }
+
+    #[test]
+    fn keeps_local_names() {
+        let repo =
+            RepoRef::parse("file:///srv/fixtures?name=Fixtures&owner=acme&commit=v1").unwrap();
+        assert!(repo.is_local());
+        assert_eq!(repo.path(), "acme/Fixtures");
+        assert_eq!(repo.git_ref.as_deref(), Some("v1"));
+    }
+}
@@ -1,207 +1,1018 @@
-use crate::git;
-use crate::parser;
-use crate::solr;
+use crate::blobs::{BlobEntry, BlobStore, Handover};
+use crate::cache::RenderCache;
+use crate::chunk::{self, Chunk};
+use crate::config::Config;
+use crate::git::{self, local::LocalRepo, RepoRef};
+use crate::metrics;
+use crate::parser::{self, Skip};
+use crate::progress::Progress;
+use crate::sink::{DocumentSink, Lines, Sink, SinkError};
 use crate::solr::client::GitFile;
 use crate::utils;
 
 use ignore::Walk;
-use select::document::Document;
-use select::predicate::{Class, Name};
+use std::collections::{BTreeMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
 
 pub struct MetaIndexFile {
     path: PathBuf,
-    root_path_len: usize,
+    /// Directory the repository was walked from.
+    root: PathBuf,
     git_repo: String,
     user_id: String,
     branch: String,
-    base_url: String,
     git_host: String,
 }
 
-pub struct Indexer {
+#[derive(Default, Clone)]
+pub struct Summary {
+    pub indexed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// Files stored as a reference to an identical file of another
+    /// repository, counted as indexed too.
+    pub duplicates: usize,
+    /// Files whose chunks came from the render cache.
+    pub cached: usize,
+    /// Chunks written, engines without partial updates store one document each.
+    pub chunks: usize,
+    /// Indexed files per language.
+    pub languages: BTreeMap<String, usize>,
+    /// Skipped files per reason.
+    pub skips: BTreeMap<Skip, usize>,
+    /// Size of the indexed sources.
+    pub bytes: u64,
+    /// Commit the files were read from.
+    pub commit: Option<String>,
+    pub phases: Phases,
+    /// Documents that could not be stored.
+    pub errors: Vec<String>,
+    /// Set when the repository could not be indexed at all.
+    pub failure: Option<String>,
+}
+
+/// Time spent in each step of indexing a repository.
+#[derive(Default, Clone, Copy)]
+pub struct Phases {
+    /// Looking up the repository and its owner on the git host.
+    pub resolve: Duration,
+    pub clone: Duration,
+    /// Walking the tree and reading the files.
+    pub walk: Duration,
+    /// Highlighting and chunking.
+    pub render: Duration,
+    /// Writing to the sink, including the final flush.
+    pub upload: Duration,
+}
+
+impl Phases {
+    fn add(&mut self, other: &Phases) {
+        self.resolve += other.resolve;
+        self.clone += other.clone;
+        self.walk += other.walk;
+        self.render += other.render;
+        self.upload += other.upload;
+    }
+}
+
+impl Summary {
+    pub fn failed(reason: String) -> Self {
+        Self {
+            failure: Some(reason),
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, other: &Summary) {
+        self.indexed += other.indexed;
+        self.failed += other.failed;
+        self.skipped += other.skipped;
+        self.duplicates += other.duplicates;
+        self.cached += other.cached;
+        self.chunks += other.chunks;
+        self.bytes += other.bytes;
+        self.phases.add(&other.phases);
+        for (lang, files) in &other.languages {
+            *self.languages.entry(lang.to_string()).or_default() += files;
+        }
+        for (skip, files) in &other.skips {
+            *self.skips.entry(*skip).or_default() += files;
+        }
+    }
+
+    // Documents already counted as indexed can fail later when a sink
+    // sends them in batches.
+    fn record_failures(&mut self, documents: usize) {
+        self.indexed = self.indexed.saturating_sub(documents);
+        self.failed += documents;
+    }
+}
+
+enum Outcome {
+    Indexed {
+        lang: String,
+        chunks: usize,
+        bytes: u64,
+        cached: bool,
+    },
+    Duplicate {
+        lang: String,
+        bytes: u64,
+    },
+    /// Holds the number of documents lost, which can include earlier files.
+    Failed {
+        documents: usize,
+        error: String,
+    },
+    Skipped(Skip),
+}
+
+/// Files whose documents are deleted with one request by an incremental run.
+const DELETE_BATCH_SIZE: usize = 500;
+
+/// Clones a repository, or reads a local one, and writes its files to a sink.
+pub struct Indexer<'a, S: DocumentSink = Sink> {
     pub repo_dir: PathBuf,
     pub git_url: String,
-    pub base_url: String,
-    pub with_delete_dir: bool,
-    pub git_host: String,
-    pub repo_name: String,
+    pub sink: &'a S,
+    pub config: &'a Config,
+    pub progress: Option<&'a Progress>,
+    /// Branch, tag or commit to index instead of the default branch.
+    pub git_ref: Option<String>,
+    /// The rest of the link into the tree `git_ref` comes from, part of the
+    /// ref when it names a branch or tag with a `/`.
+    pub tree_path: Option<String>,
+    pub repo: RepoRef,
+    /// Set for `file://` urls, which are not cloned.
+    pub local: Option<LocalRepo>,
+    /// Blobs indexed so far, files held by another repository are stored as
+    /// a reference.
+    pub blobs: Option<&'a BlobStore>,
+    /// Chunks rendered before, reused for files with the same blob.
+    pub cache: Option<&'a RenderCache>,
+    /// Commit indexed before, only the files changed since are indexed.
+    pub base: Option<String>,
+    /// The ref checked out, `git_ref` and the part of `tree_path` naming
+    /// it.
+    resolved_ref: OnceLock<String>,
 }
 
-impl Indexer {
-    pub fn new(repo_dir: PathBuf, git_url: &str, base_url: &str, with_delete_dir: bool) -> Self {
-        let git_host = utils::get_url_host(git_url).unwrap_or("github.com".to_string());
-        let repo_name = utils::get_repo_name(git_url);
+impl<'a, S: DocumentSink> Indexer<'a, S> {
+    /// Fails when `git_url` does not name a repository. A link into the tree
+    /// of a repository indexes the ref it points to.
+    pub fn new(git_url: &str, sink: &'a S, config: &'a Config) -> Result<Self, String> {
+        let repo = RepoRef::parse(git_url)?;
+        let local = LocalRepo::parse(git_url).transpose()?;
+        let (git_ref, tree_path) = match local {
+            Some(_) => (None, None),
+            None => (repo.git_ref.clone(), repo.tree_path.clone()),
+        };
 
-        Self {
-            repo_dir,
+        Ok(Self {
+            repo_dir: config.clone.folder.clone(),
             git_url: git_url.to_string(),
-            base_url: base_url.to_string(),
-            with_delete_dir,
-            git_host,
-            repo_name,
+            sink,
+            config,
+            progress: None,
+            git_ref,
+            tree_path,
+            repo,
+            local,
+            blobs: None,
+            cache: None,
+            base: None,
+            resolved_ref: OnceLock::new(),
+        })
+    }
+
+    /// Report the indexed files to `progress`.
+    pub fn with_progress(mut self, progress: &'a Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Store the files already indexed for another repository as a
+    /// reference to it.
+    pub fn with_blobs(mut self, blobs: &'a BlobStore) -> Self {
+        self.blobs = Some(blobs);
+        self
+    }
+
+    /// Reuse the chunks of `cache` for files rendered before, and store the
+    /// ones rendered now.
+    pub fn with_cache(mut self, cache: &'a RenderCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Index only the files changed since `commit`, the documents of the
+    /// other files are kept. The documents of the repository are deleted and
+    /// every file is indexed when the commit is not in the repository, the
+    /// sink does not keep documents across runs or the working tree is
+    /// indexed in place.
+    pub fn with_base(mut self, commit: &str) -> Self {
+        self.base = Some(commit.to_string());
+        self
+    }
+
+    /// Index `git_ref` of the repository, fetched again when the repository
+    /// is already cloned. Local repositories keep their working tree or
+    /// commit.
+    pub fn with_ref(mut self, git_ref: &str) -> Self {
+        if self.git_ref.as_deref() != Some(git_ref) {
+            self.tree_path = None;
         }
+        self.git_ref = Some(git_ref.to_string());
+        self
     }
 
-    pub async fn process(&self) {
-        let ssh_url = utils::get_git_ssh_url(&self.git_url);
-        let success = git::clone_repo(&self.repo_dir, &ssh_url, &self.repo_name);
+    /// Clone and index the repository.
+    pub async fn process(&self) -> Summary {
+        let source = match &self.local {
+            Some(local) => local.path.display().to_string(),
+            None => self.repo.ssh_url(),
+        };
+        let started = Instant::now();
+        let success = match &self.local {
+            Some(local) if local.in_place() => local.path.is_dir(),
+            Some(local) => local.checkout(&self.clone_dir()).await,
+            None => match git::clone_repo(&self.clone_dir(), &source).await {
+                Ok(()) => match &self.git_ref {
+                    Some(git_ref) => {
+                        let tree_path = self.tree_path.as_deref();
+                        let git_ref = git::resolve_ref(&self.clone_dir(), git_ref, tree_path).await;
+                        let success = git::checkout(&self.clone_dir(), &git_ref).await;
+                        let _ = self.resolved_ref.set(git_ref);
+                        success
+                    }
+                    None => true,
+                },
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    false
+                }
+            },
+        };
+        let clone = started.elapsed();
+        // A working tree indexed in place is neither cloned nor deleted.
+        let cloned = self.local.as_ref().is_none_or(|local| !local.in_place());
 
-        if success {
-            self.index_directory().await;
-            if self.with_delete_dir {
-                utils::delete_dir(&self.repo_dir.join(Path::new(&self.repo_name)));
+        let summary = if success {
+            let mut summary = self.index_directory().await;
+            summary.phases.clone = clone;
+            if self.config.clone.delete_dir && cloned {
+                utils::delete_dir(&self.clone_dir());
             }
+            summary
         } else {
-            print!("{}\n", format!("Failed to clone: {}", ssh_url));
-        }
+            tracing::error!("Failed to clone: {}", source);
+            let mut summary = Summary::failed(format!("Failed to clone: {}", source));
+            summary.phases.clone = clone;
+            summary
+        };
+        metrics::record_repo(&self.repo.host, &summary, cloned);
+        summary
     }
 
-    pub async fn index_directory(&self) {
-        println!("Start indexing on folder: {}", self.repo_dir.display());
+    pub async fn index_directory(&self) -> Summary {
+        let walk_dir_path = self.walk_dir();
+        tracing::info!("Start indexing on folder: {}", walk_dir_path.display());
 
-        let mut total = 0;
-        let git_repo = utils::get_git_repo_path(&self.git_url);
-        let username = git_repo.split("/").next().unwrap();
-        let branch = git::get_branch_name(&self.repo_dir);
+        let started = Instant::now();
+        let (mut total, mut unchanged) = (0, 0);
+        let mut summary = Summary::default();
+        let git_repo = self.repo.path();
+        let username = &self.repo.owner;
+        let git_ref = self.resolved_ref.get().or(self.git_ref.as_ref());
+        let branch = match (&self.local, git_ref.map(String::as_str)) {
+            (Some(local), _) => local.branch(&walk_dir_path).await,
+            // A commit, e.g. pushed to the default branch, is recorded under
+            // the default branch.
+            (None, Some(git_ref)) if git_ref == "HEAD" || git::is_commit(git_ref) => {
+                git::remote_head(&self.clone_dir())
+                    .await
+                    .unwrap_or_else(|| git::get_branch_name(&self.repo_dir))
+            }
+            (None, Some(git_ref)) => git::ref_name(git_ref).to_string(),
+            (None, None) => git::get_branch_name(&self.repo_dir),
+        };
 
-        let user_id = match &self.git_host[..] {
-            "gitlab.com" => String::from("0000"),
-            _ => match git::github::get_user_id(username).await {
-                Ok(user_id) => user_id,
-                Err(e) => {
-                    print!("{}", e);
-                    String::from("00000")
+        let resolving = Instant::now();
+        let user_id = match &self.repo.host[..] {
+            "github.com" => {
+                match git::github::get_user_id(username, &self.config.providers).await {
+                    Ok(user_id) => user_id,
+                    Err(e) => {
+                        tracing::warn!("Failed to get the id of '{}': {}", username, e);
+                        String::from("00000")
+                    }
                 }
-            },
+            }
+            _ => String::from("0000"),
         };
+        summary.phases.resolve = resolving.elapsed();
 
-        let repo_name = utils::get_repo_name(&self.git_url);
-        let walk_dir_path = self.repo_dir.join(repo_name);
-        let dirs = Walk::new(&walk_dir_path).into_iter().filter_map(|v| v.ok());
-        let root_path_len = self
-            .repo_dir
-            .to_str()
-            .unwrap()
-            .split("/")
-            .collect::<Vec<_>>()
-            .len();
+        summary.commit = git::get_commit(&walk_dir_path).await;
+        let changes = self
+            .changes(&walk_dir_path, summary.commit.as_deref())
+            .await;
+        let changed: Option<HashSet<&str>> = changes.as_ref().map(|changes| {
+            changes
+                .iter()
+                .filter(|change| !change.deleted)
+                .map(|change| change.path.as_str())
+                .collect()
+        });
+        match &changes {
+            Some(changes) => {
+                let removing = Instant::now();
+                let removed = self.remove_changed(&git_repo, changes).await;
+                summary.phases.upload += removing.elapsed();
+                if let Err(e) = removed {
+                    tracing::error!("Failed to delete the changed files: {}", e);
+                    summary.failure = Some(format!("Failed to delete the changed files: {}", e));
+                    return summary;
+                }
+            }
+            None => {
+                // Every file is indexed again, the documents of the files
+                // deleted since the base would stay.
+                if self.base.is_some() {
+                    if let Err(e) = self.remove_repo().await {
+                        tracing::error!("Failed to delete documents: {}", e);
+                        summary.failure = Some(format!("Failed to delete documents: {}", e));
+                        return summary;
+                    }
+                }
+                if let Some(blobs) = self.blobs {
+                    blobs.start_repo(&git_repo);
+                }
+            }
+        }
+        let dirs = Walk::new(&walk_dir_path).filter_map(|v| v.ok());
 
         for entry in dirs {
             if !entry.path().is_file() {
                 continue;
             }
+            if let Some(changed) = &changed {
+                let relative = entry.path().strip_prefix(&walk_dir_path).ok();
+                let path = relative.and_then(|path| path.to_str()).unwrap_or_default();
+                if !changed.contains(path) {
+                    unchanged += 1;
+                    continue;
+                }
+            }
 
-            print!("{}\n", format!("Indexing {}", entry.path().display()));
+            tracing::debug!("Indexing {}", entry.path().display());
             let meta = MetaIndexFile {
                 path: PathBuf::from(entry.path()),
                 git_repo: git_repo.to_string(),
                 user_id: user_id.to_string(),
                 branch: branch.to_string(),
-                base_url: self.base_url.to_string(),
-                git_host: self.git_host.to_string(),
-                root_path_len,
+                git_host: self.repo.host.to_string(),
+                root: walk_dir_path.clone(),
             };
-            self.process_file(meta).await;
+            let span = tracing::debug_span!("file", path = %meta.path.display());
+            match self
+                .process_file(meta, &mut summary.phases)
+                .instrument(span)
+                .await
+            {
+                Outcome::Indexed {
+                    lang,
+                    chunks,
+                    bytes,
+                    cached,
+                } => {
+                    summary.indexed += 1;
+                    summary.cached += cached as usize;
+                    summary.chunks += chunks;
+                    summary.bytes += bytes;
+                    *summary.languages.entry(lang).or_default() += 1;
+                    if let Some(progress) = self.progress {
+                        progress.file(bytes);
+                    }
+                }
+                Outcome::Duplicate { lang, bytes } => {
+                    summary.indexed += 1;
+                    summary.duplicates += 1;
+                    summary.chunks += 1;
+                    summary.bytes += bytes;
+                    *summary.languages.entry(lang).or_default() += 1;
+                    if let Some(progress) = self.progress {
+                        progress.file(bytes);
+                    }
+                }
+                Outcome::Failed { documents, error } => {
+                    summary.indexed += 1;
+                    summary.record_failures(documents);
+                    summary.errors.push(error);
+                }
+                Outcome::Skipped(skip) => {
+                    summary.skipped += 1;
+                    *summary.skips.entry(skip).or_default() += 1;
+                }
+            }
             total += 1;
         }
 
-        if total == 0 {
-            print!(
-                "{}\n",
-                format!("Folder '{}' not found!", walk_dir_path.display())
-            );
+        let flushing = Instant::now();
+        if let Err(e) = self.sink.flush(&git_repo).await {
+            tracing::error!("Failed to index '{}': {}", git_repo, e);
+            if let SinkError::Batch(batch) = &e {
+                self.discard(&git_repo, &batch.ids).await;
+            }
+            summary.record_failures(e.failed_documents());
+            summary.errors.push(e.to_string());
+        }
+        summary.phases.upload += flushing.elapsed();
+
+        let phases = summary.phases;
+        summary.phases.walk = started
+            .elapsed()
+            .saturating_sub(phases.resolve + phases.render + phases.upload);
+
+        if total + unchanged == 0 {
+            tracing::error!("Folder '{}' not found!", walk_dir_path.display());
+            summary.failure = Some(format!("Folder '{}' not found!", walk_dir_path.display()));
         } else {
-            print!(
-                "{}\n",
-                format!("Done indexing '{}' total {} files!", git_repo, total)
+            // Every file was walked, the blobs of changed or deleted files
+            // are no longer held by the repository.
+            match self.blobs.map(|blobs| blobs.finish_repo(&git_repo)) {
+                Some(Ok(handovers)) => self.hand_over(handovers).await,
+                Some(Err(e)) => tracing::warn!("Failed to record the blobs: {}", e),
+                None => {}
+            }
+            tracing::info!(
+                "Done indexing '{}' total {} files, {} failed, {} unchanged!",
+                git_repo,
+                total,
+                summary.failed,
+                unchanged
             );
         }
+
+        summary
+    }
+
+    // The files changed since `base` when the repository can be updated
+    // from them, `None` when every file is indexed.
+    async fn changes(&self, dir: &Path, head: Option<&str>) -> Option<Vec<git::Change>> {
+        let base = self.base.as_deref()?;
+        if self.local.as_ref().is_some_and(|local| local.in_place()) {
+            return None;
+        }
+        if !self.sink.incremental() {
+            tracing::debug!("The sink does not keep documents, indexing every file");
+            return None;
+        }
+        if head == Some(base) {
+            return Some(Vec::new());
+        }
+        match git::diff(dir, base).await {
+            Some(changes) => {
+                tracing::info!("{} files changed since {}", changes.len(), base);
+                Some(changes)
+            }
+            None => {
+                tracing::warn!("Commit {} not found, indexing every file", base);
+                None
+            }
+        }
+    }
+
+    /// Delete the documents of the repository, the blobs they held move to
+    /// the documents of other repositories referencing them.
+    pub async fn remove_repo(&self) -> Result<(), SinkError> {
+        let repo = self.repo.path();
+        self.sink.delete_repo(&repo).await?;
+        match self.blobs.map(|blobs| blobs.release_repo(&repo)) {
+            Some(Ok(handovers)) => self.hand_over(handovers).await,
+            Some(Err(e)) => tracing::warn!("Failed to record the blobs: {}", e),
+            None => {}
+        }
+        Ok(())
+    }
+
+    // Delete the documents and forget the blobs of the changed files, the
+    // ones still in the tree are indexed again.
+    async fn remove_changed(&self, repo: &str, changes: &[git::Change]) -> Result<(), SinkError> {
+        let ids: Vec<String> = changes
+            .iter()
+            .map(|change| format!("{}/{}/{}", repo, self.repo.name, change.path))
+            .collect();
+        for batch in ids.chunks(DELETE_BATCH_SIZE) {
+            self.sink.delete_files(repo, batch).await?;
+        }
+        if let Some(blobs) = self.blobs {
+            let mut handovers = Vec::new();
+            for (change, id) in changes.iter().zip(&ids) {
+                let Some(blob) = &change.old_blob else {
+                    continue;
+                };
+                match blobs.release(blob, id) {
+                    Ok(handover) => handovers.extend(handover),
+                    Err(e) => tracing::warn!("Failed to record the blob: {}", e),
+                }
+            }
+            self.hand_over(handovers).await;
+        }
+        Ok(())
     }
 
-    async fn process_file(&self, meta: MetaIndexFile) {
-        match parser::read_file(&meta.path) {
+    async fn process_file(&self, meta: MetaIndexFile, phases: &mut Phases) -> Outcome {
+        let read = self
+            .filter(&meta.path)
+            .and_then(|_| parser::read_file(&meta.path));
+        match read {
             Ok((input, lang)) => {
-                let html = parser::render_html(input, lang);
-                let paths = meta.path.to_str().unwrap().split("/").collect::<Vec<_>>();
-                let file_path = paths[meta.root_path_len..paths.len()].to_vec().join("/");
-                let id = [
-                    meta.git_repo.to_string(),
-                    paths[meta.root_path_len..paths.len()].to_vec().join("/"),
-                ]
-                .join("/");
-                let data = GitFile {
+                let bytes = input.iter().map(|c| c.len_utf8() as u64).sum();
+                // Paths start with the repository name, wherever it was walked from.
+                let relative = meta.path.strip_prefix(&meta.root).unwrap_or(&meta.path);
+                let mut paths = vec![self.repo.name.as_str()];
+                paths.extend(relative.iter().filter_map(|part| part.to_str()));
+                let file_path = paths.join("/");
+                let id = [meta.git_repo.to_string(), file_path.to_string()].join("/");
+                // Keys the render cache, stored only when files are deduplicated.
+                let blob = git::blob_sha(&meta.path).unwrap_or_default();
+                let mut data = GitFile {
                     id: id.to_owned(),
-                    file_id: format!(
-                        "{}/{}/{}",
-                        &meta.git_host,
-                        &meta.git_repo,
-                        file_path.to_string()
-                    ),
+                    file_id: format!("{}/{}/{}", &meta.git_host, &meta.git_repo, file_path),
                     owner_id: meta.user_id.to_string(),
-                    path: paths[meta.root_path_len - 2..paths.len() - 1]
-                        .to_vec()
-                        .join("/"),
+                    path: paths[..paths.len() - 1].join("/"),
                     repo: meta.git_repo.to_string(),
                     branch: meta.branch.to_owned(),
                     lang: lang.to_string(),
                     content: Vec::new(),
+                    blob: match self.blobs {
+                        Some(_) => blob.to_string(),
+                        None => String::new(),
+                    },
+                    duplicate_of: String::new(),
                 };
-                self.store(data, &html, &meta.base_url).await;
+
+                if let Some(original) = self.original(&data) {
+                    tracing::debug!("Same as '{}'", original.file);
+                    data.duplicate_of = original.file;
+                    let lines = Lines {
+                        start: 1,
+                        end: input.iter().filter(|c| **c == '\n').count() + 1,
+                    };
+                    let uploading = Instant::now();
+                    let stored = self.sink.insert(&data, lines).await;
+                    phases.upload += uploading.elapsed();
+                    return match stored {
+                        Ok(()) => {
+                            if let Some(Err(e)) = self.blobs.map(|blobs| blobs.refer(&data)) {
+                                tracing::warn!("Failed to record the blob: {}", e);
+                            }
+                            Outcome::Duplicate {
+                                lang: lang.to_string(),
+                                bytes,
+                            }
+                        }
+                        Err(e) => {
+                            tracing::error!("Failed to index {}: {}", meta.path.display(), e);
+                            // The reference of an earlier run is gone too.
+                            if let Some(Err(e)) =
+                                self.blobs.map(|blobs| blobs.release(&data.blob, &id))
+                            {
+                                tracing::warn!("Failed to record the blob: {}", e);
+                            }
+                            Outcome::Failed {
+                                documents: e.failed_documents(),
+                                error: format!("{}: {}", file_path, e),
+                            }
+                        }
+                    };
+                }
+
+                let rendering = Instant::now();
+                let (chunks, cached) = self.render(&blob, input, lang);
+                phases.render += rendering.elapsed();
+                let uploading = Instant::now();
+                let stored = self.store(data, &chunks).await;
+                phases.upload += uploading.elapsed();
+                match stored {
+                    Ok(()) => Outcome::Indexed {
+                        lang: lang.to_string(),
+                        chunks: chunks.len(),
+                        bytes,
+                        cached,
+                    },
+                    Err(e) => {
+                        tracing::error!("Failed to index {}: {}", meta.path.display(), e);
+                        let lost = match &e {
+                            SinkError::Batch(batch) => batch.ids.clone(),
+                            _ => vec![id.to_string()],
+                        };
+                        self.discard(&meta.git_repo, &lost).await;
+                        // Copies indexed later hold the content instead.
+                        match self.blobs.map(|blobs| blobs.release(&blob, &id)) {
+                            Some(Ok(handover)) => {
+                                self.hand_over(handover.into_iter().collect()).await
+                            }
+                            Some(Err(e)) => tracing::warn!("Failed to record the blob: {}", e),
+                            None => {}
+                        }
+                        Outcome::Failed {
+                            documents: e.failed_documents(),
+                            error: format!("{}: {}", file_path, e),
+                        }
+                    }
+                }
             }
-            Err(msg) => {
-                print!("{}\n", msg);
+            Err(skip) => {
+                tracing::debug!("Skip '{}': {}", meta.path.display(), skip);
+                Outcome::Skipped(skip)
             }
         }
     }
 
-    async fn store(&self, mut data: GitFile, html: &str, base_url: &str) {
-        let document = Document::from(html);
-        let table = document.find(Class("highlight-table"));
-        if let Some(el) = table.last() {
-            let mut update = false;
-            let mut index = 0;
-            let mut max_index = 3;
-            let max_chars = 2000;
-            let mut child: String = String::new();
-            for td in el.find(Name("tr")) {
-                index += 1;
-                child.push_str(&td.html());
-                child.push('\n');
-                if index == max_index && child.len() < max_chars {
-                    max_index += 1;
-                }
-                if index >= max_index {
-                    index = 0;
-                    max_index = 3;
-                    data.content = vec![];
-                    data.content.push(child.to_string());
-                    child = String::new();
-                    self.create_or_update(&mut update, &data, base_url).await;
-                }
+    // The document of another repository holding the blob of `data`, the
+    // blob is recorded as held by `data` otherwise.
+    fn original(&self, data: &GitFile) -> Option<BlobEntry> {
+        let blobs = self.blobs?;
+        if data.blob.is_empty() {
+            return None;
+        }
+        match blobs.claim(&data.blob, &data.id, &data.repo) {
+            Ok(original) => original,
+            Err(e) => {
+                tracing::warn!("Failed to record the blob: {}", e);
+                None
             }
+        }
+    }
 
-            // If there any left content that less than `max_index` line then store it to DB!
-            if index != 0 {
-                data.content = vec![];
-                data.content.push(child.to_string());
-                self.create_or_update(&mut update, &data, base_url).await;
+    // Store the content of the blobs handed over on their new holder and
+    // point the other references to it.
+    async fn hand_over(&self, handovers: Vec<Handover>) {
+        for handover in handovers {
+            let holder = &handover.holder;
+            let Some(chunks) = self.blob_chunks(holder).await else {
+                tracing::warn!(
+                    "Content of blob {} not found, '{}' holds it once indexed in full again",
+                    holder.blob,
+                    holder.repo
+                );
+                continue;
+            };
+            tracing::debug!("Moving blob {} to '{}'", holder.blob, holder.id);
+            if let Err(e) = self.restore(&handover, &chunks).await {
+                tracing::warn!("Failed to move blob {}: {}", holder.blob, e);
             }
         }
     }
 
-    async fn create_or_update(&self, update: &mut bool, data: &GitFile, base_url: &str) {
-        if *update == false {
-            match solr::client::insert(&data, base_url).await {
-                Ok(_) => {}
-                Err(e) => print!("{}\n", e.to_string()),
+    async fn restore(&self, handover: &Handover, chunks: &[Chunk]) -> Result<(), SinkError> {
+        let holder = &handover.holder;
+        self.sink
+            .delete_files(&holder.repo, &[holder.id.to_string()])
+            .await?;
+        self.store(holder.clone(), chunks).await?;
+        self.sink.flush(&holder.repo).await?;
+        let lines = Lines {
+            start: 1,
+            end: chunks.last().map_or(1, |chunk| chunk.lines.end),
+        };
+        for reference in &handover.references {
+            self.sink
+                .delete_files(&reference.repo, &[reference.id.to_string()])
+                .await?;
+            self.sink.insert(reference, lines).await?;
+            self.sink.flush(&reference.repo).await?;
+        }
+        Ok(())
+    }
+
+    // The chunks of the blob of `document`, cached or rendered from the
+    // clone it was read from or the clone of `document`.
+    async fn blob_chunks(&self, document: &GitFile) -> Option<Vec<Chunk>> {
+        let (blob, lang) = (&document.blob, &document.lang);
+        if let Some(chunks) = self
+            .cache
+            .and_then(|cache| cache.get(blob, lang, &self.config.chunk))
+        {
+            return Some(chunks);
+        }
+        let host = document.file_id.split('/').next().unwrap_or_default();
+        for dir in [
+            self.walk_dir(),
+            self.repo_dir.join(host).join(&document.repo),
+        ] {
+            if let Some(content) = git::cat_blob(&dir, blob).await {
+                let input = String::from_utf8_lossy(&content).chars().collect();
+                return Some(self.render(blob, input, lang).0);
             }
-            *update = true;
-        } else {
-            match solr::client::update(&data, base_url).await {
-                Ok(_) => {}
-                Err(e) => print!("{}\n", e.to_string()),
+        }
+        None
+    }
+
+    // Remove what was stored of the files a failed write lost, a file is
+    // never left with only some of its chunks.
+    async fn discard(&self, repo: &str, ids: &[String]) {
+        if let Err(e) = self.sink.delete_files(repo, ids).await {
+            tracing::warn!("Failed to delete the partly stored files: {}", e);
+        }
+    }
+
+    // Highlight and chunk the file, or take its chunks from the cache.
+    // Returns whether they were cached.
+    fn render(&self, blob: &str, input: Vec<char>, lang: &str) -> (Vec<Chunk>, bool) {
+        let options = &self.config.chunk;
+        let cache = self.cache.filter(|_| !blob.is_empty());
+        if let Some(chunks) = cache.and_then(|cache| cache.get(blob, lang, options)) {
+            return (chunks, true);
+        }
+        let html = parser::render_html(input, lang);
+        let chunks = chunk::split(&html, options);
+        if let Some(cache) = cache {
+            if let Err(e) = cache.put(blob, lang, options, &chunks) {
+                tracing::warn!("Failed to cache the chunks: {}", e);
+            }
+        }
+        (chunks, false)
+    }
+
+    // The working tree of an in-place repository, the clone otherwise.
+    fn walk_dir(&self) -> PathBuf {
+        match &self.local {
+            Some(local) if local.in_place() => local.path.clone(),
+            _ => self.clone_dir(),
+        }
+    }
+
+    // `<folder>/<host>/<owner>/<name>`, forks and repositories of other
+    // hosts with the same name never share a clone.
+    fn clone_dir(&self) -> PathBuf {
+        self.repo_dir.join(self.repo.key())
+    }
+
+    fn filter(&self, path: &Path) -> Result<(), Skip> {
+        let filters = &self.config.filters;
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            if filters.ignore_files.iter().any(|ignored| ignored == name) {
+                return Err(Skip::Ignored);
+            }
+        }
+        if let Some(max_size) = filters.max_file_size {
+            let size = path.metadata().map(|m| m.len()).unwrap_or_default();
+            if size > max_size {
+                return Err(Skip::TooLarge);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the chunks of the file.
+    async fn store(&self, mut data: GitFile, chunks: &[Chunk]) -> Result<(), SinkError> {
+        for (index, chunk) in chunks.iter().enumerate() {
+            data.content = vec![chunk.html.to_string()];
+            if index == 0 {
+                self.sink.insert(&data, chunk.lines).await?;
+            } else {
+                self.sink.update(&data, chunk.lines).await?;
             }
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::writer::ExportWriter;
+    use std::future::Future;
+
+    // Yields before every write, repositories indexed at once interleave.
+    struct Interleaved(Sink);
+
+    impl DocumentSink for Interleaved {
+        async fn insert(&self, data: &GitFile, lines: Lines) -> Result<(), SinkError> {
+            tokio::task::yield_now().await;
+            self.0.insert(data, lines).await
+        }
+
+        async fn update(&self, data: &GitFile, lines: Lines) -> Result<(), SinkError> {
+            tokio::task::yield_now().await;
+            self.0.update(data, lines).await
+        }
+
+        fn flush(&self, repo: &str) -> impl Future<Output = Result<(), SinkError>> + Send {
+            self.0.flush(repo)
+        }
+
+        fn delete_repo(&self, repo: &str) -> impl Future<Output = Result<(), SinkError>> + Send {
+            self.0.delete_repo(repo)
+        }
+
+        fn delete_files(
+            &self,
+            repo: &str,
+            ids: &[String],
+        ) -> impl Future<Output = Result<(), SinkError>> + Send {
+            self.0.delete_files(repo, ids)
+        }
+
+        fn count_repo(&self, repo: &str) -> impl Future<Output = Result<u64, SinkError>> + Send {
+            self.0.count_repo(repo)
+        }
+
+        fn incremental(&self) -> bool {
+            self.0.incremental()
+        }
+    }
+
+    // Records the files inserted and deleted.
+    #[derive(Default)]
+    struct Recorder {
+        inserted: std::sync::Mutex<Vec<String>>,
+        deleted: std::sync::Mutex<Vec<String>>,
+        /// The last document inserted per id.
+        documents: std::sync::Mutex<BTreeMap<String, GitFile>>,
+    }
+
+    impl DocumentSink for Recorder {
+        async fn insert(&self, data: &GitFile, _: Lines) -> Result<(), SinkError> {
+            self.inserted.lock().unwrap().push(data.id.to_string());
+            let mut documents = self.documents.lock().unwrap();
+            documents.insert(data.id.to_string(), data.clone());
+            Ok(())
+        }
+
+        async fn update(&self, _: &GitFile, _: Lines) -> Result<(), SinkError> {
+            Ok(())
+        }
+
+        async fn flush(&self, _: &str) -> Result<(), SinkError> {
+            Ok(())
+        }
+
+        async fn delete_repo(&self, _: &str) -> Result<(), SinkError> {
+            Ok(())
+        }
+
+        async fn delete_files(&self, _: &str, ids: &[String]) -> Result<(), SinkError> {
+            self.deleted.lock().unwrap().extend_from_slice(ids);
+            Ok(())
+        }
+
+        async fn count_repo(&self, _: &str) -> Result<u64, SinkError> {
+            Ok(0)
+        }
+    }
+
+    impl Recorder {
+        fn take(&self) -> (Vec<String>, Vec<String>) {
+            let mut inserted = std::mem::take(&mut *self.inserted.lock().unwrap());
+            let mut deleted = std::mem::take(&mut *self.deleted.lock().unwrap());
+            inserted.sort();
+            deleted.sort();
+            (inserted, deleted)
+        }
+    }
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=hli", "-c", "user.email=hli@localhost"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    fn repo(root: &Path, name: &str, files: usize) -> String {
+        let dir = root.join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        for i in 0..files {
+            let source = format!("fn f{}() {{\n    println!(\"{}\");\n}}\n", i, i);
+            std::fs::write(dir.join(format!("f{}.rs", i)), source).unwrap();
+        }
+        format!("file://{}?owner=acme", dir.display())
+    }
+
+    #[tokio::test]
+    async fn indexes_repositories_at_once_into_ndjson() {
+        let root = std::env::temp_dir().join(format!("hli-indexer-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let a = repo(&root, "a", 2);
+        let b = repo(&root, "b", 6);
+        let writer = ExportWriter::new(root.join("export"), false).unwrap();
+        let sink = Interleaved(Sink::Ndjson(writer));
+        let config = Config::default();
+
+        let a = Indexer::new(&a, &sink, &config).unwrap();
+        let b = Indexer::new(&b, &sink, &config).unwrap();
+        let (a, b) = futures::join!(a.process(), b.process());
+        assert_eq!((a.indexed, a.failed), (2, 0));
+        assert_eq!((b.indexed, b.failed), (6, 0));
+
+        // `a` is done first, flushing it leaves the file of `b` open.
+        assert_eq!(sink.count_repo("acme/a").await.unwrap(), 2);
+        assert_eq!(sink.count_repo("acme/b").await.unwrap(), 6);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn moves_the_content_of_a_deleted_original_to_a_fork() {
+        let root = std::env::temp_dir().join(format!("hli-handover-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let urls: Vec<String> = ["up", "fork", "mirror"]
+            .iter()
+            .map(|name| {
+                let url = repo(&root, name, 1);
+                let dir = root.join(name);
+                git(&dir, &["init", "--quiet"]);
+                git(&dir, &["add", "."]);
+                git(&dir, &["commit", "--quiet", "-m", "first"]);
+                url
+            })
+            .collect();
+        let blobs = BlobStore::open(&root.join("blobs.json")).unwrap();
+        let config = Config::default();
+        let sink = Recorder::default();
+        for url in &urls {
+            let indexer = Indexer::new(url, &sink, &config).unwrap();
+            let summary = indexer.with_blobs(&blobs).process().await;
+            assert_eq!((summary.indexed, summary.failed), (1, 0));
+        }
+        let fork = sink.documents.lock().unwrap()["acme/fork/fork/f0.rs"].clone();
+        assert_eq!(fork.duplicate_of, "acme/up/up/f0.rs");
+        assert!(fork.content.is_empty());
+        sink.take();
+
+        let up = Indexer::new(&urls[0], &sink, &config).unwrap();
+        up.with_blobs(&blobs).remove_repo().await.unwrap();
+        let (inserted, deleted) = sink.take();
+        assert_eq!(
+            inserted,
+            ["acme/fork/fork/f0.rs", "acme/mirror/mirror/f0.rs"]
+        );
+        assert_eq!(deleted, inserted);
+        let documents = sink.documents.lock().unwrap();
+        let fork = &documents["acme/fork/fork/f0.rs"];
+        assert_eq!(fork.duplicate_of, "");
+        assert!(fork.content[0].contains("println"));
+        let mirror = &documents["acme/mirror/mirror/f0.rs"];
+        assert_eq!(mirror.duplicate_of, "acme/fork/fork/f0.rs");
+        assert!(mirror.content.is_empty());
+        drop(documents);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn indexes_the_files_changed_since_the_base() {
+        let root = std::env::temp_dir().join(format!("hli-incremental-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("app");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.rs"), "fn a() {\n    println!(\"a\");\n}\n").unwrap();
+        std::fs::write(dir.join("b.rs"), "fn b() {\n    println!(\"b\");\n}\n").unwrap();
+        std::fs::write(dir.join("d.rs"), "fn d() {\n    println!(\"d\");\n}\n").unwrap();
+        git(&dir, &["init", "--quiet"]);
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "--quiet", "-m", "first"]);
+        let first = git(&dir, &["rev-parse", "HEAD"]);
+        std::fs::write(dir.join("a.rs"), "fn a() {\n    println!(\"A\");\n}\n").unwrap();
+        std::fs::remove_file(dir.join("b.rs")).unwrap();
+        std::fs::write(dir.join("c.rs"), "fn c() {\n    println!(\"c\");\n}\n").unwrap();
+        git(&dir, &["add", "--all"]);
+        git(&dir, &["commit", "--quiet", "-m", "second"]);
+        let second = git(&dir, &["rev-parse", "HEAD"]);
+
+        let mut config = Config::default();
+        config.clone.folder = root.join("repos");
+        let url = |commit: &str| format!("file://{}?owner=acme&commit={}", dir.display(), commit);
+        let id = |name: &str| format!("acme/app/app/{}", name);
+        let sink = Recorder::default();
+
+        let indexer = Indexer::new(&url(&first), &sink, &config).unwrap();
+        let summary = indexer.process().await;
+        assert_eq!(summary.commit.as_deref(), Some(&first[..]));
+        let (inserted, deleted) = sink.take();
+        assert_eq!(inserted, [id("a.rs"), id("b.rs"), id("d.rs")]);
+        assert!(deleted.is_empty());
+
+        let indexer = Indexer::new(&url(&second), &sink, &config).unwrap();
+        let summary = indexer.with_base(&first).process().await;
+        assert_eq!((summary.indexed, summary.failed), (2, 0));
+        assert!(summary.failure.is_none());
+        let (inserted, deleted) = sink.take();
+        assert_eq!(inserted, [id("a.rs"), id("c.rs")]);
+        assert_eq!(deleted, [id("a.rs"), id("b.rs"), id("c.rs")]);
+
+        // Nothing changed since the indexed commit.
+        let indexer = Indexer::new(&url(&second), &sink, &config).unwrap();
+        let summary = indexer.with_base(&second).process().await;
+        assert!(summary.failure.is_none());
+        assert_eq!(sink.take(), (Vec::new(), Vec::new()));
+        std::fs::remove_dir_all(&root).unwrap();
     }
 }
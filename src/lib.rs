@@ -0,0 +1,47 @@
+//! Turn git repositories into syntax highlighted, chunked documents for
+//! [heline.dev](https://heline.dev).
+//!
+//! [`indexer::Indexer`] clones a repository and writes every file to a
+//! [`sink::DocumentSink`], either one of the built-in backends of
+//! [`sink::Sink`] or your own. The steps are usable on their own:
+//! [`parser::detect_language`], [`parser::render_html`] and
+//! [`chunk::split`].
+//!
+//! ```no_run
+//! use heline_indexer::config::Config;
+//! use heline_indexer::indexer::Indexer;
+//! use heline_indexer::sink::Sink;
+//!
+//! # async fn run() -> Result<(), String> {
+//! let config = Config::load(None)?;
+//! let location = config.backend.location();
+//! let sink = Sink::new(&config.backend.options(&location, &config.backend.core))?;
+//! let summary = Indexer::new("https://github.com/ahmadrosid/hl", &sink, &config)?
+//!     .process()
+//!     .await;
+//! println!("{} files indexed", summary.indexed);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod blobs;
+pub mod cache;
+pub mod chunk;
+pub mod config;
+pub mod export;
+pub mod git;
+pub mod indexer;
+pub mod local;
+pub mod manifest;
+pub mod meilisearch;
+pub mod metrics;
+pub mod opensearch;
+pub mod parser;
+pub mod progress;
+pub mod report;
+pub mod schedule;
+pub mod sink;
+pub mod solr;
+pub mod state;
+pub mod typesense;
+pub mod utils;
@@ -0,0 +1,240 @@
+use crate::sink::Lines;
+use crate::solr::client::GitFile;
+use select::document::Document;
+use std::path::Path;
+use std::sync::Mutex;
+use tantivy::collector::Count;
+use tantivy::directory::MmapDirectory;
+use tantivy::query::TermQuery;
+use tantivy::schema::{Field, IndexRecordOption, NumericOptions, Schema, STORED, STRING, TEXT};
+use tantivy::{doc, Index, IndexReader, IndexWriter, TantivyDocument, TantivyError, Term};
+
+const WRITER_HEAP_SIZE: usize = 50_000_000;
+
+pub struct Fields {
+    pub id: Field,
+    pub file_id: Field,
+    pub owner_id: Field,
+    pub path: Field,
+    pub repo: Field,
+    pub branch: Field,
+    pub lang: Field,
+    pub start_line: Field,
+    pub end_line: Field,
+    pub blob: Field,
+    /// Id of the file holding the content of a duplicate.
+    pub duplicate_of: Field,
+    /// Rendered html of the chunk, stored only.
+    pub content: Field,
+    /// Plain text of the chunk, indexed only.
+    pub text: Field,
+}
+
+fn schema() -> Schema {
+    let mut builder = Schema::builder();
+    for name in [
+        "id",
+        "file_id",
+        "owner_id",
+        "path",
+        "repo",
+        "branch",
+        "lang",
+        "blob",
+        "duplicate_of",
+    ] {
+        builder.add_text_field(name, STRING | STORED);
+    }
+    let lines = NumericOptions::default().set_stored().set_indexed();
+    builder.add_u64_field("start_line", lines.clone());
+    builder.add_u64_field("end_line", lines);
+    builder.add_text_field("content", STORED);
+    builder.add_text_field("text", TEXT);
+    builder.build()
+}
+
+pub fn open(dir: &Path) -> Result<(Index, Fields), TantivyError> {
+    if !dir.exists() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let directory = MmapDirectory::open(dir)?;
+    let index = Index::open_or_create(directory, schema())?;
+    let schema = index.schema();
+    let fields = Fields {
+        id: schema.get_field("id")?,
+        file_id: schema.get_field("file_id")?,
+        owner_id: schema.get_field("owner_id")?,
+        path: schema.get_field("path")?,
+        repo: schema.get_field("repo")?,
+        branch: schema.get_field("branch")?,
+        lang: schema.get_field("lang")?,
+        start_line: schema.get_field("start_line")?,
+        end_line: schema.get_field("end_line")?,
+        blob: schema.get_field("blob")?,
+        duplicate_of: schema.get_field("duplicate_of")?,
+        content: schema.get_field("content")?,
+        text: schema.get_field("text")?,
+    };
+    Ok((index, fields))
+}
+
+/// On-disk Tantivy index holding one document per chunk.
+pub struct LocalIndex {
+    fields: Fields,
+    writer: Mutex<IndexWriter>,
+    reader: IndexReader,
+}
+
+impl LocalIndex {
+    pub fn open(dir: &Path) -> Result<Self, TantivyError> {
+        let (index, fields) = open(dir)?;
+        let writer = index.writer(WRITER_HEAP_SIZE)?;
+        let reader = index.reader()?;
+        Ok(Self {
+            fields,
+            writer: Mutex::new(writer),
+            reader,
+        })
+    }
+
+    /// Replace the chunks of a previously indexed file with the first chunk.
+    pub fn insert(&self, data: &GitFile, lines: Lines) -> Result<(), TantivyError> {
+        let writer = self.writer.lock().unwrap();
+        writer.delete_term(Term::from_field_text(self.fields.id, &data.id));
+        writer.add_document(self.document(data, lines))?;
+        Ok(())
+    }
+
+    pub fn update(&self, data: &GitFile, lines: Lines) -> Result<(), TantivyError> {
+        let writer = self.writer.lock().unwrap();
+        writer.add_document(self.document(data, lines))?;
+        Ok(())
+    }
+
+    pub fn delete_repo(&self, repo: &str) -> Result<(), TantivyError> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.delete_term(Term::from_field_text(self.fields.repo, repo));
+        writer.commit()?;
+        self.reader.reload()
+    }
+
+    /// Remove the chunks of the files `ids`, applied on the next commit.
+    pub fn delete_files(&self, ids: &[String]) {
+        let writer = self.writer.lock().unwrap();
+        for id in ids {
+            writer.delete_term(Term::from_field_text(self.fields.id, id));
+        }
+    }
+
+    /// Number of chunks indexed for `repo`.
+    pub fn count_repo(&self, repo: &str) -> Result<u64, TantivyError> {
+        let searcher = self.reader.searcher();
+        let term = Term::from_field_text(self.fields.repo, repo);
+        let query = TermQuery::new(term, IndexRecordOption::Basic);
+        Ok(searcher.search(&query, &Count)? as u64)
+    }
+
+    /// Write the pending changes, `count_repo` sees them right away.
+    pub fn commit(&self) -> Result<(), TantivyError> {
+        self.writer.lock().unwrap().commit()?;
+        self.reader.reload()
+    }
+
+    fn document(&self, data: &GitFile, lines: Lines) -> TantivyDocument {
+        let html = data.content.join("\n");
+        let text = Document::from(&html[..])
+            .nth(0)
+            .map(|node| node.text())
+            .unwrap_or_default();
+        let fields = &self.fields;
+        doc!(
+            fields.id => data.id.as_str(),
+            fields.file_id => data.file_id.as_str(),
+            fields.owner_id => data.owner_id.as_str(),
+            fields.path => data.path.as_str(),
+            fields.repo => data.repo.as_str(),
+            fields.branch => data.branch.as_str(),
+            fields.lang => data.lang.as_str(),
+            fields.start_line => lines.start as u64,
+            fields.end_line => lines.end as u64,
+            fields.blob => data.blob.as_str(),
+            fields.duplicate_of => data.duplicate_of.as_str(),
+            fields.content => html,
+            fields.text => text,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(repo: &str, path: &str) -> GitFile {
+        GitFile {
+            id: format!("{}/{}", repo, path),
+            file_id: format!("{}/{}", repo, path),
+            owner_id: "1".to_string(),
+            path: path.to_string(),
+            repo: repo.to_string(),
+            branch: "main".to_string(),
+            lang: "Rust".to_string(),
+            content: vec!["<tr><td>fn main() {}</td></tr>".to_string()],
+            blob: String::new(),
+            duplicate_of: String::new(),
+        }
+    }
+
+    fn lines(start: usize, end: usize) -> Lines {
+        Lines { start, end }
+    }
+
+    #[test]
+    fn replaces_the_chunks_of_a_file_indexed_again() {
+        let dir = std::env::temp_dir().join(format!("hli-tantivy-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let index = LocalIndex::open(&dir).unwrap();
+        let main = file("acme/app", "src/main.rs");
+        index.insert(&main, lines(1, 3)).unwrap();
+        index.update(&main, lines(4, 6)).unwrap();
+        index.commit().unwrap();
+        assert_eq!(index.count_repo("acme/app").unwrap(), 2);
+
+        index.insert(&main, lines(1, 5)).unwrap();
+        index.commit().unwrap();
+        assert_eq!(index.count_repo("acme/app").unwrap(), 1);
+        drop(index);
+
+        // The chunks are on disk for the next run.
+        let index = LocalIndex::open(&dir).unwrap();
+        assert_eq!(index.count_repo("acme/app").unwrap(), 1);
+        drop(index);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deletes_files_and_repositories() {
+        let dir = std::env::temp_dir().join(format!("hli-tantivy-delete-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let index = LocalIndex::open(&dir).unwrap();
+        for (repo, path) in [
+            ("acme/app", "a.rs"),
+            ("acme/app", "b.rs"),
+            ("acme/lib", "a.rs"),
+        ] {
+            index.insert(&file(repo, path), lines(1, 3)).unwrap();
+        }
+        index.commit().unwrap();
+
+        index.delete_files(&[file("acme/app", "a.rs").id]);
+        // Applied on the next commit.
+        assert_eq!(index.count_repo("acme/app").unwrap(), 2);
+        index.commit().unwrap();
+        assert_eq!(index.count_repo("acme/app").unwrap(), 1);
+
+        index.delete_repo("acme/app").unwrap();
+        assert_eq!(index.count_repo("acme/app").unwrap(), 0);
+        assert_eq!(index.count_repo("acme/lib").unwrap(), 1);
+        drop(index);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use crate::local::index;
+use std::path::Path;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::{QueryParser, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Value};
+use tantivy::{TantivyDocument, Term};
+
+pub struct Hit {
+    pub score: f32,
+    pub file_id: String,
+    pub lang: String,
+    pub start_line: u64,
+    pub end_line: u64,
+    /// Files of other repositories stored as a reference to this one.
+    pub copies: usize,
+}
+
+/// Run `query` against the chunk text, field filters like `lang:Rust` or
+/// `repo:owner/name` are supported by the query syntax.
+pub fn search(dir: &Path, query: &str, limit: usize) -> Result<Vec<Hit>, String> {
+    if !dir.exists() {
+        return Err(format!("Index '{}' not found!", dir.display()));
+    }
+    let (index, fields) = index::open(dir).map_err(|e| e.to_string())?;
+    let reader = index.reader().map_err(|e| e.to_string())?;
+    let searcher = reader.searcher();
+
+    let parser = QueryParser::for_index(&index, vec![fields.text]);
+    let query = parser.parse_query(query).map_err(|e| e.to_string())?;
+    let top_docs = searcher
+        .search(&query, &TopDocs::with_limit(limit))
+        .map_err(|e| e.to_string())?;
+
+    let mut hits = Vec::new();
+    for (score, address) in top_docs {
+        let doc: TantivyDocument = searcher.doc(address).map_err(|e| e.to_string())?;
+        let term = Term::from_field_text(fields.duplicate_of, &text(&doc, fields.id));
+        let copies = searcher
+            .search(&TermQuery::new(term, IndexRecordOption::Basic), &Count)
+            .map_err(|e| e.to_string())?;
+        hits.push(Hit {
+            score,
+            file_id: text(&doc, fields.file_id),
+            lang: text(&doc, fields.lang),
+            start_line: number(&doc, fields.start_line),
+            end_line: number(&doc, fields.end_line),
+            copies,
+        });
+    }
+    Ok(hits)
+}
+
+fn text(doc: &TantivyDocument, field: Field) -> String {
+    doc.get_first(field)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn number(doc: &TantivyDocument, field: Field) -> u64 {
+    doc.get_first(field)
+        .and_then(|v| v.as_u64())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::index::LocalIndex;
+    use crate::sink::Lines;
+    use crate::solr::client::GitFile;
+
+    fn file(repo: &str, lang: &str, html: &str) -> GitFile {
+        GitFile {
+            id: format!("{}/main", repo),
+            file_id: format!("{}/main", repo),
+            owner_id: "1".to_string(),
+            path: "main".to_string(),
+            repo: repo.to_string(),
+            branch: "main".to_string(),
+            lang: lang.to_string(),
+            content: vec![html.to_string()],
+            blob: String::new(),
+            duplicate_of: String::new(),
+        }
+    }
+
+    #[test]
+    fn finds_chunks_by_their_text() {
+        let dir = std::env::temp_dir().join(format!("hli-search-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(search(&dir, "main", 10).is_err());
+
+        let index = LocalIndex::open(&dir).unwrap();
+        let rust = file("acme/app", "Rust", "<td><span>fn</span> tokenize() {}</td>");
+        let go = file("acme/cli", "Go", "<td>func tokenize() {}</td>");
+        // A fork storing its copy as a reference to `acme/app`.
+        let fork = GitFile {
+            content: Vec::new(),
+            duplicate_of: rust.id.to_string(),
+            ..file("fork/app", "Rust", "")
+        };
+        index.insert(&rust, Lines { start: 4, end: 9 }).unwrap();
+        index.insert(&go, Lines { start: 1, end: 3 }).unwrap();
+        index.insert(&fork, Lines { start: 1, end: 9 }).unwrap();
+        index.commit().unwrap();
+        drop(index);
+
+        // The html is not searched, only its text.
+        assert!(search(&dir, "span", 10).unwrap().is_empty());
+        let hits = search(&dir, "tokenize", 10).unwrap();
+        assert_eq!(hits.len(), 2);
+
+        let hits = search(&dir, "tokenize AND lang:Rust", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        let hit = &hits[0];
+        assert_eq!(hit.file_id, "acme/app/main");
+        assert_eq!(hit.lang, "Rust");
+        assert_eq!((hit.start_line, hit.end_line), (4, 9));
+        assert_eq!(hit.copies, 1);
+
+        let hits = search(&dir, "tokenize AND repo:\"acme/cli\"", 10).unwrap();
+        assert_eq!(hits[0].file_id, "acme/cli/main");
+        assert_eq!(hits[0].copies, 0);
+        assert!(search(&dir, "lang:(", 10).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
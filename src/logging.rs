@@ -0,0 +1,116 @@
+use clap::ValueEnum;
+use heline_indexer::progress;
+use std::io::{self, IsTerminal, Write};
+use tracing_subscriber::EnvFilter;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+// Writes to stderr with the progress bar hidden.
+struct Stderr;
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        progress::suspend(|| io::stderr().write(buf))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        progress::suspend(|| io::stderr().write_all(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Log to stderr, stdout is left to the output of the commands.
+///
+/// `-v` and `-q` take precedence over `RUST_LOG`, logs of dependencies are
+/// kept at warn unless `RUST_LOG` says otherwise.
+pub fn init(verbose: u8, quiet: u8, format: LogFormat) {
+    let directives = directives(verbose, quiet);
+    let filter = if verbose == 0 && quiet == 0 {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&directives))
+    } else {
+        EnvFilter::new(&directives)
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(|| Stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    match format {
+        LogFormat::Text => builder.with_target(false).init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+// The level of `-v` and `-q` for the binary and the library, warn for the
+// dependencies.
+fn directives(verbose: u8, quiet: u8) -> String {
+    let level = match verbose as i8 - quiet as i8 {
+        i8::MIN..=-2 => "error",
+        -1 => "warn",
+        0 => "info",
+        1 => "debug",
+        _ => "trace",
+    };
+    format!("warn,hli={},heline_indexer={}", level, level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn logged(verbose: u8, quiet: u8) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::new(directives(verbose, quiet)))
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "heline_indexer::indexer", "Start indexing");
+            tracing::debug!(target: "heline_indexer::indexer", "Indexed src/lib.rs");
+            tracing::info!(target: "hli::command::index", "Done indexing");
+            tracing::info!(target: "hyper::client", "Connecting");
+        });
+        let text = buffer.0.lock().unwrap().clone();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn lets_library_events_through() {
+        let logs = logged(0, 0);
+        assert!(logs.contains("Start indexing"));
+        assert!(logs.contains("Done indexing"));
+        assert!(!logs.contains("Indexed src/lib.rs"));
+        assert!(!logs.contains("Connecting"));
+
+        let logs = logged(1, 0);
+        assert!(logs.contains("Indexed src/lib.rs"));
+        assert!(!logs.contains("Connecting"));
+
+        let logs = logged(0, 1);
+        assert!(!logs.contains("Start indexing"));
+        assert!(!logs.contains("Done indexing"));
+    }
+}
@@ -1,40 +1,47 @@
 mod arg;
-mod git;
-mod indexer;
-mod parser;
-mod solr;
-mod utils;
+mod command;
+mod logging;
+mod server;
 
-use arg::Arg;
-use indexer::Indexer;
+use arg::{Arg, Command, ConfigAction};
+use clap::Parser;
 
 #[tokio::main]
 pub async fn main() {
-    let mut arg = Arg::new();
-    match arg.parse() {
-        Ok(new_arg) => arg = new_arg,
+    let arg = Arg::parse();
+    logging::init(arg.verbose, arg.quiet, arg.log_format);
+    let config = match arg.config() {
+        Ok(config) => config,
         Err(msg) => {
-            eprintln!("{}", msg);
+            tracing::error!("{}", msg);
             std::process::exit(1);
         }
-    }
+    };
+    arg.validate(&config);
+
+    let result = match &arg.command {
+        Command::Index(index) => command::index::run(&config, index, false).await,
+        Command::Reindex(index) => command::index::run(&config, index, true).await,
+        Command::Delete(repos) => command::repos::delete(&config, repos).await,
+        Command::Search(search) => command::search::run(&config, search),
+        Command::Status(repos) => command::repos::status(&config, repos).await,
+        Command::Schema { action } => command::schema::run(&config, *action).await,
+        Command::Import { inputs } => command::import::run(&config, inputs).await,
+        Command::Serve(_) => server::run(config).await,
+        Command::Daemon(daemon) => command::daemon::run(&config, daemon).await,
+        Command::Discover(discover) => command::discover::run(&config, discover).await,
+        Command::Cache { action } => command::cache::run(&config, action),
+        Command::Config {
+            action: ConfigAction::Show,
+        } => command::config::show(&config),
+        Command::Completions { shell } => {
+            command::completions(*shell);
+            Ok(())
+        }
+    };
 
-    let value: Vec<String> = utils::parse_json(&arg.index_file);
-    for git_url in value {
-        match git::get_repo(&git_url).await {
-            Ok(_repo_id) => {
-                let indexer_service = Indexer::new(
-                    arg.folder.clone(),
-                    &git_url,
-                    &arg.solr_url,
-                    arg.with_delete_folder,
-                );
-                indexer_service.process().await;
-            }
-            Err(e) => {
-                print!("{}: Error {}\n", git_url, e);
-                continue;
-            }
-        };
+    if let Err(msg) = result {
+        tracing::error!("{}", msg);
+        std::process::exit(1);
     }
 }
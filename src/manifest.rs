@@ -0,0 +1,375 @@
+use crate::config::ProviderConfig;
+use crate::git::{github, gitlab, RemoteRepo, RepoRef};
+use crate::utils;
+use chrono::{Local, NaiveDate};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Provider {
+    GitHub,
+    GitLab,
+}
+
+/// An owner entry, e.g. `github:org/tailwindlabs?stars=100`.
+#[derive(Debug)]
+struct Source {
+    provider: Provider,
+    /// A user, otherwise a GitHub organization or a GitLab group.
+    user: bool,
+    owner: String,
+    filters: Filters,
+}
+
+#[derive(Debug, Default)]
+struct Filters {
+    forks: bool,
+    archived: bool,
+    stars: u64,
+    topics: Vec<String>,
+    language: Option<String>,
+    pushed_since: Option<String>,
+}
+
+impl Source {
+    /// `None` for a plain repository url.
+    fn parse(entry: &str) -> Option<Result<Self, String>> {
+        let (provider, rest) = match entry.split_once(':')? {
+            ("github", rest) => (Provider::GitHub, rest),
+            ("gitlab", rest) => (Provider::GitLab, rest),
+            _ => return None,
+        };
+        Some(Self::parse_owner(provider, rest).map_err(|e| format!("'{}': {}", entry, e)))
+    }
+
+    fn parse_owner(provider: Provider, rest: &str) -> Result<Self, String> {
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (kind, owner) = path
+            .split_once('/')
+            .ok_or("expected <kind>/<owner>, e.g. org/tailwindlabs")?;
+        let user = match (provider, kind) {
+            (_, "user") => true,
+            (Provider::GitHub, "org") | (Provider::GitLab, "group") => false,
+            (Provider::GitHub, _) => {
+                return Err(format!("unknown kind '{}', expected org or user", kind))
+            }
+            (Provider::GitLab, _) => {
+                return Err(format!("unknown kind '{}', expected group or user", kind))
+            }
+        };
+        let owner = owner.trim_matches('/');
+        if owner.is_empty() {
+            return Err("missing owner".to_string());
+        }
+
+        let mut filters = Filters::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let flag = || match value {
+                "true" | "" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(format!("{} expects true or false, got '{}'", key, value)),
+            };
+            match key {
+                "forks" => filters.forks = flag()?,
+                "archived" => filters.archived = flag()?,
+                "stars" => {
+                    filters.stars = value
+                        .parse()
+                        .map_err(|_| format!("stars expects a number, got '{}'", value))?
+                }
+                "topics" => filters.topics = value.split(',').map(str::to_string).collect(),
+                "language" => filters.language = Some(value.to_string()),
+                "pushed_since" => {
+                    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                        .map_err(|_| format!("pushed_since expects YYYY-MM-DD, got '{}'", value))?;
+                    filters.pushed_since = Some(value.to_string());
+                }
+                _ => return Err(format!("unknown filter '{}'", key)),
+            }
+        }
+
+        Ok(Self {
+            provider,
+            user,
+            owner: owner.to_string(),
+            filters,
+        })
+    }
+
+    async fn expand(&self, providers: &ProviderConfig) -> Result<Vec<String>, String> {
+        let repos = match self.provider {
+            Provider::GitHub => github::list_repos(&self.owner, self.user, providers).await?,
+            Provider::GitLab => {
+                let languages = self.filters.language.is_some();
+                gitlab::list_repos(&self.owner, self.user, languages, providers).await?
+            }
+        };
+        let total = repos.len();
+        let urls: Vec<String> = repos
+            .into_iter()
+            .filter(|repo| self.filters.matches(repo))
+            .map(|repo| repo.url)
+            .collect();
+        tracing::info!(
+            "Expanded '{}' to {} of {} repositories",
+            self.owner,
+            urls.len(),
+            total
+        );
+        Ok(urls)
+    }
+}
+
+impl Filters {
+    fn matches(&self, repo: &RemoteRepo) -> bool {
+        let language = match &self.language {
+            Some(language) => repo
+                .language
+                .as_ref()
+                .is_some_and(|lang| lang.eq_ignore_ascii_case(language)),
+            None => true,
+        };
+        // A date sorts before the ISO 8601 times of that day.
+        let pushed = match &self.pushed_since {
+            Some(since) => repo.pushed_at.as_ref().is_some_and(|at| at >= since),
+            None => true,
+        };
+        (self.forks || !repo.fork)
+            && (self.archived || !repo.archived)
+            && repo.stars >= self.stars
+            && self
+                .topics
+                .iter()
+                .all(|topic| repo.topics.iter().any(|t| t.eq_ignore_ascii_case(topic)))
+            && language
+            && pushed
+    }
+}
+
+/// The repository urls of the manifest at `path`, a json list of urls and
+/// owner entries expanded to every public repository of the owner:
+///
+/// ```text
+/// github:org/tailwindlabs
+/// github:user/ahmadrosid?stars=10&language=Rust
+/// gitlab:group/gitlab-org?forks=true&pushed_since=2024-01-01
+/// ```
+///
+/// Forks and archived repositories are left out unless `forks=true` or
+/// `archived=true`. `stars` is a minimum, `topics` a comma separated list of
+/// topics a repository must all have, `language` its primary language and
+/// `pushed_since` a date the last push must not be older than.
+pub async fn load(path: &Path, providers: &ProviderConfig) -> Result<Vec<String>, String> {
+    expand(utils::parse_json(path), providers)
+        .await
+        .map_err(|e| format!("Failed to expand '{}': {}", path.display(), e))
+}
+
+/// Replace the owner entries with their repositories, dropping the urls
+/// already listed.
+pub async fn expand(
+    entries: Vec<String>,
+    providers: &ProviderConfig,
+) -> Result<Vec<String>, String> {
+    let mut seen = HashSet::new();
+    let mut urls = Vec::new();
+    for entry in entries {
+        let expanded = match Source::parse(&entry) {
+            Some(source) => source?.expand(providers).await?,
+            None => vec![entry],
+        };
+        for url in expanded {
+            if seen.insert(repo_key(&url)) {
+                urls.push(url);
+            }
+        }
+    }
+    Ok(urls)
+}
+
+/// Add the repositories not listed yet to the manifest at `path`, created
+/// when missing, each with the `reason` it was added for. Returns the added
+/// urls.
+pub fn merge(path: &Path, repos: Vec<(String, String)>) -> Result<Vec<String>, String> {
+    let mut entries: Vec<Value> = match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid manifest '{}': {}", path.display(), e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(format!("Failed to read '{}': {}", path.display(), e)),
+    };
+    let mut seen: HashSet<String> = entries
+        .iter()
+        .filter_map(|entry| entry.as_str().or_else(|| entry["url"].as_str()))
+        .map(repo_key)
+        .collect();
+
+    let added_at = Local::now().format("%Y-%m-%d").to_string();
+    let mut added = Vec::new();
+    for (url, reason) in repos {
+        if !seen.insert(repo_key(&url)) {
+            continue;
+        }
+        entries.push(json!({ "url": url, "reason": reason, "added_at": added_at }));
+        added.push(url);
+    }
+
+    let json = serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())?;
+    fs::write(path, json + "\n")
+        .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+    Ok(added)
+}
+
+/// Urls of the same repository map to the same key, whatever form they take.
+pub fn repo_key(url: &str) -> String {
+    match RepoRef::parse(url) {
+        Ok(repo) => repo.key(),
+        Err(_) => url.trim_end_matches('/').to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(name: &str) -> RemoteRepo {
+        RemoteRepo {
+            url: format!("https://github.com/heline/{}", name),
+            fork: false,
+            archived: false,
+            stars: 12,
+            topics: vec!["Search".to_string(), "rust".to_string()],
+            language: Some("Rust".to_string()),
+            pushed_at: Some("2024-05-01T10:00:00Z".to_string()),
+        }
+    }
+
+    fn filters(entry: &str) -> Filters {
+        Source::parse(entry).unwrap().unwrap().filters
+    }
+
+    #[test]
+    fn leaves_out_forks_and_archived_repositories() {
+        let fork = RemoteRepo {
+            fork: true,
+            ..repo("fork")
+        };
+        let archived = RemoteRepo {
+            archived: true,
+            ..repo("old")
+        };
+        let default = filters("github:org/heline");
+        assert!(default.matches(&repo("heline.dev")));
+        assert!(!default.matches(&fork));
+        assert!(!default.matches(&archived));
+
+        let all = filters("github:org/heline?forks&archived=true");
+        assert!(all.matches(&fork));
+        assert!(all.matches(&archived));
+    }
+
+    #[test]
+    fn filters_the_repositories_of_an_owner() {
+        let repo = repo("heline.dev");
+        let cases = [
+            ("stars=12", true),
+            ("stars=13", false),
+            ("topics=search", true),
+            ("topics=search,go", false),
+            ("language=rust", true),
+            ("language=Go", false),
+            ("pushed_since=2024-05-01", true),
+            ("pushed_since=2024-05-02", false),
+            (
+                "stars=10&topics=rust&language=Rust&pushed_since=2024-01-01",
+                true,
+            ),
+        ];
+        for (query, matches) in cases {
+            let filters = filters(&format!("gitlab:group/heline?{}", query));
+            assert_eq!(filters.matches(&repo), matches, "{}", query);
+        }
+        let unknown = RemoteRepo {
+            language: None,
+            pushed_at: None,
+            ..repo
+        };
+        assert!(!filters("github:org/heline?language=Rust").matches(&unknown));
+        assert!(!filters("github:org/heline?pushed_since=2024-01-01").matches(&unknown));
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        let cases = [
+            "github:org/heline?stars=many",
+            "github:org/heline?forks=yes",
+            "github:org/heline?pushed_since=May",
+            "github:org/heline?license=mit",
+            "github:group/heline",
+            "gitlab:user/",
+        ];
+        for entry in cases {
+            assert!(Source::parse(entry).unwrap().is_err(), "{}", entry);
+        }
+        assert!(Source::parse("https://github.com/heline/heline.dev").is_none());
+    }
+
+    #[test]
+    fn merges_only_the_repositories_not_listed_yet() {
+        let path = std::env::temp_dir().join(format!("hli-merge-{}.json", std::process::id()));
+        let listed = json!([
+            "https://github.com/heline/heline.dev",
+            { "url": "git@github.com:ahmadrosid/hli.git", "reason": "by hand" },
+        ]);
+        fs::write(&path, listed.to_string()).unwrap();
+
+        let found = [
+            "https://github.com/Heline/heline.dev/",
+            "https://github.com/ahmadrosid/hli",
+            "https://github.com/tailwindlabs/tailwindcss",
+            // Listed twice by the search.
+            "https://github.com/tailwindlabs/tailwindcss.git",
+        ];
+        let repos = found
+            .iter()
+            .map(|url| (url.to_string(), "github search 'stars:>1000'".to_string()))
+            .collect();
+        let added = merge(&path, repos).unwrap();
+        assert_eq!(added, ["https://github.com/tailwindlabs/tailwindcss"]);
+
+        let entries: Vec<Value> =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], listed[0]);
+        assert_eq!(entries[1], listed[1]);
+        assert_eq!(
+            entries[2]["url"],
+            "https://github.com/tailwindlabs/tailwindcss"
+        );
+        assert_eq!(entries[2]["reason"], "github search 'stars:>1000'");
+        assert!(entries[2]["added_at"].is_string());
+
+        // Merging the same result again adds nothing.
+        let repos = vec![(found[2].to_string(), "again".to_string())];
+        assert!(merge(&path, repos).unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn creates_a_missing_manifest() {
+        let path = std::env::temp_dir().join(format!("hli-merge-new-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let repos = vec![(
+            "https://gitlab.com/heline/heline.dev".to_string(),
+            "gitlab search 'heline'".to_string(),
+        )];
+        assert_eq!(merge(&path, repos).unwrap().len(), 1);
+        let entries: Vec<Value> =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(entries[0]["url"], "https://gitlab.com/heline/heline.dev");
+        fs::remove_file(&path).unwrap();
+    }
+}
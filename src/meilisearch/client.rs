@@ -0,0 +1,216 @@
+use crate::sink::{self, BatchError, ChunkDocument, Lines};
+use crate::solr::client::GitFile;
+use reqwest::{Method, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const BACKEND: &str = "Meilisearch";
+const BATCH_SIZE: usize = 1000;
+const TASK_POLL_MS: u64 = 500;
+const TASK_TIMEOUT_SECS: u64 = 300;
+
+#[derive(Deserialize, Debug)]
+struct TaskInfo {
+    #[serde(rename = "taskUid")]
+    task_uid: u64,
+}
+
+/// Uid of an enqueued task and the ids of the files it adds.
+type Pending = (u64, Vec<String>);
+
+#[derive(Deserialize, Debug)]
+struct Task {
+    status: String,
+    error: Option<Value>,
+}
+
+/// Buffers chunk documents per repository and adds them in batches.
+/// Meilisearch processes them asynchronously, `flush` waits for the tasks
+/// enqueued for the repository to finish.
+pub struct Client {
+    base_url: String,
+    index: String,
+    api_key: Option<String>,
+    documents: Mutex<HashMap<String, Vec<ChunkDocument>>>,
+    tasks: Mutex<HashMap<String, Vec<Pending>>>,
+}
+
+impl Client {
+    pub fn new(base_url: &str, index: &str, api_key: Option<&str>) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            index: index.to_string(),
+            api_key: api_key.map(|key| key.to_string()),
+            documents: Mutex::new(HashMap::new()),
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn add(&self, data: &GitFile, lines: Lines) -> Result<(), BatchError> {
+        let batch = {
+            let mut documents = self.documents.lock().unwrap();
+            let documents = documents.entry(data.repo.to_string()).or_default();
+            documents.push(ChunkDocument::new(data, lines));
+            if documents.len() < BATCH_SIZE {
+                return Ok(());
+            }
+            std::mem::take(documents)
+        };
+        self.send(&data.repo, batch).await
+    }
+
+    /// Add the documents still buffered for `repo` and wait for its tasks.
+    pub async fn flush(&self, repo: &str) -> Result<(), BatchError> {
+        let batch = self.documents.lock().unwrap().remove(repo);
+        let sent = match batch {
+            Some(batch) if !batch.is_empty() => self.send(repo, batch).await,
+            _ => Ok(()),
+        };
+
+        let tasks = self.tasks.lock().unwrap().remove(repo).unwrap_or_default();
+        let mut error = sent.err();
+        for (task_uid, ids) in tasks {
+            if let Err(reason) = self.wait(task_uid).await {
+                let error = error.get_or_insert(BatchError {
+                    backend: BACKEND,
+                    ids: Vec::new(),
+                    reason: String::new(),
+                });
+                error.ids.extend(ids);
+                error.reason = reason;
+            }
+        }
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Drop the documents still buffered for `repo` and stop tracking its
+    /// tasks.
+    pub fn discard(&self, repo: &str) {
+        self.documents.lock().unwrap().remove(repo);
+        self.tasks.lock().unwrap().remove(repo);
+    }
+
+    pub async fn delete_repo(&self, repo: &str) -> Result<(), String> {
+        self.delete(filter(repo)).await
+    }
+
+    /// Remove the chunks of the files `ids`.
+    pub async fn delete_files(&self, ids: &[String]) -> Result<(), String> {
+        let ids: Vec<String> = ids.iter().map(|id| quote(id)).collect();
+        self.delete(format!("file IN [{}]", ids.join(", "))).await
+    }
+
+    async fn delete(&self, filter: String) -> Result<(), String> {
+        let path = format!("/indexes/{}/documents/delete", self.index);
+        let body = json!({ "filter": filter });
+        let res = self
+            .request(Method::POST, &path)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let task = res.json::<TaskInfo>().await.map_err(|e| e.to_string())?;
+        self.wait(task.task_uid).await
+    }
+
+    /// Number of chunks indexed for `repo`.
+    pub async fn count_repo(&self, repo: &str) -> Result<u64, String> {
+        let path = format!("/indexes/{}/search", self.index);
+        let body = json!({ "q": "", "filter": filter(repo), "limit": 0 });
+        let res = self
+            .request(Method::POST, &path)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let json = res.json::<Value>().await.map_err(|e| e.to_string())?;
+        match json["estimatedTotalHits"].as_u64() {
+            Some(total) => Ok(total),
+            None => Err(format!("Meilisearch search failed: {}", json)),
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        request(&self.base_url, self.api_key.as_deref(), method, path)
+    }
+
+    async fn send(&self, repo: &str, batch: Vec<ChunkDocument>) -> Result<(), BatchError> {
+        let ids = sink::file_ids(&batch);
+        let path = format!("/indexes/{}/documents?primaryKey=id", self.index);
+        let res = self.request(Method::POST, &path).json(&batch).send().await;
+        let error = |reason: String| BatchError {
+            backend: BACKEND,
+            ids: ids.clone(),
+            reason,
+        };
+
+        let res = res.map_err(|e| error(e.to_string()))?;
+        if !res.status().is_success() {
+            let reason = format!("{} {}", res.status(), res.text().await.unwrap_or_default());
+            return Err(error(reason));
+        }
+        let task = res
+            .json::<TaskInfo>()
+            .await
+            .map_err(|e| error(e.to_string()))?;
+        let mut tasks = self.tasks.lock().unwrap();
+        let tasks = tasks.entry(repo.to_string()).or_default();
+        tasks.push((task.task_uid, ids));
+        Ok(())
+    }
+
+    async fn wait(&self, task_uid: u64) -> Result<(), String> {
+        let path = format!("/tasks/{}", task_uid);
+        let mut waited = 0;
+        while waited < TASK_TIMEOUT_SECS * 1000 {
+            let res = self
+                .request(Method::GET, &path)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            let task = res.json::<Task>().await.map_err(|e| e.to_string())?;
+            match &task.status[..] {
+                "succeeded" => return Ok(()),
+                "failed" | "canceled" => {
+                    let reason = task
+                        .error
+                        .and_then(|e| e["message"].as_str().map(|v| v.to_string()));
+                    return Err(reason.unwrap_or(task.status));
+                }
+                _ => {}
+            }
+            tokio::time::sleep(Duration::from_millis(TASK_POLL_MS)).await;
+            waited += TASK_POLL_MS;
+        }
+        Err(format!("Task {} did not finish in time", task_uid))
+    }
+}
+
+fn filter(repo: &str) -> String {
+    format!("repo = {}", quote(repo))
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\\\""))
+}
+
+pub fn request(
+    base_url: &str,
+    api_key: Option<&str>,
+    method: Method,
+    path: &str,
+) -> RequestBuilder {
+    let client = reqwest::Client::new();
+    let builder = client.request(method, format!("{}{}", base_url, path));
+    match api_key {
+        Some(key) => builder.bearer_auth(key),
+        None => builder,
+    }
+}
@@ -0,0 +1,93 @@
+use crate::meilisearch::client::request;
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+const SEARCHABLE: [&str; 3] = ["content", "path", "repo"];
+const FILTERABLE: [&str; 7] = [
+    "repo",
+    "lang",
+    "path",
+    "branch",
+    "file",
+    "blob",
+    "duplicate_of",
+];
+const SORTABLE: [&str; 1] = ["start_line"];
+
+fn missing(current: &Value, expected: &[&str]) -> Vec<String> {
+    let current: Vec<&str> = current
+        .as_array()
+        .map(|values| values.iter().filter_map(|v| v.as_str()).collect())
+        .unwrap_or_default();
+    // `["*"]` is the default and makes every attribute searchable.
+    if current == ["*"] {
+        return Vec::new();
+    }
+    expected
+        .iter()
+        .filter(|name| !current.contains(name))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+pub async fn check(
+    base_url: &str,
+    index: &str,
+    api_key: Option<&str>,
+) -> Result<Vec<String>, String> {
+    let path = format!("/indexes/{}/settings", index);
+    let res = request(base_url, api_key, Method::GET, &path)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if res.status() == StatusCode::NOT_FOUND {
+        return Ok(vec![format!("missing index '{}'", index)]);
+    }
+    let settings = res.json::<Value>().await.map_err(|e| e.to_string())?;
+
+    let mut drifts = Vec::new();
+    for name in missing(&settings["searchableAttributes"], &SEARCHABLE) {
+        drifts.push(format!("'{}' is not searchable", name));
+    }
+    for name in missing(&settings["filterableAttributes"], &FILTERABLE) {
+        drifts.push(format!("'{}' is not filterable", name));
+    }
+    for name in missing(&settings["sortableAttributes"], &SORTABLE) {
+        drifts.push(format!("'{}' is not sortable", name));
+    }
+    Ok(drifts)
+}
+
+/// Create the index and set its searchable, filterable and sortable attributes.
+pub async fn apply(
+    base_url: &str,
+    index: &str,
+    api_key: Option<&str>,
+) -> Result<Vec<String>, String> {
+    let drifts = check(base_url, index, api_key).await?;
+    if drifts.is_empty() {
+        return Ok(drifts);
+    }
+
+    let body = json!({ "uid": index, "primaryKey": "id" });
+    send(request(base_url, api_key, Method::POST, "/indexes").json(&body)).await?;
+
+    let body = json!({
+        "searchableAttributes": SEARCHABLE,
+        "filterableAttributes": FILTERABLE,
+        "sortableAttributes": SORTABLE
+    });
+    let path = format!("/indexes/{}/settings", index);
+    send(request(base_url, api_key, Method::PATCH, &path).json(&body)).await?;
+
+    Ok(drifts)
+}
+
+async fn send(builder: reqwest::RequestBuilder) -> Result<(), String> {
+    let res = builder.send().await.map_err(|e| e.to_string())?;
+    if !res.status().is_success() {
+        let msg = res.text().await.unwrap_or_default();
+        return Err(format!("Meilisearch request failed: {}", msg));
+    }
+    Ok(())
+}
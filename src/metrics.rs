@@ -0,0 +1,217 @@
+use crate::indexer::Summary;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Metrics of the indexer, exposed by `hli serve` and `hli daemon` as
+/// `/metrics`.
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+struct Metrics {
+    registry: Registry,
+    repos: IntCounterVec,
+    files: IntCounterVec,
+    skipped: IntCounterVec,
+    chunks: IntCounterVec,
+    clone_duration: HistogramVec,
+    sink_duration: HistogramVec,
+    sink_errors: IntCounterVec,
+    /// Every attempt of a Solr request, retries included.
+    solr_duration: Histogram,
+    solr_errors: IntCounterVec,
+    rate_limit: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let histogram = |name: &str, help: &str, labels: &[&str], buckets: Vec<f64>| {
+            let opts = HistogramOpts::new(name, help).buckets(buckets);
+            let histogram = HistogramVec::new(opts, labels).unwrap();
+            registry.register(Box::new(histogram.clone())).unwrap();
+            histogram
+        };
+        let gauge = |name: &str, help: &str, labels: &[&str]| {
+            let gauge = IntGaugeVec::new(Opts::new(name, help), labels).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+
+        Self {
+            repos: counter(
+                "hli_repos_total",
+                "Repositories processed, by outcome",
+                &["host", "status"],
+            ),
+            files: counter(
+                "hli_files_indexed_total",
+                "Files indexed",
+                &["host", "lang"],
+            ),
+            skipped: counter(
+                "hli_files_skipped_total",
+                "Files skipped, by reason",
+                &["host", "reason"],
+            ),
+            chunks: counter("hli_chunks_total", "Chunks uploaded", &["host"]),
+            clone_duration: histogram(
+                "hli_clone_duration_seconds",
+                "Time spent cloning or fetching a repository",
+                &["host"],
+                prometheus::exponential_buckets(0.1, 2.0, 12).unwrap(),
+            ),
+            sink_duration: histogram(
+                "hli_sink_request_duration_seconds",
+                "Latency of the writes, deletes and counts of every backend",
+                &["backend", "operation"],
+                prometheus::exponential_buckets(0.0005, 2.0, 16).unwrap(),
+            ),
+            sink_errors: counter(
+                "hli_sink_errors_total",
+                "Failed backend operations, after retries",
+                &["backend", "operation", "kind"],
+            ),
+            solr_duration: {
+                let opts = HistogramOpts::new(
+                    "hli_solr_request_duration_seconds",
+                    "Latency of every attempt of a Solr update request, Solr only",
+                )
+                .buckets(prometheus::exponential_buckets(0.005, 2.0, 12).unwrap());
+                let histogram = Histogram::with_opts(opts).unwrap();
+                registry.register(Box::new(histogram.clone())).unwrap();
+                histogram
+            },
+            solr_errors: counter(
+                "hli_solr_errors_total",
+                "Failed attempts of Solr update requests, retries included, Solr only",
+                &["kind"],
+            ),
+            rate_limit: gauge(
+                "hli_provider_rate_limit_remaining",
+                "Requests left in the provider api rate limit window",
+                &["provider"],
+            ),
+            registry,
+        }
+    }
+}
+
+/// Count a repository and its files once it is processed, the clone
+/// duration only when it was `cloned` rather than indexed in place.
+pub fn record_repo(host: &str, summary: &Summary, cloned: bool) {
+    let metrics = &*METRICS;
+    let status = if summary.failure.is_some() {
+        "failed"
+    } else if summary.failed > 0 {
+        "partial"
+    } else {
+        "indexed"
+    };
+    metrics.repos.with_label_values(&[host, status]).inc();
+    for (lang, files) in &summary.languages {
+        let files = *files as u64;
+        metrics.files.with_label_values(&[host, lang]).inc_by(files);
+    }
+    for (skip, files) in &summary.skips {
+        let reason = skip.to_string();
+        let files = *files as u64;
+        metrics
+            .skipped
+            .with_label_values(&[host, &reason])
+            .inc_by(files);
+    }
+    let chunks = summary.chunks as u64;
+    metrics.chunks.with_label_values(&[host]).inc_by(chunks);
+    if cloned {
+        let clone = summary.phases.clone.as_secs_f64();
+        metrics
+            .clone_duration
+            .with_label_values(&[host])
+            .observe(clone);
+    }
+}
+
+/// `operation` is the `Sink` method, e.g. `insert` or `delete_repo`.
+pub fn record_sink_request(backend: &str, operation: &str, duration: Duration) {
+    METRICS
+        .sink_duration
+        .with_label_values(&[backend, operation])
+        .observe(duration.as_secs_f64());
+}
+
+/// `kind` is the label of the `SinkError`.
+pub fn record_sink_error(backend: &str, operation: &str, kind: &str) {
+    METRICS
+        .sink_errors
+        .with_label_values(&[backend, operation, kind])
+        .inc();
+}
+
+pub fn record_solr_request(duration: Duration) {
+    METRICS.solr_duration.observe(duration.as_secs_f64());
+}
+
+/// `kind` is `request`, `server`, `throttled` or `rejected`, see `SolrError`.
+pub fn record_solr_error(kind: &str) {
+    METRICS.solr_errors.with_label_values(&[kind]).inc();
+}
+
+pub fn record_rate_limit(provider: &str, remaining: i64) {
+    METRICS
+        .rate_limit
+        .with_label_values(&[provider])
+        .set(remaining);
+}
+
+/// Every metric in the Prometheus text format.
+pub fn gather() -> String {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    // Encoding into a Vec does not fail.
+    let _ = encoder.encode(&METRICS.registry.gather(), &mut buffer);
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::writer::ExportWriter;
+    use crate::sink::Sink;
+
+    #[tokio::test]
+    async fn records_the_operations_of_every_backend() {
+        let dir = std::env::temp_dir().join(format!("hli-metrics-{}", std::process::id()));
+        let sink = Sink::Ndjson(ExportWriter::new(dir.clone(), false).unwrap());
+        sink.flush("acme/app").await.unwrap();
+        sink.count_repo("acme/app").await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let text = gather();
+        for operation in ["flush", "count_repo"] {
+            let series = format!(
+                "hli_sink_request_duration_seconds_count{{backend=\"ndjson\",operation=\"{}\"}} ",
+                operation
+            );
+            assert!(text.contains(&series), "{}", series);
+        }
+    }
+
+    #[test]
+    fn leaves_repositories_indexed_in_place_out_of_the_clone_durations() {
+        let summary = Summary::default();
+        record_repo("in-place.localhost", &summary, false);
+        record_repo("cloned.localhost", &summary, true);
+        let text = gather();
+        assert!(text.contains("hli_repos_total{host=\"in-place.localhost\",status=\"indexed\"} 1"));
+        assert!(!text.contains("hli_clone_duration_seconds_count{host=\"in-place.localhost\"}"));
+        assert!(text.contains("hli_clone_duration_seconds_count{host=\"cloned.localhost\"} 1"));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::sink::BatchError;
+use crate::solr::client::GitFile;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const BACKEND: &str = "OpenSearch";
+const BATCH_SIZE: usize = 500;
+
+/// A `GitFile` with every rendered chunk stored as a nested document.
+#[derive(Serialize, Debug)]
+pub struct OpenSearchFile<'a> {
+    pub id: &'a str,
+    pub file_id: &'a str,
+    pub owner_id: &'a str,
+    pub path: &'a str,
+    pub repo: &'a str,
+    pub branch: &'a str,
+    pub lang: &'a str,
+    pub content: Vec<Chunk<'a>>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub blob: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub duplicate_of: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Chunk<'a> {
+    pub html: &'a str,
+}
+
+impl<'a> From<&'a GitFile> for OpenSearchFile<'a> {
+    fn from(data: &'a GitFile) -> Self {
+        Self {
+            id: &data.id,
+            file_id: &data.file_id,
+            owner_id: &data.owner_id,
+            path: &data.path,
+            repo: &data.repo,
+            branch: &data.branch,
+            lang: &data.lang,
+            content: chunks(data),
+            blob: &data.blob,
+            duplicate_of: &data.duplicate_of,
+        }
+    }
+}
+
+fn chunks(data: &GitFile) -> Vec<Chunk<'_>> {
+    data.content.iter().map(|html| Chunk { html }).collect()
+}
+
+#[derive(Deserialize, Debug)]
+struct BulkResponse {
+    errors: bool,
+    #[serde(default)]
+    items: Vec<Value>,
+}
+
+/// Buffers bulk operations per repository and sends them to `_bulk` once
+/// `BATCH_SIZE` operations are queued or when the repository is flushed.
+pub struct Client {
+    base_url: String,
+    index: String,
+    operations: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl Client {
+    pub fn new(base_url: &str, index: &str) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            index: index.to_string(),
+            operations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn insert(&self, data: &GitFile) -> Result<(), BatchError> {
+        let action = json!({ "index": { "_index": self.index, "_id": data.id } });
+        let document = OpenSearchFile::from(data);
+        self.push(&data.repo, action, json!(document)).await
+    }
+
+    pub async fn update(&self, data: &GitFile) -> Result<(), BatchError> {
+        let action = json!({ "update": { "_index": self.index, "_id": data.id } });
+        let script = json!({
+            "script": {
+                "source": "ctx._source.content.addAll(params.content)",
+                "lang": "painless",
+                "params": { "content": chunks(data) }
+            }
+        });
+        self.push(&data.repo, action, script).await
+    }
+
+    async fn push(&self, repo: &str, action: Value, source: Value) -> Result<(), BatchError> {
+        let batch = {
+            let mut operations = self.operations.lock().unwrap();
+            let operations = operations.entry(repo.to_string()).or_default();
+            operations.push(action.to_string());
+            operations.push(source.to_string());
+            if operations.len() < BATCH_SIZE * 2 {
+                return Ok(());
+            }
+            std::mem::take(operations)
+        };
+        self.bulk(batch).await
+    }
+
+    /// Send the operations still buffered for `repo`.
+    pub async fn flush(&self, repo: &str) -> Result<(), BatchError> {
+        let batch = self.operations.lock().unwrap().remove(repo);
+        let Some(batch) = batch.filter(|batch| !batch.is_empty()) else {
+            return Ok(());
+        };
+        self.bulk(batch).await
+    }
+
+    /// Drop the operations still buffered for `repo`.
+    pub fn discard(&self, repo: &str) {
+        self.operations.lock().unwrap().remove(repo);
+    }
+
+    pub async fn delete_repo(&self, repo: &str) -> Result<(), String> {
+        self.delete_by_query(json!({ "term": { "repo": repo } }))
+            .await
+    }
+
+    /// Remove the documents of the files `ids`.
+    pub async fn delete_files(&self, ids: &[String]) -> Result<(), String> {
+        self.delete_by_query(json!({ "ids": { "values": ids } }))
+            .await
+    }
+
+    pub async fn count_repo(&self, repo: &str) -> Result<u64, String> {
+        let url = format!("{}/{}/_count", self.base_url, self.index);
+        let json = self
+            .query(&url, json!({ "term": { "repo": repo } }))
+            .await?;
+        Ok(json["count"].as_u64().unwrap_or_default())
+    }
+
+    async fn delete_by_query(&self, query: Value) -> Result<(), String> {
+        let url = format!(
+            "{}/{}/_delete_by_query?refresh=true",
+            self.base_url, self.index
+        );
+        self.query(&url, query).await?;
+        Ok(())
+    }
+
+    async fn query(&self, url: &str, query: Value) -> Result<Value, String> {
+        let client = reqwest::Client::new();
+        let res = client
+            .post(url)
+            .json(&json!({ "query": query }))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !res.status().is_success() {
+            let msg = res.text().await.unwrap_or_default();
+            return Err(format!("OpenSearch request failed: {}", msg));
+        }
+        res.json::<Value>().await.map_err(|e| e.to_string())
+    }
+
+    async fn bulk(&self, batch: Vec<String>) -> Result<(), BatchError> {
+        let mut body = batch.join("\n");
+        body.push('\n');
+
+        let url = format!("{}/_bulk", self.base_url);
+        let client = reqwest::Client::new();
+        let res = client
+            .post(url)
+            .header("Content-Type", "application/x-ndjson")
+            .body(body)
+            .send()
+            .await;
+
+        let response = match res {
+            Ok(res) if res.status().is_success() => res.json::<BulkResponse>().await,
+            Ok(res) => {
+                let reason = format!("{} {}", res.status(), res.text().await.unwrap_or_default());
+                return Err(batch_error(&batch, reason));
+            }
+            Err(e) => return Err(batch_error(&batch, e.to_string())),
+        };
+        let response = response.map_err(|e| batch_error(&batch, e.to_string()))?;
+        if !response.errors {
+            return Ok(());
+        }
+
+        let mut error = BatchError {
+            backend: BACKEND,
+            ids: Vec::new(),
+            reason: String::new(),
+        };
+        for item in &response.items {
+            let result = item.as_object().and_then(|op| op.values().next());
+            if let Some(result) = result {
+                if let Some(reason) = result.get("error") {
+                    let id = result["_id"].as_str().unwrap_or_default().to_string();
+                    tracing::warn!("Failed to store '{}': {}", id, reason["reason"]);
+                    if !error.ids.contains(&id) {
+                        error.ids.push(id);
+                    }
+                    error.reason = reason["reason"].as_str().unwrap_or_default().to_string();
+                }
+            }
+        }
+        Err(error)
+    }
+}
+
+// Every document of a batch is lost when the request itself fails.
+fn batch_error(batch: &[String], reason: String) -> BatchError {
+    let mut ids: Vec<String> = Vec::new();
+    for action in batch.iter().step_by(2) {
+        let action = serde_json::from_str::<Value>(action).unwrap_or_default();
+        let id = action["index"]["_id"]
+            .as_str()
+            .or(action["update"]["_id"].as_str());
+        if let Some(id) = id {
+            if !ids.iter().any(|v| v == id) {
+                ids.push(id.to_string());
+            }
+        }
+    }
+    BatchError {
+        backend: BACKEND,
+        ids,
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::sync::Arc;
+
+    fn file(content: &str) -> GitFile {
+        GitFile {
+            id: "ahmadrosid/hl/hl/src/main.rs".to_string(),
+            file_id: "github.com/ahmadrosid/hl/hl/src/main.rs".to_string(),
+            owner_id: "0000".to_string(),
+            path: "hl/src".to_string(),
+            repo: "ahmadrosid/hl".to_string(),
+            branch: "main".to_string(),
+            lang: "Rust".to_string(),
+            content: vec![content.to_string()],
+            blob: String::new(),
+            duplicate_of: String::new(),
+        }
+    }
+
+    // An OpenSearch answering `_bulk` with `response`, the request bodies
+    // are recorded.
+    async fn serve(response: Value) -> (String, Arc<Mutex<Vec<String>>>) {
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let recorded = bodies.clone();
+        let app = Router::new().route(
+            "/_bulk",
+            post(move |body: String| async move {
+                recorded.lock().unwrap().push(body);
+                Json(response)
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (base_url, bodies)
+    }
+
+    #[tokio::test]
+    async fn sends_chunks_as_nested_documents() {
+        let (base_url, bodies) = serve(json!({ "errors": false, "items": [] })).await;
+        let client = Client::new(&base_url, "heline");
+        client.insert(&file("<tr>fn main()</tr>")).await.unwrap();
+        client.update(&file("<tr>}</tr>")).await.unwrap();
+        client.flush("ahmadrosid/hl").await.unwrap();
+
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 1);
+        let lines: Vec<Value> = bodies[0]
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            json!({ "index": { "_index": "heline", "_id": "ahmadrosid/hl/hl/src/main.rs" } })
+        );
+        assert_eq!(
+            lines[1]["content"],
+            json!([{ "html": "<tr>fn main()</tr>" }])
+        );
+        assert_eq!(lines[1]["repo"], "ahmadrosid/hl");
+        // Without dedupe the mapping needs no blob fields.
+        assert!(lines[1].get("blob").is_none());
+        assert!(lines[1].get("duplicate_of").is_none());
+        assert_eq!(lines[2]["update"]["_id"], "ahmadrosid/hl/hl/src/main.rs");
+        assert_eq!(
+            lines[3]["script"]["params"]["content"],
+            json!([{ "html": "<tr>}</tr>" }])
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_the_rejected_documents() {
+        let response = json!({
+            "errors": true,
+            "items": [{
+                "index": {
+                    "_id": "ahmadrosid/hl/hl/src/main.rs",
+                    "error": { "reason": "mapper_parsing_exception" }
+                }
+            }]
+        });
+        let (base_url, _) = serve(response).await;
+        let client = Client::new(&base_url, "heline");
+        client.insert(&file("<tr>fn main()</tr>")).await.unwrap();
+        let error = client.flush("ahmadrosid/hl").await.unwrap_err();
+        assert_eq!(error.ids, ["ahmadrosid/hl/hl/src/main.rs"]);
+        assert_eq!(error.reason, "mapper_parsing_exception");
+    }
+}
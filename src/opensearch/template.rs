@@ -0,0 +1,217 @@
+use crate::solr::schema::FIELDS;
+use serde_json::{json, Map, Value};
+
+// Mirrors the Solr schema: string fields become keywords and every content
+// chunk is a nested document with html stripped before analysis.
+fn mappings() -> Value {
+    let mut properties = Map::new();
+    properties.insert("id".to_string(), json!({ "type": "keyword" }));
+    for field in FIELDS.iter() {
+        let mapping = match field.name {
+            "content" => json!({
+                "type": "nested",
+                "properties": {
+                    "html": { "type": "text", "analyzer": "html_text" }
+                }
+            }),
+            _ => json!({ "type": "keyword" }),
+        };
+        properties.insert(field.name.to_string(), mapping);
+    }
+    json!({ "properties": properties })
+}
+
+// The analyzer of the content chunks, an index needs it before the mapping
+// referring to it is added.
+fn settings() -> Value {
+    json!({
+        "analysis": {
+            "analyzer": {
+                "html_text": {
+                    "type": "custom",
+                    "char_filter": ["html_strip"],
+                    "tokenizer": "standard",
+                    "filter": ["lowercase"]
+                }
+            }
+        }
+    })
+}
+
+fn template(index: &str) -> Value {
+    json!({
+        "index_patterns": [format!("{}*", index)],
+        "template": {
+            "settings": settings(),
+            "mappings": mappings()
+        }
+    })
+}
+
+async fn get(url: &str) -> Result<Option<Value>, String> {
+    let client = reqwest::Client::new();
+    let res = client.get(url).send().await.map_err(|e| e.to_string())?;
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let json = res.json::<Value>().await.map_err(|e| e.to_string())?;
+    Ok(Some(json))
+}
+
+async fn put(url: &str, body: &Value) -> Result<(), String> {
+    send(reqwest::Method::PUT, url, body).await
+}
+
+async fn post(url: &str) -> Result<(), String> {
+    send(reqwest::Method::POST, url, &json!({})).await
+}
+
+async fn send(method: reqwest::Method, url: &str, body: &Value) -> Result<(), String> {
+    let client = reqwest::Client::new();
+    let res = client
+        .request(method, url)
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !res.status().is_success() {
+        let msg = res.text().await.unwrap_or_default();
+        return Err(format!("Request to {} failed: {}", url, msg));
+    }
+    Ok(())
+}
+
+pub async fn check(base_url: &str, index: &str) -> Result<Vec<String>, String> {
+    let mut drifts = Vec::new();
+
+    let url = format!("{}/_index_template/{}", base_url, index);
+    if get(&url).await?.is_none() {
+        drifts.push(format!("missing index template '{}'", index));
+    }
+
+    let url = format!("{}/{}/_settings", base_url, index);
+    if let Some(json) = get(&url).await? {
+        if !has_analyzer(&json, index) {
+            drifts.push("missing analyzer 'html_text'".to_string());
+        }
+    }
+
+    let url = format!("{}/{}/_mapping", base_url, index);
+    match get(&url).await? {
+        None => drifts.push(format!("missing index '{}'", index)),
+        Some(json) => {
+            let current = &json[index]["mappings"]["properties"];
+            let expected = mappings();
+            if let Some(properties) = expected["properties"].as_object() {
+                for (name, mapping) in properties {
+                    let field_type = current[name]["type"].as_str();
+                    match field_type {
+                        None => drifts.push(format!("missing field '{}'", name)),
+                        Some(field_type) if field_type != mapping["type"] => drifts.push(format!(
+                            "field '{}' is {}, expected {}",
+                            name, field_type, mapping["type"]
+                        )),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(drifts)
+}
+
+fn has_analyzer(settings: &Value, index: &str) -> bool {
+    let analysis = &settings[index]["settings"]["index"]["analysis"];
+    !analysis["analyzer"]["html_text"].is_null()
+}
+
+/// Install the index template and create the index when it does not exist.
+/// An existing index is closed while the analyzer is added to it.
+///
+/// Mappings of an existing index can not change type in place, those drifts
+/// are reported back and need a reindex into a new index.
+pub async fn apply(base_url: &str, index: &str) -> Result<Vec<String>, String> {
+    let drifts = check(base_url, index).await?;
+    if drifts.is_empty() {
+        return Ok(drifts);
+    }
+
+    let url = format!("{}/_index_template/{}", base_url, index);
+    put(&url, &template(index)).await?;
+
+    let url = format!("{}/{}", base_url, index);
+    if get(&url).await?.is_none() {
+        let body = json!({ "settings": settings(), "mappings": mappings() });
+        put(&url, &body).await?;
+        return Ok(drifts);
+    }
+
+    let settings_url = format!("{}/{}/_settings", base_url, index);
+    let current = get(&settings_url).await?.unwrap_or_default();
+    if !has_analyzer(&current, index) {
+        post(&format!("{}/_close", url)).await?;
+        let added = put(&settings_url, &settings()).await;
+        // Reopened even when the analyzer could not be added.
+        post(&format!("{}/_open", url)).await?;
+        added?;
+    }
+    put(&format!("{}/_mapping", url), &mappings()).await?;
+
+    Ok(drifts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::Router;
+    use std::sync::{Arc, Mutex};
+
+    // An OpenSearch with an index `heline` created without the analyzer,
+    // the requests are recorded.
+    async fn serve() -> (String, Arc<Mutex<Vec<String>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let app = Router::new().fallback(move |request: Request| async move {
+            let call = format!("{} {}", request.method(), request.uri().path());
+            recorded.lock().unwrap().push(call.clone());
+            let body = match &call[..] {
+                "GET /_index_template/heline" => return StatusCode::NOT_FOUND.into_response(),
+                "GET /heline/_settings" => json!({ "heline": { "settings": { "index": {} } } }),
+                "GET /heline/_mapping" => json!({ "heline": { "mappings": {} } }),
+                _ => json!({ "acknowledged": true }),
+            };
+            axum::Json(body).into_response()
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (base_url, calls)
+    }
+
+    #[tokio::test]
+    async fn adds_the_analyzer_before_the_mapping() {
+        let (base_url, calls) = serve().await;
+        let drifts = apply(&base_url, "heline").await.unwrap();
+        assert!(drifts.contains(&"missing analyzer 'html_text'".to_string()));
+        let calls = calls.lock().unwrap().clone();
+        let applied: Vec<&str> = calls
+            .iter()
+            .map(|call| &call[..])
+            .filter(|call| !call.starts_with("GET"))
+            .collect();
+        assert_eq!(
+            applied,
+            [
+                "PUT /_index_template/heline",
+                "POST /heline/_close",
+                "PUT /heline/_settings",
+                "POST /heline/_open",
+                "PUT /heline/_mapping"
+            ]
+        );
+    }
+}
@@ -1,8 +1,36 @@
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
+
+/// Why a file is not indexed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Skip {
+    /// Listed in `filters.ignore_files`.
+    Ignored,
+    /// Larger than `filters.max_file_size`.
+    TooLarge,
+    /// Not valid UTF-8 or not readable, e.g. binaries.
+    Unreadable,
+    Empty,
+    /// 12 bytes or less.
+    TooShort,
+}
+
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Skip::Ignored => "ignored",
+            Skip::TooLarge => "too large",
+            Skip::Unreadable => "unreadable",
+            Skip::Empty => "empty",
+            Skip::TooShort => "too short",
+        };
+        f.write_str(reason)
+    }
+}
 
 // TODO: Use this enum to map the file extension
-#[allow(dead_code)]
+#[allow(dead_code, clippy::upper_case_acronyms)]
 enum Language {
     Raw,
     Shell,
@@ -34,82 +62,85 @@ enum Language {
     Dockerfile,
 }
 
-pub fn read_file(file_path: &PathBuf) -> Result<(Vec<char>, &str), String> {
+pub fn read_file(file_path: &Path) -> Result<(Vec<char>, &str), Skip> {
     let path = file_path.to_str().unwrap();
-    if let Some(name) = file_path.file_name().unwrap().to_str() {
-        match name {
-            "package-lock.json" => return Err(format!("Ignore file: '{}'!", path)),
-            "yarn.lock" => return Err(format!("Ignore file: '{}'!", path)),
-            _ => {}
-        }
-    }
 
     if let Ok(source) = fs::read_to_string(path) {
-        if source.len() == 0 {
-            return Err(format!("Failed to read file: '{}'!", path));
+        if source.is_empty() {
+            return Err(Skip::Empty);
         }
 
         if source.len() <= 12 {
-            return Err(format!("Source code to short: {}", source));
+            return Err(Skip::TooShort);
         }
 
         let input = source.chars().collect();
-        let lang = match file_path.extension() {
-            Some(ext) => match ext.to_str().unwrap_or("Raw") {
-                "sh" | "zsh" | "bash" => "Shell",
-                "js" => "JavaScript",
-                "go" => "Go",
-                "groovy" => "Groovy",
-                "ts" | "tsx" => "TypeScript",
-                "c" | "h" => "C",
-                "cpp" | "c++" => "C++",
-                "html" => "HTML",
-                "hs" => "Haskell",
-                "java" => "Java",
-                "kt" => "Kotlin",
-                "lua" => "Lua",
-                "md" | "adoc" => "Markdown",
-                "nim" => "Nim",
-                "py" => "Python",
-                "php" => "PHP",
-                "ru" | "rb" | "podspec" => "Ruby",
-                "rs" => "Rust",
-                "toml" => "TOML",
-                "cs" => "C#",
-                "yml" | "yaml" => "YAML",
-                "dart" => "Dart",
-                "patch" => "Diff",
-                "json" => "JSON",
-                "proto" => "Protocol Buffer",
-                "lock" => match file_path.file_name().unwrap().to_str().unwrap() {
-                    "Cargo.lock" => "TOML",
-                    "Gemfile.lock" => "Gemfile",
-                    "yarn.lock" => "YAML",
-                    _ => "Raw",
-                },
-                _ => ext.to_str().unwrap(),
-            },
-            _ => parse_file_name(file_path.file_name().unwrap().to_str().unwrap()),
-        };
+        let lang = detect_language(file_path);
         Ok((input, lang))
     } else {
-        Err(format!("Failed to read file: '{}'!", path))
+        Err(Skip::Unreadable)
+    }
+}
+
+/// Language of a file from its extension or name, `Raw` when unknown.
+pub fn detect_language(file_path: &Path) -> &str {
+    match file_path.extension() {
+        Some(ext) => match ext.to_str().unwrap_or("Raw") {
+            "sh" | "zsh" | "bash" => "Shell",
+            "js" => "JavaScript",
+            "go" => "Go",
+            "groovy" => "Groovy",
+            "ts" | "tsx" => "TypeScript",
+            "c" | "h" => "C",
+            "cpp" | "c++" => "C++",
+            "html" => "HTML",
+            "hs" => "Haskell",
+            "java" => "Java",
+            "kt" => "Kotlin",
+            "lua" => "Lua",
+            "md" | "adoc" => "Markdown",
+            "nim" => "Nim",
+            "py" => "Python",
+            "php" => "PHP",
+            "ru" | "rb" | "podspec" => "Ruby",
+            "rs" => "Rust",
+            "toml" => "TOML",
+            "cs" => "C#",
+            "yml" | "yaml" => "YAML",
+            "dart" => "Dart",
+            "patch" => "Diff",
+            "json" => "JSON",
+            "proto" => "Protocol Buffer",
+            "lock" => match file_path.file_name().unwrap().to_str().unwrap() {
+                "Cargo.lock" => "TOML",
+                "Gemfile.lock" => "Gemfile",
+                "yarn.lock" => "YAML",
+                _ => "Raw",
+            },
+            _ => ext.to_str().unwrap(),
+        },
+        _ => parse_file_name(file_path.file_name().unwrap().to_str().unwrap()),
     }
 }
 
 fn parse_file_name(file: &str) -> &str {
-    return match file {
+    match file {
         "Jenkinsfile" => "Groovy",
         "Dockerfile" => "Dockerfile",
         "Makefile" => "Makefile",
         "Gemfile" => "Gemfile",
         "Rakefile" => "Rakefile",
         _ => "Raw",
-    };
+    }
 }
 
+/// Version of the html of `render_html` and `chunk::split`, bumped when
+/// their output changes, e.g. on upgrading `hl_core`, so cached renders are
+/// not reused.
+pub const RENDER_VERSION: u32 = 1;
+
 pub fn render_html(input: Vec<char>, lang: &str) -> String {
-    return match lang {
+    match lang {
         "Shell" => hl_core::render_html(input, "bash"),
         "C" => hl_core::render_html(input, "c"),
         "C++" => hl_core::render_html(input, "cpp"),
@@ -204,7 +235,7 @@ pub fn render_html(input: Vec<char>, lang: &str) -> String {
                 }
             }
 
-            return hl_core::render_html(input, "raw");
+            hl_core::render_html(input, "raw")
         }
-    };
+    }
 }
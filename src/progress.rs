@@ -0,0 +1,127 @@
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often a summary line is logged when stdout is not a terminal.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+// The bar being drawn, logs are written around it.
+static BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Run `f` with the progress bar hidden, so writing to the terminal does not
+/// garble it.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    let bar = BAR.lock().unwrap().clone();
+    match bar {
+        Some(bar) => bar.suspend(f),
+        None => f(),
+    }
+}
+
+/// Progress of an index run, drawn as a bar on a terminal and logged every
+/// `REPORT_INTERVAL` otherwise.
+pub struct Progress {
+    bar: Option<ProgressBar>,
+    total: usize,
+    started: Instant,
+    repos: AtomicUsize,
+    files: AtomicUsize,
+    bytes: AtomicU64,
+    current: Mutex<Vec<String>>,
+    reported: Mutex<Instant>,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        let bar = if std::io::stdout().is_terminal() {
+            let bar =
+                ProgressBar::with_draw_target(Some(total as u64), ProgressDrawTarget::stdout());
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{spinner} [{elapsed_precise}] {bar:30} {pos}/{len} repos, ETA {eta} {msg}",
+                )
+                .unwrap(),
+            );
+            bar.enable_steady_tick(Duration::from_millis(200));
+            *BAR.lock().unwrap() = Some(bar.clone());
+            Some(bar)
+        } else {
+            None
+        };
+
+        Self {
+            bar,
+            total,
+            started: Instant::now(),
+            repos: AtomicUsize::new(0),
+            files: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            current: Mutex::new(Vec::new()),
+            reported: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn start_repo(&self, repo: &str) {
+        self.current.lock().unwrap().push(repo.to_string());
+        self.update();
+    }
+
+    pub fn finish_repo(&self, repo: &str) {
+        self.current
+            .lock()
+            .unwrap()
+            .retain(|current| current != repo);
+        self.repos.fetch_add(1, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+        }
+        self.update();
+    }
+
+    /// Count an indexed file of `bytes`.
+    pub fn file(&self, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.update();
+    }
+
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+            *BAR.lock().unwrap() = None;
+        }
+    }
+
+    fn update(&self) {
+        let files = self.files.load(Ordering::Relaxed);
+        let rate = files as f64 / self.started.elapsed().as_secs_f64().max(1.0);
+        let bytes = HumanBytes(self.bytes.load(Ordering::Relaxed));
+
+        match &self.bar {
+            Some(bar) => {
+                let current = self.current.lock().unwrap().join(", ");
+                bar.set_message(format!(
+                    "{} files ({:.1}/s), {} | {}",
+                    files, rate, bytes, current
+                ));
+            }
+            None => {
+                let mut reported = self.reported.lock().unwrap();
+                if reported.elapsed() < REPORT_INTERVAL {
+                    return;
+                }
+                *reported = Instant::now();
+                tracing::info!(
+                    "Progress: {}/{} repositories, {} files ({:.1}/s), {}",
+                    self.repos.load(Ordering::Relaxed),
+                    self.total,
+                    files,
+                    rate,
+                    bytes
+                );
+            }
+        }
+    }
+}
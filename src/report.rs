@@ -0,0 +1,282 @@
+use crate::git::RepoRef;
+use crate::indexer::{Phases, Summary};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Outcome of an `index` or `reindex` run, written with `--report`.
+#[derive(Serialize)]
+pub struct RunReport {
+    /// Unix time the run started at, in seconds.
+    pub started_at: u64,
+    pub duration_ms: u128,
+    pub backend: String,
+    pub collection: String,
+    pub dry_run: bool,
+    pub repos: Vec<RepoReport>,
+    pub total: Counts,
+    /// Set when the run swaps an alias to the collection.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap: Option<SwapReport>,
+}
+
+/// Outcome of `--swap-alias`.
+#[derive(Serialize)]
+pub struct SwapReport {
+    pub alias: String,
+    /// Why the alias was left on the previous collection.
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RepoReport {
+    pub url: String,
+    pub repo: String,
+    pub status: Status,
+    pub commit: Option<String>,
+    pub phases_ms: PhasesReport,
+    #[serde(flatten)]
+    pub counts: Counts,
+    pub languages: BTreeMap<String, usize>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Some documents were not stored.
+    Partial,
+    /// The repository could not be resolved, cloned or cleared.
+    Failed,
+}
+
+#[derive(Serialize)]
+pub struct PhasesReport {
+    pub resolve: u128,
+    pub clone: u128,
+    pub walk: u128,
+    pub render: u128,
+    pub upload: u128,
+}
+
+impl From<Phases> for PhasesReport {
+    fn from(phases: Phases) -> Self {
+        Self {
+            resolve: phases.resolve.as_millis(),
+            clone: phases.clone.as_millis(),
+            walk: phases.walk.as_millis(),
+            render: phases.render.as_millis(),
+            upload: phases.upload.as_millis(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Counts {
+    pub files: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// Files stored as a reference to another repository.
+    pub duplicates: usize,
+    pub chunks: usize,
+    pub bytes: u64,
+    /// Skipped files per reason.
+    pub skips: BTreeMap<String, usize>,
+}
+
+impl From<&Summary> for Counts {
+    fn from(summary: &Summary) -> Self {
+        Self {
+            files: summary.indexed,
+            failed: summary.failed,
+            skipped: summary.skipped,
+            duplicates: summary.duplicates,
+            chunks: summary.chunks,
+            bytes: summary.bytes,
+            skips: summary
+                .skips
+                .iter()
+                .map(|(skip, files)| (skip.to_string(), *files))
+                .collect(),
+        }
+    }
+}
+
+impl RepoReport {
+    pub fn new(url: &str, summary: &Summary) -> Self {
+        let status = if summary.failure.is_some() {
+            Status::Failed
+        } else if summary.failed > 0 {
+            Status::Partial
+        } else {
+            Status::Ok
+        };
+        let mut errors = Vec::new();
+        errors.extend(summary.failure.clone());
+        errors.extend(summary.errors.iter().cloned());
+
+        Self {
+            url: url.to_string(),
+            repo: RepoRef::parse(url)
+                .map(|repo| repo.path())
+                .unwrap_or_default(),
+            status,
+            commit: summary.commit.clone(),
+            phases_ms: summary.phases.into(),
+            counts: summary.into(),
+            languages: summary.languages.clone(),
+            errors,
+        }
+    }
+}
+
+impl RunReport {
+    pub fn new(
+        backend: &str,
+        collection: &str,
+        dry_run: bool,
+        started: SystemTime,
+        summaries: &[(String, Summary)],
+    ) -> Self {
+        let mut total = Summary::default();
+        for (_, summary) in summaries {
+            total.add(summary);
+        }
+
+        Self {
+            started_at: started
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            duration_ms: started.elapsed().unwrap_or_default().as_millis(),
+            backend: backend.to_string(),
+            collection: collection.to_string(),
+            dry_run,
+            repos: summaries
+                .iter()
+                .map(|(url, summary)| RepoReport::new(url, summary))
+                .collect(),
+            total: Counts::from(&total),
+            swap: None,
+        }
+    }
+
+    /// Record the swap of `alias` to the collection.
+    pub fn swapped(&mut self, alias: &str, swapped: &Result<(), String>) {
+        self.swap = Some(SwapReport {
+            alias: alias.to_string(),
+            error: swapped.as_ref().err().cloned(),
+        });
+    }
+
+    /// Repositories that did not end with `Status::Ok`.
+    pub fn failures(&self) -> usize {
+        self.repos
+            .iter()
+            .filter(|repo| repo.status != Status::Ok)
+            .count()
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, json)
+            .map_err(|e| format!("Failed to write report '{}': {}", path.display(), e))
+    }
+
+    /// One test case per repository, for CI systems that read JUnit XML.
+    pub fn write_junit(&self, path: &Path) -> Result<(), String> {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuite name=\"hli index\" tests=\"{}\" failures=\"{}\" time=\"{}\">\n",
+            self.repos.len() + self.swap.is_some() as usize,
+            self.failures() + self.swap.as_ref().is_some_and(|swap| swap.error.is_some()) as usize,
+            seconds(self.duration_ms)
+        ));
+        for repo in &self.repos {
+            let p = &repo.phases_ms;
+            let time = p.resolve + p.clone + p.walk + p.render + p.upload;
+            xml.push_str(&format!(
+                "  <testcase classname=\"{}\" name=\"{}\" time=\"{}\"",
+                escape(&self.backend),
+                escape(&repo.repo),
+                seconds(time)
+            ));
+            if repo.status == Status::Ok {
+                xml.push_str("/>\n");
+                continue;
+            }
+            let message = match repo.status {
+                Status::Failed => "repository failed".to_string(),
+                _ => format!("{} files failed", repo.counts.failed),
+            };
+            xml.push_str(&format!(
+                ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>\n",
+                escape(&message),
+                escape(&repo.errors.join("\n"))
+            ));
+        }
+        if let Some(swap) = &self.swap {
+            xml.push_str(&format!(
+                "  <testcase classname=\"{}\" name=\"alias {}\"",
+                escape(&self.backend),
+                escape(&swap.alias)
+            ));
+            match &swap.error {
+                Some(error) => xml.push_str(&format!(
+                    ">\n    <failure message=\"alias not swapped\">{}</failure>\n  </testcase>\n",
+                    escape(error)
+                )),
+                None => xml.push_str("/>\n"),
+            }
+        }
+        xml.push_str("</testsuite>\n");
+
+        fs::write(path, xml)
+            .map_err(|e| format!("Failed to write report '{}': {}", path.display(), e))
+    }
+}
+
+fn seconds(millis: u128) -> String {
+    format!("{:.3}", Duration::from_millis(millis as u64).as_secs_f64())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_a_failed_swap() {
+        let summaries = [(
+            "https://github.com/ahmadrosid/hl".to_string(),
+            Summary {
+                indexed: 3,
+                ..Default::default()
+            },
+        )];
+        let mut report = RunReport::new("solr", "heline_1", false, SystemTime::now(), &summaries);
+        report.swapped("heline", &Err("2 of 3 documents stored".to_string()));
+
+        let path = std::env::temp_dir().join(format!("hli-report-{}.xml", std::process::id()));
+        report.write_junit(&path).unwrap();
+        let xml = fs::read_to_string(&path).unwrap();
+        assert!(xml.contains("tests=\"2\" failures=\"1\""));
+        assert!(xml.contains("name=\"alias heline\""));
+        assert!(xml.contains("2 of 3 documents stored"));
+
+        report.write_json(&path).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(json["swap"]["alias"], "heline");
+        assert_eq!(json["swap"]["error"], "2 of 3 documents stored");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Local};
+use croner::Cron;
+use std::time::{Duration, SystemTime};
+
+/// When a repository is checked for new commits.
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    Interval(Duration),
+    /// A 5 field cron expression, in local time.
+    Cron(Box<Cron>),
+}
+
+impl Schedule {
+    /// `cron` when given, otherwise every `interval` seconds.
+    pub fn new(interval: u64, cron: Option<&str>) -> Result<Self, String> {
+        match cron {
+            Some(pattern) => Cron::new(pattern)
+                .parse()
+                .map(|cron| Schedule::Cron(Box::new(cron)))
+                .map_err(|e| format!("Invalid cron expression '{}': {}", pattern, e)),
+            None if interval == 0 => Err("The interval must be at least 1 second".to_string()),
+            None => Ok(Schedule::Interval(Duration::from_secs(interval))),
+        }
+    }
+
+    /// The first run after `time`.
+    pub fn next(&self, time: SystemTime) -> SystemTime {
+        match self {
+            Schedule::Interval(interval) => time + *interval,
+            Schedule::Cron(cron) => {
+                let time: DateTime<Local> = time.into();
+                match cron.find_next_occurrence(&time, false) {
+                    Ok(next) => next.into(),
+                    // Patterns that never match again, e.g. February 30th.
+                    Err(_) => SystemTime::UNIX_EPOCH + Duration::from_secs(u32::MAX as u64),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2001-09-09 01:46:40 UTC.
+    const TIME: u64 = 1_000_000_000;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn runs_every_interval() {
+        let schedule = Schedule::new(600, None).unwrap();
+        assert_eq!(schedule.next(at(TIME)), at(TIME + 600));
+    }
+
+    #[test]
+    fn rejects_an_empty_interval() {
+        let error = Schedule::new(0, None).unwrap_err();
+        assert_eq!(error, "The interval must be at least 1 second");
+    }
+
+    #[test]
+    fn runs_on_the_cron_expression() {
+        // Quarter hours are the same in every time zone.
+        let schedule = Schedule::new(600, Some("*/15 * * * *")).unwrap();
+        assert_eq!(schedule.next(at(TIME)), at(TIME + 800));
+        assert_eq!(schedule.next(at(TIME + 800)), at(TIME + 800 + 900));
+    }
+
+    #[test]
+    fn rejects_an_invalid_cron_expression() {
+        let error = Schedule::new(600, Some("every hour")).unwrap_err();
+        assert!(error.starts_with("Invalid cron expression 'every hour'"));
+    }
+
+    #[test]
+    fn never_runs_on_a_date_that_does_not_exist() {
+        let schedule = Schedule::new(600, Some("0 0 30 2 *")).unwrap();
+        assert!(schedule.next(at(TIME)) > at(u32::MAX as u64 - 1));
+    }
+}
@@ -0,0 +1,324 @@
+use heline_indexer::blobs::BlobStore;
+use heline_indexer::cache::RenderCache;
+use heline_indexer::config::Config;
+use heline_indexer::git::{self, RepoRef};
+use heline_indexer::indexer::{Indexer, Summary};
+use heline_indexer::sink::Sink;
+use heline_indexer::state::{self, StateStore};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::AbortHandle;
+use tracing::Instrument;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Job {
+    pub id: u64,
+    pub url: String,
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    /// Delete the documents of the repository before indexing it.
+    pub reindex: bool,
+    pub status: JobStatus,
+    /// Unix times in seconds.
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub commit: Option<String>,
+    pub indexed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub chunks: usize,
+    pub error: Option<String>,
+}
+
+pub enum CancelError {
+    NotFound,
+    Finished,
+}
+
+struct Entry {
+    job: Job,
+    handle: Option<AbortHandle>,
+}
+
+/// Index jobs, run by at most `server.workers` tasks at a time. Jobs of the
+/// same repository never run concurrently since they share the clone. The
+/// sink keeps its buffers per repository, jobs of other repositories do not
+/// flush each other's documents.
+pub struct Queue {
+    config: Arc<Config>,
+    sink: Arc<Sink>,
+    state: Arc<StateStore>,
+    blobs: Option<BlobStore>,
+    cache: Option<RenderCache>,
+    workers: Semaphore,
+    jobs: Mutex<BTreeMap<u64, Entry>>,
+    repos: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    next_id: AtomicU64,
+}
+
+impl Queue {
+    pub fn new(
+        config: Arc<Config>,
+        sink: Arc<Sink>,
+        state: Arc<StateStore>,
+        blobs: Option<BlobStore>,
+        cache: Option<RenderCache>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            workers: Semaphore::new(config.server.workers),
+            config,
+            sink,
+            state,
+            blobs,
+            cache,
+            jobs: Mutex::new(BTreeMap::new()),
+            repos: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    pub fn submit(self: &Arc<Self>, url: &str, git_ref: Option<&str>, reindex: bool) -> Job {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let job = Job {
+            id,
+            url: url.to_string(),
+            git_ref: git_ref.map(str::to_string),
+            reindex,
+            status: JobStatus::Queued,
+            created_at: state::now(),
+            started_at: None,
+            finished_at: None,
+            commit: None,
+            indexed: 0,
+            failed: 0,
+            skipped: 0,
+            chunks: 0,
+            error: None,
+        };
+
+        let mut jobs = self.jobs.lock().unwrap();
+        self.prune(&mut jobs);
+        let queue = self.clone();
+        let span = tracing::info_span!("job", id, url = %url);
+        let handle = tokio::spawn(queue.run(job.clone()).instrument(span));
+        jobs.insert(
+            id,
+            Entry {
+                job: job.clone(),
+                handle: Some(handle.abort_handle()),
+            },
+        );
+        tracing::info!("Queued job {} for '{}'", id, url);
+        job
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(&id).map(|entry| entry.job.clone())
+    }
+
+    /// A job of `url` waiting for a worker, changed to index `git_ref` so
+    /// another one is not needed.
+    pub fn pending(&self, url: &str, git_ref: &str) -> Option<Job> {
+        let key = repo_key(url);
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = jobs
+            .values_mut()
+            .filter(|entry| entry.job.status == JobStatus::Queued)
+            .find(|entry| repo_key(&entry.job.url) == key)?;
+        entry.job.git_ref = Some(git_ref.to_string());
+        Some(entry.job.clone())
+    }
+
+    /// The most recent jobs first.
+    pub fn list(&self, limit: usize) -> Vec<Job> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.values()
+            .rev()
+            .take(limit)
+            .map(|entry| entry.job.clone())
+            .collect()
+    }
+
+    pub fn cancel(&self, id: u64) -> Result<Job, CancelError> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = jobs.get_mut(&id).ok_or(CancelError::NotFound)?;
+        if entry.job.status.is_finished() {
+            return Err(CancelError::Finished);
+        }
+        if let Some(handle) = entry.handle.take() {
+            handle.abort();
+        }
+        entry.job.status = JobStatus::Cancelled;
+        entry.job.finished_at = Some(state::now());
+        tracing::info!("Cancelled job {}", id);
+        Ok(entry.job.clone())
+    }
+
+    async fn run(self: Arc<Self>, job: Job) {
+        // Jobs waiting for another job of their repository hold no worker.
+        let repo = self.repo_lock(&job.url);
+        let _clone = repo.lock().await;
+        let _permit = self.workers.acquire().await;
+        // The ref may have changed while the job was queued.
+        let mut started = None;
+        if !self.update(job.id, |job| {
+            job.status = JobStatus::Running;
+            job.started_at = Some(state::now());
+            started = Some(job.clone());
+        }) {
+            return;
+        }
+        let job = started.unwrap_or(job);
+
+        // Read before the state is reset to running.
+        let base = match job.reindex {
+            true => None,
+            false => self.state.indexed_commit(&job.url),
+        };
+        if let Err(e) = self.state.start(&job.url) {
+            tracing::warn!("Failed to record the state: {}", e);
+        }
+        let mut running = Running {
+            queue: &self,
+            url: &job.url,
+            done: false,
+        };
+        let summary = self.index(&job, base.as_deref()).await;
+        running.done = true;
+        if let Err(e) = self.state.finish(&job.url, &summary) {
+            tracing::warn!("Failed to record the state: {}", e);
+        }
+
+        self.update(job.id, |job| finish(job, &summary));
+    }
+
+    // Index the files changed since `base`, every file without one.
+    async fn index(&self, job: &Job, base: Option<&str>) -> Summary {
+        if let Err(e) = git::get_repo(&job.url, &self.config.providers).await {
+            tracing::error!("Failed to resolve: {}", e);
+            return Summary::failed(format!("Failed to resolve: {}", e));
+        }
+        let mut indexer = match Indexer::new(&job.url, &*self.sink, &self.config) {
+            Ok(indexer) => indexer,
+            Err(e) => return Summary::failed(e),
+        };
+        if let Some(blobs) = &self.blobs {
+            indexer = indexer.with_blobs(blobs);
+        }
+        if let Some(cache) = &self.cache {
+            indexer = indexer.with_cache(cache);
+        }
+        if job.reindex {
+            if let Err(e) = indexer.remove_repo().await {
+                tracing::error!("Failed to delete documents: {}", e);
+                return Summary::failed(format!("Failed to delete documents: {}", e));
+            }
+        }
+        if let Some(base) = base {
+            indexer = indexer.with_base(base);
+        }
+        // Without a ref the default branch, or the ref the url links to, is
+        // fetched again, a repository cloned earlier would be stale otherwise.
+        let git_ref = job.git_ref.clone().or_else(|| indexer.git_ref.clone());
+        indexer
+            .with_ref(git_ref.as_deref().unwrap_or("HEAD"))
+            .process()
+            .await
+    }
+
+    // Apply `f` unless the job was cancelled, returns whether it was applied.
+    fn update(&self, id: u64, f: impl FnOnce(&mut Job)) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        match jobs.get_mut(&id) {
+            Some(entry) if entry.job.status != JobStatus::Cancelled => {
+                f(&mut entry.job);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn repo_lock(&self, url: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut repos = self.repos.lock().unwrap();
+        repos.entry(repo_key(url)).or_default().clone()
+    }
+
+    // Drop the oldest finished jobs beyond `server.history`.
+    fn prune(&self, jobs: &mut BTreeMap<u64, Entry>) {
+        let finished: Vec<u64> = jobs
+            .values()
+            .filter(|entry| entry.job.status.is_finished())
+            .map(|entry| entry.job.id)
+            .collect();
+        let excess = finished.len().saturating_sub(self.config.server.history);
+        for id in &finished[..excess] {
+            jobs.remove(id);
+        }
+    }
+}
+
+// `host/owner/name`, the same for every url of a repository.
+fn repo_key(url: &str) -> String {
+    RepoRef::parse(url).map_or_else(|_| url.to_string(), |repo| repo.key())
+}
+
+// Records a job that is aborted while it indexes as failed and drops what the
+// sink still buffers for its repository.
+struct Running<'a> {
+    queue: &'a Queue,
+    url: &'a str,
+    done: bool,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Ok(repo) = RepoRef::parse(self.url) {
+            self.queue.sink.discard(&repo.path());
+        }
+        let summary = Summary::failed("Cancelled".to_string());
+        if let Err(e) = self.queue.state.finish(self.url, &summary) {
+            tracing::warn!("Failed to record the state: {}", e);
+        }
+    }
+}
+
+fn finish(job: &mut Job, summary: &Summary) {
+    job.status = match summary.failure {
+        Some(_) => JobStatus::Failed,
+        None => JobStatus::Done,
+    };
+    job.finished_at = Some(state::now());
+    job.commit = summary.commit.clone();
+    job.indexed = summary.indexed;
+    job.failed = summary.failed;
+    job.skipped = summary.skipped;
+    job.chunks = summary.chunks;
+    job.error = summary
+        .failure
+        .clone()
+        .or_else(|| summary.errors.first().cloned());
+}
@@ -0,0 +1,180 @@
+mod jobs;
+mod webhook;
+
+use crate::command;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use heline_indexer::config::{Config, ProviderConfig};
+use heline_indexer::git::{self, RepoRef};
+use heline_indexer::sink::Sink;
+use heline_indexer::state::StateStore;
+use jobs::{CancelError, Queue};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+#[derive(Clone)]
+struct AppState {
+    config: Arc<Config>,
+    queue: Arc<Queue>,
+}
+
+#[derive(Deserialize)]
+struct JobRequest {
+    url: String,
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
+    #[serde(default)]
+    reindex: bool,
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    limit: Option<usize>,
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// Serve the job api on `server.listen` until the process is stopped.
+pub async fn run(config: Config) -> Result<(), String> {
+    let backend = &config.backend;
+    let sink = Sink::new(&backend.options(&backend.location(), &backend.core))?;
+    let state_file = &config.index.state_file;
+    let state = StateStore::open(state_file)
+        .map_err(|e| format!("Failed to open state '{}': {}", state_file.display(), e))?;
+    let blobs = command::index::open_blobs(&config)?;
+    let cache = command::index::open_cache(&config)?;
+
+    let listen = config.server.listen.clone();
+    let config = Arc::new(config);
+    let app_state = AppState {
+        queue: Queue::new(
+            config.clone(),
+            Arc::new(sink),
+            Arc::new(state),
+            blobs,
+            cache,
+        ),
+        config,
+    };
+    // Webhooks are signed by the forges instead of carrying the token.
+    let app = Router::new()
+        .route("/jobs", post(create_job).get(list_jobs))
+        .route("/jobs/:id", get(get_job))
+        .route("/jobs/:id/cancel", post(cancel_job))
+        .layer(middleware::from_fn_with_state(app_state.clone(), authorize))
+        .route("/webhooks/:forge", post(webhook::receive))
+        .route("/metrics", get(metrics))
+        .with_state(app_state);
+
+    let listener = tokio::net::TcpListener::bind(&listen)
+        .await
+        .map_err(|e| format!("Failed to listen on '{}': {}", listen, e))?;
+    tracing::info!("Listening on {}", listen);
+    axum::serve(listener, app)
+        .await
+        .map_err(|e| format!("Server error: {}", e))
+}
+
+/// Serve `/metrics` on `listen` in the background.
+pub async fn serve_metrics(listen: &str) -> Result<(), String> {
+    let app = Router::new().route("/metrics", get(metrics));
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| format!("Failed to listen on '{}': {}", listen, e))?;
+    tracing::info!("Serving metrics on {}", listen);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("Metrics server error: {}", e);
+        }
+    });
+    Ok(())
+}
+
+async fn metrics() -> impl IntoResponse {
+    let content_type = "text/plain; version=0.0.4";
+    (
+        [(header::CONTENT_TYPE, content_type)],
+        heline_indexer::metrics::gather(),
+    )
+}
+
+async fn authorize(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if let Some(token) = &state.config.server.token {
+        let bearer = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if bearer != Some(token.as_str()) {
+            let msg = "Missing or invalid bearer token".to_string();
+            return ApiError(StatusCode::UNAUTHORIZED, msg).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+async fn create_job(
+    State(state): State<AppState>,
+    Json(request): Json<JobRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate_url(&request.url, &state.config.providers)
+        .map_err(|msg| ApiError(StatusCode::UNPROCESSABLE_ENTITY, msg))?;
+    let job = state
+        .queue
+        .submit(&request.url, request.git_ref.as_deref(), request.reindex);
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+async fn list_jobs(
+    State(state): State<AppState>,
+    Query(query): Query<ListQuery>,
+) -> impl IntoResponse {
+    Json(state.queue.list(query.limit.unwrap_or(50)))
+}
+
+async fn get_job(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, ApiError> {
+    match state.queue.get(id) {
+        Some(job) => Ok(Json(job)),
+        None => Err(not_found(id)),
+    }
+}
+
+async fn cancel_job(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, ApiError> {
+    match state.queue.cancel(id) {
+        Ok(job) => Ok(Json(job)),
+        Err(CancelError::NotFound) => Err(not_found(id)),
+        Err(CancelError::Finished) => Err(ApiError(
+            StatusCode::CONFLICT,
+            format!("Job {} already finished", id),
+        )),
+    }
+}
+
+fn not_found(id: u64) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("Job {} not found", id))
+}
+
+fn validate_url(url: &str, providers: &ProviderConfig) -> Result<(), String> {
+    let repo = RepoRef::parse(url)?;
+    if !git::is_supported_host(&repo.host, providers) {
+        return Err(format!("Unsupported git host: {}", repo.host));
+    }
+    Ok(())
+}
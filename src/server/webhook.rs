@@ -0,0 +1,286 @@
+use super::{ApiError, AppState};
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use heline_indexer::git::{self, RepoRef};
+use heline_indexer::manifest;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+/// The fields of a push event, GitHub, GitLab and Gitea payloads only differ
+/// in where the repository is.
+#[derive(Deserialize)]
+struct Push {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    after: String,
+    repository: Repository,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    // `web_url` on GitLab.
+    #[serde(alias = "web_url")]
+    html_url: String,
+    default_branch: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Forge {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+impl Forge {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "github" => Some(Forge::GitHub),
+            "gitlab" => Some(Forge::GitLab),
+            "gitea" => Some(Forge::Gitea),
+            _ => None,
+        }
+    }
+
+    fn event<'a>(&self, headers: &'a HeaderMap) -> &'a str {
+        let name = match self {
+            Forge::GitHub => "X-GitHub-Event",
+            Forge::GitLab => "X-Gitlab-Event",
+            Forge::Gitea => "X-Gitea-Event",
+        };
+        header(headers, name).unwrap_or_default()
+    }
+
+    fn payload(&self, body: &[u8]) -> serde_json::Result<Push> {
+        let mut value: Value = serde_json::from_slice(body)?;
+        // GitLab has the web url in `project`, its `repository` is a
+        // deprecated subset.
+        if let Forge::GitLab = self {
+            value["repository"] = value["project"].take();
+        }
+        serde_json::from_value(value)
+    }
+
+    fn is_push(&self, event: &str) -> bool {
+        match self {
+            Forge::GitLab => event == "Push Hook",
+            Forge::GitHub | Forge::Gitea => event == "push",
+        }
+    }
+
+    // GitHub and Gitea sign the body with HMAC-SHA256, GitLab sends the
+    // secret itself.
+    fn verify(&self, headers: &HeaderMap, body: &[u8], secret: &str) -> bool {
+        match self {
+            Forge::GitHub => header(headers, "X-Hub-Signature-256")
+                .and_then(|signature| signature.strip_prefix("sha256="))
+                .is_some_and(|signature| verify_hmac(secret, body, signature)),
+            Forge::Gitea => header(headers, "X-Gitea-Signature")
+                .is_some_and(|signature| verify_hmac(secret, body, signature)),
+            Forge::GitLab => header(headers, "X-Gitlab-Token")
+                .is_some_and(|token| constant_time_eq(token.as_bytes(), secret.as_bytes())),
+        }
+    }
+}
+
+/// Index the commit pushed to the default branch of a repository.
+pub async fn receive(
+    State(state): State<AppState>,
+    Path(forge): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let forge = Forge::from_name(&forge).ok_or_else(|| {
+        ApiError(
+            StatusCode::NOT_FOUND,
+            format!(
+                "Unknown forge '{}', expected github, gitlab or gitea",
+                forge
+            ),
+        )
+    })?;
+    let secret = state
+        .config
+        .server
+        .webhook_secret
+        .as_deref()
+        .ok_or_else(|| {
+            let msg = "Webhooks are disabled, server.webhook_secret is not set".to_string();
+            ApiError(StatusCode::FORBIDDEN, msg)
+        })?;
+    if !forge.verify(&headers, &body, secret) {
+        let msg = "Invalid webhook signature".to_string();
+        return Err(ApiError(StatusCode::UNAUTHORIZED, msg));
+    }
+
+    let event = forge.event(&headers);
+    if !forge.is_push(event) {
+        return Ok(ignored(&format!("'{}' is not a push event", event)));
+    }
+    let push = forge
+        .payload(&body)
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("Invalid payload: {}", e)))?;
+
+    let branch = match push.git_ref.strip_prefix("refs/heads/") {
+        Some(branch) => branch,
+        None => return Ok(ignored(&format!("'{}' is not a branch", push.git_ref))),
+    };
+    if push.after.chars().all(|c| c == '0') {
+        return Ok(ignored(&format!("Branch '{}' was deleted", branch)));
+    }
+    // Only the default branch of a repository is indexed.
+    if push.repository.default_branch.as_deref() != Some(branch) {
+        return Ok(ignored(&format!("'{}' is not the default branch", branch)));
+    }
+    let url = match manifest_entry(&state, &push.repository.html_url).await {
+        Ok(url) => url,
+        Err(reason) => return Ok(ignored(&reason)),
+    };
+
+    // The files changed since the indexed commit are indexed again.
+    let job = match state.queue.pending(&url, &push.after) {
+        Some(job) => job,
+        None => state.queue.submit(&url, Some(&push.after), false),
+    };
+    Ok((StatusCode::ACCEPTED, Json(job)).into_response())
+}
+
+// The manifest entry of the repository at `url`, so the jobs and the state
+// use the same url as `hli index`.
+async fn manifest_entry(state: &AppState, url: &str) -> Result<String, String> {
+    let repo = RepoRef::parse(url)?;
+    if !git::is_supported_host(&repo.host, &state.config.providers) {
+        return Err(format!("Unsupported git host: {}", repo.host));
+    }
+    let manifest = match &state.config.server.manifest {
+        Some(manifest) => manifest,
+        None => return Ok(url.to_string()),
+    };
+    manifest::load(manifest, &state.config.providers)
+        .await?
+        .into_iter()
+        .find(|entry| manifest::repo_key(entry) == repo.key())
+        .ok_or_else(|| format!("'{}' is not in the manifest", url))
+}
+
+fn ignored(reason: &str) -> Response {
+    tracing::debug!("Ignored webhook: {}", reason);
+    Json(json!({ "ignored": reason })).into_response()
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn verify_hmac(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = match hex::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key size");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::jobs::Queue;
+    use heline_indexer::config::Config;
+    use heline_indexer::sink::Sink;
+    use heline_indexer::state::StateStore;
+    use std::sync::Arc;
+
+    // The example of the GitHub documentation on validating webhooks.
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str = "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn verifies_hmac_signatures() {
+        assert!(verify_hmac(SECRET, BODY, SIGNATURE));
+        assert!(!verify_hmac(SECRET, b"Hello, World?", SIGNATURE));
+        assert!(!verify_hmac("another secret", BODY, SIGNATURE));
+        assert!(!verify_hmac(SECRET, BODY, "not hex"));
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+    }
+
+    #[test]
+    fn verifies_the_forge_signatures() {
+        let github = headers(&[("X-Hub-Signature-256", &format!("sha256={}", SIGNATURE))]);
+        assert!(Forge::GitHub.verify(&github, BODY, SECRET));
+        assert!(!Forge::GitHub.verify(&github, b"Hello, World?", SECRET));
+        // The signature without its prefix is not accepted.
+        let bare = headers(&[("X-Hub-Signature-256", SIGNATURE)]);
+        assert!(!Forge::GitHub.verify(&bare, BODY, SECRET));
+
+        let gitea = headers(&[("X-Gitea-Signature", SIGNATURE)]);
+        assert!(Forge::Gitea.verify(&gitea, BODY, SECRET));
+        assert!(!Forge::Gitea.verify(&gitea, b"Hello, World?", SECRET));
+
+        let gitlab = headers(&[("X-Gitlab-Token", SECRET)]);
+        assert!(Forge::GitLab.verify(&gitlab, BODY, SECRET));
+        let tampered = headers(&[("X-Gitlab-Token", "It's a secret to everybody")]);
+        assert!(!Forge::GitLab.verify(&tampered, BODY, SECRET));
+        assert!(!Forge::GitLab.verify(&HeaderMap::new(), BODY, SECRET));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_bad_signature() {
+        let mut config = Config::default();
+        config.server.webhook_secret = Some(SECRET.to_string());
+        let state_file = format!("hli-webhook-{}.json", std::process::id());
+        config.index.state_file = std::env::temp_dir().join(state_file);
+        let config = Arc::new(config);
+        let state = StateStore::open(&config.index.state_file).unwrap();
+        let queue = Queue::new(
+            config.clone(),
+            Arc::new(Sink::DryRun),
+            Arc::new(state),
+            None,
+            None,
+        );
+        let state = AppState { config, queue };
+
+        let signature = format!("sha256={}", SIGNATURE);
+        let headers = headers(&[
+            ("X-Hub-Signature-256", &signature),
+            ("X-GitHub-Event", "ping"),
+        ]);
+        let tampered = Bytes::from_static(b"Hello, World?");
+        let forge = Path("github".to_string());
+        let rejected = receive(State(state.clone()), forge, headers.clone(), tampered).await;
+        assert!(matches!(
+            rejected,
+            Err(ApiError(StatusCode::UNAUTHORIZED, _))
+        ));
+
+        // A valid signature gets through to the event, nothing is queued.
+        let forge = Path("github".to_string());
+        let accepted = receive(State(state), forge, headers, Bytes::from_static(BODY)).await;
+        let response = accepted.ok().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,564 @@
+use crate::export::writer::ExportWriter;
+use crate::local::index::LocalIndex;
+use crate::solr::client::{GitFile, SolrError};
+use crate::{meilisearch, opensearch, typesense};
+use crate::{metrics, solr};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+/// 1-based inclusive range of the source lines held by a chunk.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Lines {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A single chunk as its own document, for engines without partial updates.
+///
+/// Ids are derived from the file id and the first line of the chunk so a
+/// re-index overwrites the previous chunks of a file.
+#[derive(Serialize, Clone, Debug)]
+pub struct ChunkDocument {
+    pub id: String,
+    /// Id of the `GitFile` the chunk belongs to.
+    pub file: String,
+    pub file_id: String,
+    pub owner_id: String,
+    pub path: String,
+    pub repo: String,
+    pub branch: String,
+    pub lang: String,
+    pub content: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub blob: String,
+    pub duplicate_of: String,
+}
+
+impl ChunkDocument {
+    pub fn new(data: &GitFile, lines: Lines) -> Self {
+        Self {
+            id: format!("{}-{}", encode_id(&data.id), lines.start),
+            file: data.id.to_string(),
+            file_id: data.file_id.to_string(),
+            owner_id: data.owner_id.to_string(),
+            path: data.path.to_string(),
+            repo: data.repo.to_string(),
+            branch: data.branch.to_string(),
+            lang: data.lang.to_string(),
+            content: data.content.join("\n"),
+            start_line: lines.start,
+            end_line: lines.end,
+            blob: data.blob.to_string(),
+            duplicate_of: data.duplicate_of.to_string(),
+        }
+    }
+}
+
+/// Distinct ids of the files the chunks belong to.
+pub fn file_ids<'a>(documents: impl IntoIterator<Item = &'a ChunkDocument>) -> Vec<String> {
+    let mut ids: Vec<String> = Vec::new();
+    for document in documents {
+        if !ids.contains(&document.file) {
+            ids.push(document.file.to_string());
+        }
+    }
+    ids
+}
+
+// Meilisearch only accepts `[a-zA-Z0-9_-]` in ids, escape everything else
+// as `_xx` so distinct paths never collide.
+fn encode_id(id: &str) -> String {
+    let mut encoded = String::new();
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("_{:02x}", byte));
+        }
+    }
+    encoded
+}
+
+/// Failure of a batched write, holds the ids of the files that were not stored.
+#[derive(Debug)]
+pub struct BatchError {
+    pub backend: &'static str,
+    pub ids: Vec<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed to store {} documents: {}",
+            self.backend,
+            self.ids.len(),
+            self.reason
+        )
+    }
+}
+
+/// Names accepted by `Sink::new`.
+pub const BACKENDS: [&str; 7] = [
+    "solr",
+    "opensearch",
+    "elasticsearch",
+    "meilisearch",
+    "typesense",
+    "tantivy",
+    "ndjson",
+];
+
+/// Destination of the indexed documents, `Sink` implements it for the
+/// built-in backends.
+///
+/// The indexer calls `insert` with the first chunk of a file and `update`
+/// with each following one, then `flush` once a repository is done. Several
+/// repositories may be indexed at once, buffers are kept per repository.
+pub trait DocumentSink: Sync {
+    /// Create the document with its first chunk.
+    fn insert(
+        &self,
+        data: &GitFile,
+        lines: Lines,
+    ) -> impl Future<Output = Result<(), SinkError>> + Send;
+
+    /// Append the chunk of `data` to the document created by `insert`.
+    fn update(
+        &self,
+        data: &GitFile,
+        lines: Lines,
+    ) -> impl Future<Output = Result<(), SinkError>> + Send;
+
+    /// Send everything still buffered for `repo`, e.g. `owner/name`.
+    fn flush(&self, repo: &str) -> impl Future<Output = Result<(), SinkError>> + Send;
+
+    /// Remove every document of `repo`, e.g. `owner/name`.
+    fn delete_repo(&self, repo: &str) -> impl Future<Output = Result<(), SinkError>> + Send;
+
+    /// Remove the documents of the files `ids` of `repo`, e.g. the chunks
+    /// of a file that was only partly stored.
+    fn delete_files(
+        &self,
+        repo: &str,
+        ids: &[String],
+    ) -> impl Future<Output = Result<(), SinkError>> + Send;
+
+    /// Number of documents stored for `repo`.
+    fn count_repo(&self, repo: &str) -> impl Future<Output = Result<u64, SinkError>> + Send;
+
+    /// Whether the documents of a run are kept by the next one, so only the
+    /// files changed since can be indexed.
+    fn incremental(&self) -> bool {
+        true
+    }
+
+    /// Drop what is still buffered for `repo`, e.g. when indexing it is
+    /// cancelled.
+    fn discard(&self, repo: &str) {
+        let _ = repo;
+    }
+}
+
+/// Where the indexed documents are written to.
+pub enum Sink {
+    Solr {
+        base_url: String,
+        collection: String,
+        commit_within: u64,
+    },
+    OpenSearch(opensearch::client::Client),
+    Tantivy(Box<LocalIndex>),
+    Ndjson(ExportWriter),
+    Meilisearch(meilisearch::client::Client),
+    Typesense(typesense::client::Client),
+    /// Discards every document, for `index --dry-run`.
+    DryRun,
+}
+
+pub struct SinkOptions<'a> {
+    pub backend: &'a str,
+    /// Server url, or the output directory for `tantivy` and `ndjson`.
+    pub location: &'a str,
+    /// Solr collection, index or Typesense collection name.
+    pub collection: &'a str,
+    /// Gzip the `ndjson` files.
+    pub compress: bool,
+    pub api_key: Option<&'a str>,
+    /// Solr `commitWithin` in milliseconds.
+    pub commit_within: u64,
+}
+
+#[derive(Debug)]
+pub enum SinkError {
+    Solr(SolrError),
+    Batch(BatchError),
+    Tantivy(tantivy::TantivyError),
+    Export(io::Error),
+    Backend(String),
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkError::Solr(e) => e.fmt(f),
+            SinkError::Batch(e) => e.fmt(f),
+            SinkError::Tantivy(e) => e.fmt(f),
+            SinkError::Export(e) => e.fmt(f),
+            SinkError::Backend(msg) => msg.fmt(f),
+        }
+    }
+}
+
+impl SinkError {
+    /// Label of the error in the metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SinkError::Solr(e) => e.kind(),
+            SinkError::Batch(_) => "batch",
+            SinkError::Tantivy(_) => "index",
+            SinkError::Export(_) => "io",
+            SinkError::Backend(_) => "backend",
+        }
+    }
+
+    /// Number of documents lost with this error.
+    pub fn failed_documents(&self) -> usize {
+        match self {
+            SinkError::Batch(e) => e.ids.len(),
+            _ => 1,
+        }
+    }
+}
+
+impl From<SolrError> for SinkError {
+    fn from(e: SolrError) -> Self {
+        SinkError::Solr(e)
+    }
+}
+
+impl From<BatchError> for SinkError {
+    fn from(e: BatchError) -> Self {
+        SinkError::Batch(e)
+    }
+}
+
+impl From<tantivy::TantivyError> for SinkError {
+    fn from(e: tantivy::TantivyError) -> Self {
+        SinkError::Tantivy(e)
+    }
+}
+
+impl From<io::Error> for SinkError {
+    fn from(e: io::Error) -> Self {
+        SinkError::Export(e)
+    }
+}
+
+impl Sink {
+    pub fn new(options: &SinkOptions) -> Result<Self, String> {
+        let location = options.location;
+        let collection = options.collection;
+        match options.backend {
+            "solr" => Ok(Sink::Solr {
+                base_url: location.to_string(),
+                collection: collection.to_string(),
+                commit_within: options.commit_within,
+            }),
+            "opensearch" | "elasticsearch" => Ok(Sink::OpenSearch(
+                opensearch::client::Client::new(location, collection),
+            )),
+            "tantivy" => match LocalIndex::open(Path::new(location)) {
+                Ok(index) => Ok(Sink::Tantivy(Box::new(index))),
+                Err(e) => Err(format!("Failed to open index '{}': {}", location, e)),
+            },
+            "ndjson" => {
+                match ExportWriter::new(Path::new(location).to_path_buf(), options.compress) {
+                    Ok(writer) => Ok(Sink::Ndjson(writer)),
+                    Err(e) => Err(format!("Failed to create '{}': {}", location, e)),
+                }
+            }
+            "meilisearch" => Ok(Sink::Meilisearch(meilisearch::client::Client::new(
+                location,
+                collection,
+                options.api_key,
+            ))),
+            "typesense" => Ok(Sink::Typesense(typesense::client::Client::new(
+                location,
+                collection,
+                options.api_key,
+            ))),
+            backend => Err(format!("Unsupported backend: {}", backend)),
+        }
+    }
+
+    /// Create the document with its first chunk.
+    pub async fn insert(&self, data: &GitFile, lines: Lines) -> Result<(), SinkError> {
+        self.observe("insert", async {
+            match self {
+                Sink::Solr {
+                    base_url,
+                    collection,
+                    commit_within,
+                } => {
+                    solr::client::insert(data, base_url, collection, *commit_within).await?;
+                }
+                Sink::OpenSearch(client) => client.insert(data).await?,
+                Sink::Tantivy(index) => index.insert(data, lines)?,
+                Sink::Ndjson(writer) => writer.insert(data)?,
+                Sink::Meilisearch(client) => client.add(data, lines).await?,
+                Sink::Typesense(client) => client.add(data, lines).await?,
+                Sink::DryRun => {}
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Append the chunks of `data` to the document created by `insert`.
+    pub async fn update(&self, data: &GitFile, lines: Lines) -> Result<(), SinkError> {
+        self.observe("update", async {
+            match self {
+                Sink::Solr {
+                    base_url,
+                    collection,
+                    ..
+                } => {
+                    solr::client::update(data, base_url, collection).await?;
+                }
+                Sink::OpenSearch(client) => client.update(data).await?,
+                Sink::Tantivy(index) => index.update(data, lines)?,
+                Sink::Ndjson(writer) => writer.update(data)?,
+                Sink::Meilisearch(client) => client.add(data, lines).await?,
+                Sink::Typesense(client) => client.add(data, lines).await?,
+                Sink::DryRun => {}
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Send everything still buffered for `repo`, called once it is done.
+    pub async fn flush(&self, repo: &str) -> Result<(), SinkError> {
+        self.observe("flush", async {
+            match self {
+                Sink::Solr { .. } => {}
+                Sink::OpenSearch(client) => client.flush(repo).await?,
+                // Commits the other repositories indexed meanwhile too.
+                Sink::Tantivy(index) => index.commit()?,
+                Sink::Ndjson(writer) => writer.flush(repo)?,
+                Sink::Meilisearch(client) => client.flush(repo).await?,
+                Sink::Typesense(client) => client.flush(repo).await?,
+                Sink::DryRun => {}
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Remove every document of `repo`, e.g. `owner/name`.
+    pub async fn delete_repo(&self, repo: &str) -> Result<(), SinkError> {
+        self.observe("delete_repo", async {
+            match self {
+                Sink::Solr {
+                    base_url,
+                    collection,
+                    ..
+                } => {
+                    let query = solr::collections::repo_query(repo);
+                    solr::collections::delete_documents(base_url, collection, &query)
+                        .await
+                        .map_err(SinkError::Backend)?
+                }
+                Sink::OpenSearch(client) => {
+                    client.delete_repo(repo).await.map_err(SinkError::Backend)?
+                }
+                Sink::Tantivy(index) => index.delete_repo(repo)?,
+                Sink::Ndjson(writer) => writer.delete_repo(repo)?,
+                Sink::Meilisearch(client) => {
+                    client.delete_repo(repo).await.map_err(SinkError::Backend)?
+                }
+                Sink::Typesense(client) => {
+                    client.delete_repo(repo).await.map_err(SinkError::Backend)?
+                }
+                Sink::DryRun => {}
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Remove the documents of the files `ids` of `repo`.
+    pub async fn delete_files(&self, repo: &str, ids: &[String]) -> Result<(), SinkError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        self.observe("delete_files", async {
+            match self {
+                Sink::Solr {
+                    base_url,
+                    collection,
+                    ..
+                } => {
+                    let query = solr::collections::ids_query(ids);
+                    solr::collections::delete_documents(base_url, collection, &query)
+                        .await
+                        .map_err(SinkError::Backend)?
+                }
+                Sink::OpenSearch(client) => {
+                    client.delete_files(ids).await.map_err(SinkError::Backend)?
+                }
+                Sink::Tantivy(index) => index.delete_files(ids),
+                Sink::Ndjson(writer) => writer.delete_files(repo, ids)?,
+                Sink::Meilisearch(client) => {
+                    client.delete_files(ids).await.map_err(SinkError::Backend)?
+                }
+                Sink::Typesense(client) => {
+                    client.delete_files(ids).await.map_err(SinkError::Backend)?
+                }
+                Sink::DryRun => {}
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Drop what is still buffered for `repo`, the `ndjson` file is closed
+    /// with the records written so far.
+    pub fn discard(&self, repo: &str) {
+        match self {
+            Sink::OpenSearch(client) => client.discard(repo),
+            Sink::Ndjson(writer) => {
+                if let Err(e) = writer.flush(repo) {
+                    tracing::warn!("Failed to close the export of '{}': {}", repo, e);
+                }
+            }
+            Sink::Meilisearch(client) => client.discard(repo),
+            Sink::Typesense(client) => client.discard(repo),
+            Sink::Solr { .. } | Sink::Tantivy(_) | Sink::DryRun => {}
+        }
+    }
+
+    /// Name of the backend in the metrics.
+    pub fn backend(&self) -> &'static str {
+        match self {
+            Sink::Solr { .. } => "solr",
+            Sink::OpenSearch(_) => "opensearch",
+            Sink::Tantivy(_) => "tantivy",
+            Sink::Ndjson(_) => "ndjson",
+            Sink::Meilisearch(_) => "meilisearch",
+            Sink::Typesense(_) => "typesense",
+            Sink::DryRun => "dry_run",
+        }
+    }
+
+    // Record the latency of `operation` and its failure, a dry run writes
+    // nowhere and is not recorded.
+    async fn observe<T>(
+        &self,
+        operation: &str,
+        request: impl Future<Output = Result<T, SinkError>>,
+    ) -> Result<T, SinkError> {
+        let started = Instant::now();
+        let result = request.await;
+        if !matches!(self, Sink::DryRun) {
+            let backend = self.backend();
+            metrics::record_sink_request(backend, operation, started.elapsed());
+            if let Err(e) = &result {
+                metrics::record_sink_error(backend, operation, e.kind());
+            }
+        }
+        result
+    }
+
+    /// Whether the documents of a run are kept by the next one, the `ndjson`
+    /// files are written again by every run.
+    pub fn incremental(&self) -> bool {
+        !matches!(self, Sink::Ndjson(_))
+    }
+
+    /// Number of documents stored for `repo`, chunks for the engines storing
+    /// one document per chunk.
+    pub async fn count_repo(&self, repo: &str) -> Result<u64, SinkError> {
+        self.observe("count_repo", async {
+            let total = match self {
+                Sink::Solr {
+                    base_url,
+                    collection,
+                    ..
+                } => {
+                    let query = solr::collections::repo_query(repo);
+                    solr::collections::count_documents(base_url, collection, &query)
+                        .await
+                        .map_err(SinkError::Backend)?
+                }
+                Sink::OpenSearch(client) => {
+                    client.count_repo(repo).await.map_err(SinkError::Backend)?
+                }
+                Sink::Tantivy(index) => index.count_repo(repo)?,
+                Sink::Ndjson(writer) => writer.count_repo(repo)?,
+                Sink::Meilisearch(client) => {
+                    client.count_repo(repo).await.map_err(SinkError::Backend)?
+                }
+                Sink::Typesense(client) => {
+                    client.count_repo(repo).await.map_err(SinkError::Backend)?
+                }
+                Sink::DryRun => 0,
+            };
+            Ok(total)
+        })
+        .await
+    }
+}
+
+impl DocumentSink for Sink {
+    fn insert(
+        &self,
+        data: &GitFile,
+        lines: Lines,
+    ) -> impl Future<Output = Result<(), SinkError>> + Send {
+        Sink::insert(self, data, lines)
+    }
+
+    fn update(
+        &self,
+        data: &GitFile,
+        lines: Lines,
+    ) -> impl Future<Output = Result<(), SinkError>> + Send {
+        Sink::update(self, data, lines)
+    }
+
+    fn flush(&self, repo: &str) -> impl Future<Output = Result<(), SinkError>> + Send {
+        Sink::flush(self, repo)
+    }
+
+    fn delete_repo(&self, repo: &str) -> impl Future<Output = Result<(), SinkError>> + Send {
+        Sink::delete_repo(self, repo)
+    }
+
+    fn delete_files(
+        &self,
+        repo: &str,
+        ids: &[String],
+    ) -> impl Future<Output = Result<(), SinkError>> + Send {
+        Sink::delete_files(self, repo, ids)
+    }
+
+    fn count_repo(&self, repo: &str) -> impl Future<Output = Result<u64, SinkError>> + Send {
+        Sink::count_repo(self, repo)
+    }
+
+    fn incremental(&self) -> bool {
+        Sink::incremental(self)
+    }
+
+    fn discard(&self, repo: &str) {
+        Sink::discard(self, repo)
+    }
+}
@@ -1,6 +1,14 @@
-use serde::Serialize;
+use crate::metrics;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
 
-#[derive(Serialize, Clone, Debug)]
+const MAX_RETRIES: u32 = 3;
+const RETRY_DELAY_MS: u64 = 500;
+/// Longest `Retry-After` waited for, a longer one is cut down to this.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct GitFile {
     pub id: String,
     pub file_id: String,
@@ -10,44 +18,319 @@ pub struct GitFile {
     pub branch: String,
     pub lang: String,
     pub content: Vec<String>,
+    /// Git blob hash of the file, only set when identical files are
+    /// deduplicated.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub blob: String,
+    /// Id of the document holding the content of an identical file of
+    /// another repository, whose own content is left empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub duplicate_of: String,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct GithubFileUpdate {
     pub id: String,
     pub content: AddString,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AddString {
     pub add: Vec<String>,
 }
 
-pub async fn insert(data: &GitFile, base_url: &str) -> Result<String, reqwest::Error> {
-    let mut body: Vec<GitFile> = Vec::new();
-    body.push(data.clone());
+impl From<&GitFile> for GithubFileUpdate {
+    fn from(data: &GitFile) -> Self {
+        Self {
+            id: data.id.to_string(),
+            content: AddString {
+                add: data.content.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponseHeader {
+    pub status: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SolrResponse {
+    #[serde(rename = "responseHeader")]
+    pub response_header: ResponseHeader,
+    pub error: Option<ErrorDetail>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ErrorDetail {
+    #[serde(default)]
+    pub msg: String,
+    #[serde(default)]
+    pub code: u16,
+}
+
+#[derive(Debug)]
+pub enum SolrError {
+    /// The request never got a response, e.g. connection refused or timeout.
+    Request(reqwest::Error),
+    /// Solr answered with a 5xx status.
+    Server { status: u16, msg: String },
+    /// Solr, or a proxy in front of it, answered with a 429.
+    Throttled {
+        retry_after: Option<Duration>,
+        msg: String,
+    },
+    /// Solr rejected the document, e.g. a 400 for a schema violation.
+    Rejected { status: u16, msg: String },
+}
+
+impl SolrError {
+    /// Label of the error in the metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SolrError::Request(_) => "request",
+            SolrError::Server { .. } => "server",
+            SolrError::Throttled { .. } => "throttled",
+            SolrError::Rejected { .. } => "rejected",
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SolrError::Request(e) => e.is_timeout() || e.is_connect(),
+            SolrError::Server { .. } | SolrError::Throttled { .. } => true,
+            SolrError::Rejected { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for SolrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolrError::Request(e) => write!(f, "Solr request failed: {}", e),
+            SolrError::Server { status, msg } => write!(f, "Solr server error {}: {}", status, msg),
+            SolrError::Throttled { msg, .. } => write!(f, "Solr throttled 429: {}", msg),
+            SolrError::Rejected { status, msg } => write!(f, "Solr rejected {}: {}", status, msg),
+        }
+    }
+}
+
+pub async fn insert(
+    data: &GitFile,
+    base_url: &str,
+    collection: &str,
+    commit_within: u64,
+) -> Result<SolrResponse, SolrError> {
+    let body: Vec<GitFile> = vec![data.clone()];
     let url = format!(
-        "{}/solr/heline/update?&commitWithin=1000&overwrite=false&wt=json",
-        base_url
+        "{}/solr/{}/update?&commitWithin={}&overwrite=false&wt=json",
+        base_url, collection, commit_within
     );
-    let client = reqwest::Client::new();
-    let res = client.post(url).json(&body).send().await?;
-    let json = res.text().await?;
-    Ok(json)
-}
-
-pub async fn update(data: &GitFile, base_url: &str) -> Result<String, reqwest::Error> {
-    let data = data.clone();
-    let mut body: Vec<GithubFileUpdate> = Vec::new();
-    let update = GithubFileUpdate {
-        id: data.id,
-        content: AddString { add: data.content },
+    post(&url, &body).await
+}
+
+/// Insert `docs` in one request, replacing the documents with the same ids.
+pub async fn insert_all(
+    docs: &[GitFile],
+    base_url: &str,
+    collection: &str,
+    commit_within: u64,
+) -> Result<SolrResponse, SolrError> {
+    let url = format!(
+        "{}/solr/{}/update?&commitWithin={}&wt=json",
+        base_url, collection, commit_within
+    );
+    post(&url, docs).await
+}
+
+pub async fn update(
+    data: &GitFile,
+    base_url: &str,
+    collection: &str,
+) -> Result<SolrResponse, SolrError> {
+    append(&GithubFileUpdate::from(data), base_url, collection).await
+}
+
+/// Send an atomic update adding chunks to an existing document.
+pub async fn append(
+    update: &GithubFileUpdate,
+    base_url: &str,
+    collection: &str,
+) -> Result<SolrResponse, SolrError> {
+    let body = vec![update];
+    let url = format!("{}/solr/{}/update?wt=json", base_url, collection);
+    post(&url, &body).await
+}
+
+/// Send the atomic updates `updates` in one request.
+pub async fn append_all(
+    updates: &[GithubFileUpdate],
+    base_url: &str,
+    collection: &str,
+) -> Result<SolrResponse, SolrError> {
+    let url = format!("{}/solr/{}/update?wt=json", base_url, collection);
+    post(&url, updates).await
+}
+
+async fn post<T: Serialize + ?Sized>(url: &str, body: &T) -> Result<SolrResponse, SolrError> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .map_err(SolrError::Request)?;
+
+    let mut attempt = 0;
+    loop {
+        let started = Instant::now();
+        let result = send(&client, url, body).await;
+        metrics::record_solr_request(started.elapsed());
+        if let Err(e) = &result {
+            metrics::record_solr_error(e.kind());
+        }
+        match result {
+            Err(e) if e.is_retryable() && attempt < MAX_RETRIES => {
+                attempt += 1;
+                let backoff = Duration::from_millis(RETRY_DELAY_MS * 2u64.pow(attempt - 1));
+                let delay = match e {
+                    SolrError::Throttled {
+                        retry_after: Some(retry_after),
+                        ..
+                    } => retry_after.min(MAX_RETRY_AFTER),
+                    _ => backoff,
+                };
+                tracing::warn!(
+                    "{}, retrying in {}ms ({}/{})",
+                    e,
+                    delay.as_millis(),
+                    attempt,
+                    MAX_RETRIES
+                );
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn send<T: Serialize + ?Sized>(
+    client: &reqwest::Client,
+    url: &str,
+    body: &T,
+) -> Result<SolrResponse, SolrError> {
+    let res = client
+        .post(url)
+        .json(body)
+        .send()
+        .await
+        .map_err(SolrError::Request)?;
+    let status = res.status().as_u16();
+    let retry_after = res
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+    let text = res.text().await.map_err(SolrError::Request)?;
+
+    let response = serde_json::from_str::<SolrResponse>(&text);
+    let msg = match &response {
+        Ok(SolrResponse {
+            error: Some(error), ..
+        }) => error.msg.to_string(),
+        _ => text.to_string(),
     };
 
-    body.push(update);
-    let url = format!("{}/solr/heline/update", base_url);
-    let client = reqwest::Client::new();
-    let res = client.post(url).json(&body).send().await?;
-    let json = res.text().await?;
-    Ok(json)
+    if status == 429 {
+        return Err(SolrError::Throttled { retry_after, msg });
+    }
+    if status >= 500 {
+        return Err(SolrError::Server { status, msg });
+    }
+    match response {
+        Ok(response) if status < 400 && response.response_header.status == 0 => Ok(response),
+        Ok(response) => Err(SolrError::Rejected {
+            status: response.error.map(|e| e.code).unwrap_or(status),
+            msg,
+        }),
+        Err(_) => Err(SolrError::Rejected { status, msg }),
+    }
+}
+
+// `Retry-After` holds either seconds or an http date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let wait = date.signed_duration_since(chrono::Utc::now());
+    Some(wait.to_std().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(parse_retry_after("3"), Some(Duration::from_secs(3)));
+        let past = "Wed, 21 Oct 2015 07:28:00 GMT";
+        assert_eq!(parse_retry_after(past), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn leaves_out_the_blob_fields_without_dedupe() {
+        let mut file = GitFile {
+            id: "ahmadrosid/hl/hl/README.md".to_string(),
+            file_id: "github.com/ahmadrosid/hl/hl/README.md".to_string(),
+            owner_id: "0000".to_string(),
+            path: "hl".to_string(),
+            repo: "ahmadrosid/hl".to_string(),
+            branch: "main".to_string(),
+            lang: "Markdown".to_string(),
+            content: Vec::new(),
+            blob: String::new(),
+            duplicate_of: String::new(),
+        };
+        let json = serde_json::to_value(&file).unwrap();
+        assert!(json.get("blob").is_none());
+        assert!(json.get("duplicate_of").is_none());
+
+        file.blob = "1b4c2e0".to_string();
+        let json = serde_json::to_value(&file).unwrap();
+        assert_eq!(json["blob"], "1b4c2e0");
+        assert!(json.get("duplicate_of").is_none());
+    }
+
+    #[tokio::test]
+    async fn retries_throttled_requests() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let app = Router::new().route(
+            "/solr/heline/update",
+            post(move || async move {
+                if counter.fetch_add(1, Ordering::Relaxed) == 0 {
+                    let headers = [(header::RETRY_AFTER, "0")];
+                    return (StatusCode::TOO_MANY_REQUESTS, headers, "slow down").into_response();
+                }
+                r#"{"responseHeader":{"status":0}}"#.into_response()
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let update = GithubFileUpdate {
+            id: "ahmadrosid/hl/hl/README.md".to_string(),
+            content: AddString { add: Vec::new() },
+        };
+        let response = append(&update, &base_url, "heline").await;
+        assert!(response.is_ok());
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+    }
 }
@@ -0,0 +1,324 @@
+use serde_json::{json, Value};
+
+async fn admin(base_url: &str, params: &[(&str, &str)]) -> Result<Value, String> {
+    let url = format!("{}/solr/admin/collections", base_url);
+    let client = reqwest::Client::new();
+    let res = client
+        .get(url)
+        .query(params)
+        .query(&[("wt", "json")])
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let json = res.json::<Value>().await.map_err(|e| e.to_string())?;
+    match json.get("error").and_then(|e| e.get("msg")) {
+        Some(msg) => Err(msg.as_str().unwrap_or_default().to_string()),
+        None => Ok(json),
+    }
+}
+
+pub async fn create(base_url: &str, name: &str, config_set: &str) -> Result<(), String> {
+    admin(
+        base_url,
+        &[
+            ("action", "CREATE"),
+            ("name", name),
+            ("numShards", "1"),
+            ("collection.configName", config_set),
+        ],
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn delete(base_url: &str, name: &str) -> Result<(), String> {
+    admin(base_url, &[("action", "DELETE"), ("name", name)]).await?;
+    Ok(())
+}
+
+pub async fn get_alias(base_url: &str, alias: &str) -> Result<Option<String>, String> {
+    let json = admin(base_url, &[("action", "LISTALIASES")]).await?;
+    let collection = json
+        .get("aliases")
+        .and_then(|aliases| aliases.get(alias))
+        .and_then(|name| name.as_str())
+        .map(|name| name.to_string());
+    Ok(collection)
+}
+
+pub async fn set_alias(base_url: &str, alias: &str, collection: &str) -> Result<(), String> {
+    admin(
+        base_url,
+        &[
+            ("action", "CREATEALIAS"),
+            ("name", alias),
+            ("collections", collection),
+        ],
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn commit(base_url: &str, collection: &str) -> Result<(), String> {
+    let url = format!(
+        "{}/solr/{}/update?commit=true&wt=json",
+        base_url, collection
+    );
+    let client = reqwest::Client::new();
+    let res = client.get(url).send().await.map_err(|e| e.to_string())?;
+    let status = res.status();
+    let json = res.json::<Value>().await.unwrap_or_default();
+    match json.get("error").and_then(|e| e.get("msg")) {
+        Some(msg) => Err(msg.as_str().unwrap_or_default().to_string()),
+        None if !status.is_success() => {
+            Err(format!("Commit of '{}' failed: {}", collection, status))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Point `alias` to `collection` once it holds at least the `stored`
+/// documents. The collection the alias pointed to before is kept to roll
+/// back to, unless `drop_previous` is set. The new collection is dropped
+/// instead when anything fails, so the alias keeps serving the previous one.
+/// Returns the previous collection.
+pub async fn swap_alias(
+    base_url: &str,
+    alias: &str,
+    collection: &str,
+    stored: usize,
+    drop_previous: bool,
+) -> Result<Option<String>, String> {
+    let swapped = async {
+        let previous = get_alias(base_url, alias).await?;
+        commit(base_url, collection).await?;
+        let total = count_documents(base_url, collection, "*:*").await?;
+        if total == 0 || total < stored as u64 {
+            return Err(format!(
+                "Validation failed for '{}': expected {} documents, found {}. Alias '{}' still points to {}.",
+                collection,
+                stored,
+                total,
+                alias,
+                previous.as_deref().unwrap_or("nothing")
+            ));
+        }
+        set_alias(base_url, alias, collection).await?;
+        Ok(previous)
+    }
+    .await;
+
+    match swapped {
+        Ok(Some(previous)) if drop_previous && previous != collection => {
+            if let Err(e) = delete(base_url, &previous).await {
+                tracing::warn!("Failed to delete collection '{}': {}", previous, e);
+            }
+            Ok(Some(previous))
+        }
+        Ok(previous) => Ok(previous),
+        Err(e) => {
+            if let Err(e) = delete(base_url, collection).await {
+                tracing::warn!("Failed to delete collection '{}': {}", collection, e);
+            }
+            Err(e)
+        }
+    }
+}
+
+pub async fn count_documents(base_url: &str, collection: &str, query: &str) -> Result<u64, String> {
+    let url = format!("{}/solr/{}/select", base_url, collection);
+    let client = reqwest::Client::new();
+    let res = client
+        .get(url)
+        .query(&[("q", query), ("rows", "0"), ("wt", "json")])
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let json = res.json::<Value>().await.map_err(|e| e.to_string())?;
+    match json.get("response").and_then(|r| r.get("numFound")) {
+        Some(total) => Ok(total.as_u64().unwrap_or_default()),
+        None => Err(format!(
+            "Unexpected response from '{}': {}",
+            collection, json
+        )),
+    }
+}
+
+pub async fn delete_documents(base_url: &str, collection: &str, query: &str) -> Result<(), String> {
+    let url = format!(
+        "{}/solr/{}/update?commit=true&wt=json",
+        base_url, collection
+    );
+    let client = reqwest::Client::new();
+    let res = client
+        .post(url)
+        .json(&json!({ "delete": { "query": query } }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let json = res.json::<Value>().await.map_err(|e| e.to_string())?;
+    match json.get("error").and_then(|e| e.get("msg")) {
+        Some(msg) => Err(msg.as_str().unwrap_or_default().to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Query matching every document of `repo`.
+pub fn repo_query(repo: &str) -> String {
+    format!("repo:{}", quote(repo))
+}
+
+/// Query matching the documents of the files `ids`.
+pub fn ids_query(ids: &[String]) -> String {
+    let ids: Vec<String> = ids.iter().map(|id| quote(id)).collect();
+    format!("id:({})", ids.join(" OR "))
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, Query, State};
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// A Solr answering the Collections API, `documents` in every collection
+    /// and commits of `broken` with a 500.
+    #[derive(Clone, Default)]
+    struct Solr {
+        alias: Option<String>,
+        documents: u64,
+        broken: Option<String>,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    async fn admin(
+        State(solr): State<Solr>,
+        Query(params): Query<HashMap<String, String>>,
+    ) -> Json<Value> {
+        let action = &params["action"];
+        let name = params.get("name").cloned().unwrap_or_default();
+        solr.calls
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", action, name));
+        match &action[..] {
+            "LISTALIASES" => Json(json!({ "aliases": { "heline": solr.alias } })),
+            _ => Json(json!({ "responseHeader": { "status": 0 } })),
+        }
+    }
+
+    async fn update(
+        State(solr): State<Solr>,
+        Path(collection): Path<String>,
+    ) -> (StatusCode, Json<Value>) {
+        solr.calls
+            .lock()
+            .unwrap()
+            .push(format!("COMMIT {}", collection));
+        if solr.broken.as_deref() == Some(&collection[..]) {
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({})));
+        }
+        (
+            StatusCode::OK,
+            Json(json!({ "responseHeader": { "status": 0 } })),
+        )
+    }
+
+    async fn select(State(solr): State<Solr>) -> Json<Value> {
+        Json(json!({ "response": { "numFound": solr.documents } }))
+    }
+
+    async fn serve(solr: Solr) -> String {
+        let app = Router::new()
+            .route("/solr/admin/collections", get(admin))
+            .route("/solr/:collection/update", get(update))
+            .route("/solr/:collection/select", get(select))
+            .with_state(solr);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn swap_alias_keeps_the_previous_collection() {
+        let solr = Solr {
+            alias: Some("heline_1".to_string()),
+            documents: 10,
+            ..Default::default()
+        };
+        let base_url = serve(solr.clone()).await;
+        let previous = swap_alias(&base_url, "heline", "heline_2", 10, false).await;
+        assert_eq!(previous, Ok(Some("heline_1".to_string())));
+        let calls = solr.calls.lock().unwrap().clone();
+        assert_eq!(
+            calls,
+            ["LISTALIASES ", "COMMIT heline_2", "CREATEALIAS heline"]
+        );
+    }
+
+    #[tokio::test]
+    async fn swap_alias_drops_the_previous_collection() {
+        let solr = Solr {
+            alias: Some("heline_1".to_string()),
+            documents: 10,
+            ..Default::default()
+        };
+        let base_url = serve(solr.clone()).await;
+        let previous = swap_alias(&base_url, "heline", "heline_2", 10, true).await;
+        assert_eq!(previous, Ok(Some("heline_1".to_string())));
+        let calls = solr.calls.lock().unwrap().clone();
+        assert_eq!(
+            calls,
+            [
+                "LISTALIASES ",
+                "COMMIT heline_2",
+                "CREATEALIAS heline",
+                "DELETE heline_1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn swap_alias_keeps_the_alias_when_validation_fails() {
+        let solr = Solr {
+            alias: Some("heline_1".to_string()),
+            documents: 9,
+            ..Default::default()
+        };
+        let base_url = serve(solr.clone()).await;
+        let swapped = swap_alias(&base_url, "heline", "heline_2", 10, true).await;
+        assert!(swapped
+            .unwrap_err()
+            .contains("expected 10 documents, found 9"));
+        let calls = solr.calls.lock().unwrap().clone();
+        assert_eq!(
+            calls,
+            ["LISTALIASES ", "COMMIT heline_2", "DELETE heline_2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn swap_alias_keeps_the_alias_when_the_commit_fails() {
+        let solr = Solr {
+            documents: 10,
+            broken: Some("heline_2".to_string()),
+            ..Default::default()
+        };
+        let base_url = serve(solr.clone()).await;
+        let swapped = swap_alias(&base_url, "heline", "heline_2", 10, true).await;
+        assert!(swapped.unwrap_err().contains("500"));
+        let calls = solr.calls.lock().unwrap().clone();
+        assert_eq!(
+            calls,
+            ["LISTALIASES ", "COMMIT heline_2", "DELETE heline_2"]
+        );
+    }
+}
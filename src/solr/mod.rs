@@ -1 +1,3 @@
 pub mod client;
+pub mod collections;
+pub mod schema;
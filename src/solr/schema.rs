@@ -0,0 +1,325 @@
+use serde_json::{json, Value};
+
+pub struct Field {
+    pub name: &'static str,
+    pub field_type: &'static str,
+    pub multi_valued: bool,
+}
+
+const CONTENT_TYPE: &str = "html_text";
+
+// `id` is the schema uniqueKey so it is not managed here.
+pub const FIELDS: [Field; 9] = [
+    Field {
+        name: "file_id",
+        field_type: "string",
+        multi_valued: false,
+    },
+    Field {
+        name: "owner_id",
+        field_type: "string",
+        multi_valued: false,
+    },
+    Field {
+        name: "path",
+        field_type: "string",
+        multi_valued: false,
+    },
+    Field {
+        name: "repo",
+        field_type: "string",
+        multi_valued: false,
+    },
+    Field {
+        name: "branch",
+        field_type: "string",
+        multi_valued: false,
+    },
+    Field {
+        name: "lang",
+        field_type: "string",
+        multi_valued: false,
+    },
+    Field {
+        name: "content",
+        field_type: CONTENT_TYPE,
+        multi_valued: true,
+    },
+    Field {
+        name: "blob",
+        field_type: "string",
+        multi_valued: false,
+    },
+    Field {
+        name: "duplicate_of",
+        field_type: "string",
+        multi_valued: false,
+    },
+];
+
+pub enum Drift {
+    MissingFieldType(&'static str),
+    MissingField(&'static Field),
+    ChangedField(&'static Field, String),
+}
+
+impl std::fmt::Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::MissingFieldType(name) => write!(f, "missing field type '{}'", name),
+            Drift::MissingField(field) => write!(f, "missing field '{}'", field.name),
+            Drift::ChangedField(field, current) => write!(
+                f,
+                "field '{}' is {}, expected {}",
+                field.name,
+                current,
+                describe(field.field_type, field.multi_valued)
+            ),
+        }
+    }
+}
+
+fn describe(field_type: &str, multi_valued: bool) -> String {
+    if multi_valued {
+        format!("{} (multiValued)", field_type)
+    } else {
+        field_type.to_string()
+    }
+}
+
+fn content_field_type() -> Value {
+    json!({
+        "name": CONTENT_TYPE,
+        "class": "solr.TextField",
+        "positionIncrementGap": "100",
+        "analyzer": {
+            "charFilters": [{ "class": "solr.HTMLStripCharFilterFactory" }],
+            "tokenizer": { "class": "solr.StandardTokenizerFactory" },
+            "filters": [{ "class": "solr.LowerCaseFilterFactory" }]
+        }
+    })
+}
+
+fn field_definition(field: &Field) -> Value {
+    json!({
+        "name": field.name,
+        "type": field.field_type,
+        "multiValued": field.multi_valued,
+        "indexed": true,
+        "stored": true
+    })
+}
+
+async fn get(base_url: &str, core: &str, path: &str) -> Result<Value, String> {
+    let url = format!("{}/solr/{}/schema/{}?wt=json", base_url, core, path);
+    let client = reqwest::Client::new();
+    let res = client.get(url).send().await.map_err(|e| e.to_string())?;
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(format!("Core '{}' not found!", core));
+    }
+    res.json::<Value>().await.map_err(|e| e.to_string())
+}
+
+pub async fn check(base_url: &str, core: &str) -> Result<Vec<Drift>, String> {
+    let mut drifts = Vec::new();
+
+    let field_types = get(base_url, core, "fieldtypes").await?;
+    let has_content_type = field_types
+        .get("fieldTypes")
+        .and_then(|types| types.as_array())
+        .map(|types| types.iter().any(|t| t["name"] == CONTENT_TYPE))
+        .unwrap_or(false);
+    if !has_content_type {
+        drifts.push(Drift::MissingFieldType(CONTENT_TYPE));
+    }
+
+    let fields = get(base_url, core, "fields").await?;
+    let fields = fields
+        .get("fields")
+        .and_then(|fields| fields.as_array())
+        .cloned()
+        .unwrap_or_default();
+    for field in FIELDS.iter() {
+        match fields.iter().find(|f| f["name"] == field.name) {
+            None => drifts.push(Drift::MissingField(field)),
+            Some(current) => {
+                let field_type = current["type"].as_str().unwrap_or_default();
+                let multi_valued = current["multiValued"].as_bool().unwrap_or(false);
+                if field_type != field.field_type || multi_valued != field.multi_valued {
+                    drifts.push(Drift::ChangedField(
+                        field,
+                        describe(field_type, multi_valued),
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(drifts)
+}
+
+/// Add missing field types and fields and replace the ones that drifted.
+pub async fn apply(base_url: &str, core: &str) -> Result<Vec<Drift>, String> {
+    let drifts = check(base_url, core).await?;
+    if drifts.is_empty() {
+        return Ok(drifts);
+    }
+
+    let mut commands = serde_json::Map::new();
+    let mut add_fields = Vec::new();
+    let mut replace_fields = Vec::new();
+    for drift in &drifts {
+        match drift {
+            Drift::MissingFieldType(_) => {
+                commands.insert("add-field-type".to_string(), content_field_type());
+            }
+            Drift::MissingField(field) => add_fields.push(field_definition(field)),
+            Drift::ChangedField(field, _) => replace_fields.push(field_definition(field)),
+        }
+    }
+    if !add_fields.is_empty() {
+        commands.insert("add-field".to_string(), Value::Array(add_fields));
+    }
+    if !replace_fields.is_empty() {
+        commands.insert("replace-field".to_string(), Value::Array(replace_fields));
+    }
+
+    let url = format!("{}/solr/{}/schema?wt=json", base_url, core);
+    let client = reqwest::Client::new();
+    let res = client
+        .post(url)
+        .json(&Value::Object(commands))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let json = res.json::<Value>().await.map_err(|e| e.to_string())?;
+    if let Some(error) = json.get("error") {
+        return Err(format!("Failed to update schema of '{}': {}", core, error));
+    }
+
+    Ok(drifts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::routing::{get as get_route, post};
+    use axum::{Json, Router};
+    use std::sync::{Arc, Mutex};
+
+    /// A core with `fields`, and the content field type unless
+    /// `without_content_type`. Schema updates are recorded.
+    #[derive(Clone, Default)]
+    struct Schema {
+        fields: Vec<Value>,
+        without_content_type: bool,
+        updates: Arc<Mutex<Vec<Value>>>,
+    }
+
+    async fn field_types(State(schema): State<Schema>) -> Json<Value> {
+        let mut types = vec![json!({ "name": "string" })];
+        if !schema.without_content_type {
+            types.push(json!({ "name": CONTENT_TYPE }));
+        }
+        Json(json!({ "fieldTypes": types }))
+    }
+
+    async fn fields(State(schema): State<Schema>) -> Json<Value> {
+        Json(json!({ "fields": schema.fields }))
+    }
+
+    async fn update(State(schema): State<Schema>, Json(body): Json<Value>) -> Json<Value> {
+        schema.updates.lock().unwrap().push(body);
+        Json(json!({ "responseHeader": { "status": 0 } }))
+    }
+
+    async fn serve(schema: Schema) -> String {
+        let app = Router::new()
+            .route("/solr/heline/schema/fieldtypes", get_route(field_types))
+            .route("/solr/heline/schema/fields", get_route(fields))
+            .route("/solr/heline/schema", post(update))
+            .with_state(schema);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    fn expected() -> Vec<Value> {
+        FIELDS.iter().map(field_definition).collect()
+    }
+
+    // The schema without `blob`, with `path` as text and `content` single
+    // valued.
+    fn drifted() -> Schema {
+        let mut fields = expected();
+        fields.retain(|field| field["name"] != "blob");
+        for field in &mut fields {
+            match field["name"].as_str() {
+                Some("path") => field["type"] = json!("text_general"),
+                Some("content") => field["multiValued"] = json!(false),
+                _ => {}
+            }
+        }
+        Schema {
+            fields,
+            without_content_type: true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_no_drift_in_a_matching_schema() {
+        let schema = Schema {
+            fields: expected(),
+            ..Default::default()
+        };
+        let base_url = serve(schema.clone()).await;
+        assert!(check(&base_url, "heline").await.unwrap().is_empty());
+        assert!(apply(&base_url, "heline").await.unwrap().is_empty());
+        assert!(schema.updates.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn detects_missing_and_changed_fields() {
+        let base_url = serve(drifted()).await;
+        let drifts: Vec<String> = check(&base_url, "heline")
+            .await
+            .unwrap()
+            .iter()
+            .map(Drift::to_string)
+            .collect();
+        assert_eq!(
+            drifts,
+            [
+                "missing field type 'html_text'",
+                "field 'path' is text_general, expected string",
+                "field 'content' is html_text, expected html_text (multiValued)",
+                "missing field 'blob'",
+            ]
+        );
+        let missing = check(&base_url, "other").await;
+        assert_eq!(missing.err().as_deref(), Some("Core 'other' not found!"));
+    }
+
+    #[tokio::test]
+    async fn adds_and_replaces_the_fields_that_drifted() {
+        let schema = drifted();
+        let base_url = serve(schema.clone()).await;
+        assert_eq!(apply(&base_url, "heline").await.unwrap().len(), 4);
+        let updates = schema.updates.lock().unwrap();
+        assert_eq!(updates.len(), 1);
+        let update = &updates[0];
+        assert_eq!(update["add-field-type"]["name"], CONTENT_TYPE);
+        assert_eq!(update["add-field"], json!([field_definition(&FIELDS[7])]));
+        let replaced: Vec<&Value> = update["replace-field"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| &field["name"])
+            .collect();
+        assert_eq!(replaced, ["path", "content"]);
+        assert_eq!(update["replace-field"][1]["multiValued"], true);
+    }
+}
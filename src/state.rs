@@ -0,0 +1,324 @@
+use crate::indexer::Summary;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Delay before the first retry of a failed repository, doubled on every
+/// further failure.
+const BACKOFF_SECS: u64 = 300;
+const MAX_BACKOFF_SECS: u64 = 24 * 3600;
+/// Lines the journal holds beyond one per repository before it is compacted
+/// again, a long running daemon appends two per repository and check.
+const COMPACT_SLACK: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RepoStatus {
+    /// Started but never finished, e.g. the run crashed.
+    Running,
+    Indexed,
+    /// Indexed with some documents failing.
+    Partial,
+    Failed,
+}
+
+impl fmt::Display for RepoStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            RepoStatus::Running => "running",
+            RepoStatus::Indexed => "indexed",
+            RepoStatus::Partial => "partial",
+            RepoStatus::Failed => "failed",
+        };
+        f.write_str(status)
+    }
+}
+
+/// Last known state of a repository.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RepoState {
+    pub url: String,
+    pub status: RepoStatus,
+    /// Commit of the last run that indexed the files.
+    pub commit: Option<String>,
+    pub failure: Option<String>,
+    /// Failed runs since the last success.
+    pub attempts: u32,
+    /// Unix time of the last change, in seconds.
+    pub updated_at: u64,
+}
+
+impl RepoState {
+    /// Unix time from which a failed repository is retried by `--resume`.
+    pub fn retry_at(&self) -> u64 {
+        if self.attempts == 0 {
+            return self.updated_at;
+        }
+        let backoff = BACKOFF_SECS.saturating_mul(1 << (self.attempts - 1).min(16));
+        self.updated_at + backoff.min(MAX_BACKOFF_SECS)
+    }
+}
+
+/// Repository states, kept in a json lines journal so a crash loses at most
+/// the line being written. The journal is compacted when opened and once it
+/// outgrows the repositories by `COMPACT_SLACK` lines.
+pub struct StateStore {
+    path: PathBuf,
+    repos: Mutex<HashMap<String, RepoState>>,
+    /// Lines of the journal.
+    lines: Mutex<usize>,
+}
+
+impl StateStore {
+    /// Replay the journal at `path`, then compact it to one line per
+    /// repository.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut repos = HashMap::new();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    path: path.to_path_buf(),
+                    repos: Mutex::new(repos),
+                    lines: Mutex::new(0),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(file).lines() {
+            // A torn last line is skipped.
+            if let Ok(state) = serde_json::from_str::<RepoState>(&line?) {
+                repos.insert(state.url.to_string(), state);
+            }
+        }
+
+        let store = Self {
+            path: path.to_path_buf(),
+            lines: Mutex::new(repos.len()),
+            repos: Mutex::new(repos),
+        };
+        store.compact(&store.repos.lock().unwrap())?;
+        Ok(store)
+    }
+
+    pub fn get(&self, url: &str) -> Option<RepoState> {
+        self.repos.lock().unwrap().get(url).cloned()
+    }
+
+    /// Commit of the last run that indexed every file of `url`, the base of
+    /// an incremental run.
+    pub fn indexed_commit(&self, url: &str) -> Option<String> {
+        let state = self.get(url)?;
+        match state.status {
+            RepoStatus::Indexed => state.commit,
+            _ => None,
+        }
+    }
+
+    /// Every known repository, sorted by url.
+    pub fn all(&self) -> Vec<RepoState> {
+        let mut states: Vec<RepoState> = self.repos.lock().unwrap().values().cloned().collect();
+        states.sort_by(|a, b| a.url.cmp(&b.url));
+        states
+    }
+
+    pub fn start(&self, url: &str) -> io::Result<()> {
+        let state = match self.get(url) {
+            Some(state) => RepoState {
+                status: RepoStatus::Running,
+                updated_at: now(),
+                ..state
+            },
+            None => RepoState {
+                url: url.to_string(),
+                status: RepoStatus::Running,
+                commit: None,
+                failure: None,
+                attempts: 0,
+                updated_at: now(),
+            },
+        };
+        self.append(state)
+    }
+
+    pub fn finish(&self, url: &str, summary: &Summary) -> io::Result<()> {
+        let previous = self.get(url);
+        let attempts = previous.as_ref().map(|state| state.attempts).unwrap_or(0);
+        let (status, failure) = if let Some(failure) = &summary.failure {
+            (RepoStatus::Failed, Some(failure.to_string()))
+        } else if summary.failed > 0 {
+            let failure = format!("{} documents failed", summary.failed);
+            (RepoStatus::Partial, Some(failure))
+        } else {
+            (RepoStatus::Indexed, None)
+        };
+        let succeeded = status == RepoStatus::Indexed;
+
+        self.append(RepoState {
+            url: url.to_string(),
+            status,
+            commit: match &summary.commit {
+                Some(commit) if status != RepoStatus::Failed => Some(commit.to_string()),
+                _ => previous.and_then(|state| state.commit),
+            },
+            failure,
+            attempts: if succeeded { 0 } else { attempts + 1 },
+            updated_at: now(),
+        })
+    }
+
+    fn append(&self, state: RepoState) -> io::Result<()> {
+        let mut repos = self.repos.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&state)?)?;
+        repos.insert(state.url.to_string(), state);
+        let mut lines = self.lines.lock().unwrap();
+        *lines += 1;
+        if *lines > repos.len() + COMPACT_SLACK {
+            self.compact(&repos)?;
+            *lines = repos.len();
+        }
+        Ok(())
+    }
+
+    fn compact(&self, repos: &HashMap<String, RepoState>) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        let mut states: Vec<&RepoState> = repos.values().collect();
+        states.sort_by(|a, b| a.url.cmp(&b.url));
+        for state in states {
+            writeln!(file, "{}", serde_json::to_string(state)?)?;
+        }
+        file.sync_all()?;
+        fs::rename(tmp, &self.path)
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> (StateStore, PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("hli-state-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        (StateStore::open(&path).unwrap(), path)
+    }
+
+    #[test]
+    fn compacts_the_journal_as_it_grows() {
+        let (state, path) = store("compact");
+        let summary = Summary::default();
+        for _ in 0..COMPACT_SLACK {
+            for url in ["https://github.com/a/a", "https://github.com/b/b"] {
+                state.start(url).unwrap();
+                state.finish(url, &summary).unwrap();
+            }
+        }
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines <= 2 + COMPACT_SLACK, "{} lines", lines);
+        drop(state);
+
+        let state = StateStore::open(&path).unwrap();
+        assert_eq!(state.all().len(), 2);
+        let a = state.get("https://github.com/a/a").unwrap();
+        assert_eq!(a.status, RepoStatus::Indexed);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let state = |attempts| RepoState {
+            url: "https://github.com/a/a".to_string(),
+            status: RepoStatus::Failed,
+            commit: None,
+            failure: Some("clone failed".to_string()),
+            attempts,
+            updated_at: 1000,
+        };
+        assert_eq!(state(0).retry_at(), 1000);
+        assert_eq!(state(1).retry_at(), 1000 + BACKOFF_SECS);
+        assert_eq!(state(2).retry_at(), 1000 + 2 * BACKOFF_SECS);
+        assert_eq!(state(3).retry_at(), 1000 + 4 * BACKOFF_SECS);
+        // Capped at a day, however often it failed.
+        assert_eq!(state(10).retry_at(), 1000 + MAX_BACKOFF_SECS);
+        assert_eq!(state(u32::MAX).retry_at(), 1000 + MAX_BACKOFF_SECS);
+    }
+
+    #[test]
+    fn resumes_after_a_partial_run() {
+        let (state, path) = store("resume");
+        let indexed = |commit: &str| Summary {
+            commit: Some(commit.to_string()),
+            ..Default::default()
+        };
+        let (a, b, c, d) = (
+            "https://github.com/a/a",
+            "https://github.com/b/b",
+            "https://github.com/c/c",
+            "https://github.com/d/d",
+        );
+        for url in [a, b, c, d] {
+            state.start(url).unwrap();
+            state.finish(url, &indexed("1b4c2e")).unwrap();
+        }
+        // The next run indexes `a`, fails `b`, partly stores `c` and
+        // crashes while indexing `d`, the last line is torn.
+        state.start(a).unwrap();
+        state.finish(a, &indexed("d0a1f3")).unwrap();
+        state.start(b).unwrap();
+        let failed = Summary::failed("clone failed".to_string());
+        state.finish(b, &failed).unwrap();
+        state.start(c).unwrap();
+        let partial = Summary {
+            failed: 2,
+            ..indexed("d0a1f3")
+        };
+        state.finish(c, &partial).unwrap();
+        state.start(d).unwrap();
+        drop(state);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"url\":\"{}\",\"sta", d).unwrap();
+
+        let state = StateStore::open(&path).unwrap();
+        let get = |url| state.get(url).unwrap();
+        assert_eq!(get(a).status, RepoStatus::Indexed);
+        assert_eq!(state.indexed_commit(a).as_deref(), Some("d0a1f3"));
+        // A failed run keeps the commit of the last one.
+        assert_eq!(get(b).status, RepoStatus::Failed);
+        assert_eq!(get(b).commit.as_deref(), Some("1b4c2e"));
+        assert_eq!(get(b).attempts, 1);
+        assert!(get(b).retry_at() > now());
+        // Partly stored files are indexed again in full.
+        assert_eq!(get(c).status, RepoStatus::Partial);
+        assert_eq!(state.indexed_commit(c), None);
+        assert_eq!(get(d).status, RepoStatus::Running);
+        assert_eq!(state.indexed_commit(d), None);
+
+        // Another failure doubles the backoff, a success resets it.
+        state.start(b).unwrap();
+        state.finish(b, &failed).unwrap();
+        assert_eq!(get(b).attempts, 2);
+        assert_eq!(get(b).retry_at(), get(b).updated_at + 2 * BACKOFF_SECS);
+        state.start(b).unwrap();
+        state.finish(b, &indexed("d0a1f3")).unwrap();
+        assert_eq!(get(b).attempts, 0);
+        assert_eq!(get(b).failure, None);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use crate::sink::{self, BatchError, ChunkDocument, Lines};
+use crate::solr::client::GitFile;
+use reqwest::{Method, RequestBuilder};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const BACKEND: &str = "Typesense";
+const BATCH_SIZE: usize = 1000;
+
+/// Buffers chunk documents per repository and upserts them through the
+/// import endpoint.
+pub struct Client {
+    base_url: String,
+    collection: String,
+    api_key: Option<String>,
+    documents: Mutex<HashMap<String, Vec<ChunkDocument>>>,
+}
+
+impl Client {
+    pub fn new(base_url: &str, collection: &str, api_key: Option<&str>) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            collection: collection.to_string(),
+            api_key: api_key.map(|key| key.to_string()),
+            documents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn add(&self, data: &GitFile, lines: Lines) -> Result<(), BatchError> {
+        let batch = {
+            let mut documents = self.documents.lock().unwrap();
+            let documents = documents.entry(data.repo.to_string()).or_default();
+            documents.push(ChunkDocument::new(data, lines));
+            if documents.len() < BATCH_SIZE {
+                return Ok(());
+            }
+            std::mem::take(documents)
+        };
+        self.import(batch).await
+    }
+
+    /// Upsert the documents still buffered for `repo`.
+    pub async fn flush(&self, repo: &str) -> Result<(), BatchError> {
+        let batch = self.documents.lock().unwrap().remove(repo);
+        match batch {
+            Some(batch) if !batch.is_empty() => self.import(batch).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Drop the documents still buffered for `repo`.
+    pub fn discard(&self, repo: &str) {
+        self.documents.lock().unwrap().remove(repo);
+    }
+
+    pub async fn delete_repo(&self, repo: &str) -> Result<(), String> {
+        self.delete(&filter(repo)).await
+    }
+
+    /// Remove the chunks of the files `ids`.
+    pub async fn delete_files(&self, ids: &[String]) -> Result<(), String> {
+        let ids: Vec<String> = ids.iter().map(|id| format!("`{}`", id)).collect();
+        self.delete(&format!("file:=[{}]", ids.join(","))).await
+    }
+
+    async fn delete(&self, filter_by: &str) -> Result<(), String> {
+        let path = format!("/collections/{}/documents", self.collection);
+        self.send(Method::DELETE, &path, &[("filter_by", filter_by)])
+            .await?;
+        Ok(())
+    }
+
+    /// Number of chunks indexed for `repo`.
+    pub async fn count_repo(&self, repo: &str) -> Result<u64, String> {
+        let path = format!("/collections/{}/documents/search", self.collection);
+        let query = [
+            ("q", "*"),
+            ("query_by", "content"),
+            ("filter_by", &filter(repo)),
+            ("per_page", "0"),
+        ];
+        let json = self.send(Method::GET, &path, &query).await?;
+        Ok(json["found"].as_u64().unwrap_or_default())
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Value, String> {
+        let res = request(&self.base_url, self.api_key.as_deref(), method, path)
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !res.status().is_success() {
+            let msg = res.text().await.unwrap_or_default();
+            return Err(format!("Typesense request failed: {}", msg));
+        }
+        res.json::<Value>().await.map_err(|e| e.to_string())
+    }
+
+    async fn import(&self, batch: Vec<ChunkDocument>) -> Result<(), BatchError> {
+        let mut body = String::new();
+        for document in &batch {
+            body.push_str(&serde_json::to_string(document).unwrap_or_default());
+            body.push('\n');
+        }
+
+        let path = format!(
+            "/collections/{}/documents/import?action=upsert",
+            self.collection
+        );
+        let res = request(&self.base_url, self.api_key.as_deref(), Method::POST, &path)
+            .header("Content-Type", "text/plain")
+            .body(body)
+            .send()
+            .await;
+        let error = |reason: String| BatchError {
+            backend: BACKEND,
+            ids: sink::file_ids(&batch),
+            reason,
+        };
+
+        let res = res.map_err(|e| error(e.to_string()))?;
+        if !res.status().is_success() {
+            let reason = format!("{} {}", res.status(), res.text().await.unwrap_or_default());
+            return Err(error(reason));
+        }
+        let text = res.text().await.map_err(|e| error(e.to_string()))?;
+
+        // The response has one result line per imported document, in order.
+        let mut failed = Vec::new();
+        let mut reason = String::new();
+        for (document, line) in batch.iter().zip(text.lines()) {
+            let result = serde_json::from_str::<Value>(line).unwrap_or_default();
+            if result["success"].as_bool() != Some(true) {
+                tracing::warn!("Failed to store '{}': {}", document.id, result["error"]);
+                reason = result["error"].as_str().unwrap_or_default().to_string();
+                failed.push(document);
+            }
+        }
+        if failed.is_empty() {
+            return Ok(());
+        }
+        Err(BatchError {
+            backend: BACKEND,
+            ids: sink::file_ids(failed),
+            reason,
+        })
+    }
+}
+
+fn filter(repo: &str) -> String {
+    format!("repo:=`{}`", repo)
+}
+
+pub fn request(
+    base_url: &str,
+    api_key: Option<&str>,
+    method: Method,
+    path: &str,
+) -> RequestBuilder {
+    let client = reqwest::Client::new();
+    let builder = client.request(method, format!("{}{}", base_url, path));
+    match api_key {
+        Some(key) => builder.header("X-TYPESENSE-API-KEY", key),
+        None => builder,
+    }
+}
@@ -0,0 +1,174 @@
+use crate::typesense::client::request;
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use std::fmt;
+
+pub struct Field {
+    pub name: &'static str,
+    pub field_type: &'static str,
+    pub facet: bool,
+}
+
+// Every field of a `ChunkDocument` besides `id`.
+pub const FIELDS: [Field; 12] = [
+    Field {
+        name: "file",
+        field_type: "string",
+        facet: true,
+    },
+    Field {
+        name: "file_id",
+        field_type: "string",
+        facet: false,
+    },
+    Field {
+        name: "owner_id",
+        field_type: "string",
+        facet: true,
+    },
+    Field {
+        name: "path",
+        field_type: "string",
+        facet: true,
+    },
+    Field {
+        name: "repo",
+        field_type: "string",
+        facet: true,
+    },
+    Field {
+        name: "branch",
+        field_type: "string",
+        facet: true,
+    },
+    Field {
+        name: "lang",
+        field_type: "string",
+        facet: true,
+    },
+    Field {
+        name: "content",
+        field_type: "string",
+        facet: false,
+    },
+    Field {
+        name: "start_line",
+        field_type: "int32",
+        facet: false,
+    },
+    Field {
+        name: "end_line",
+        field_type: "int32",
+        facet: false,
+    },
+    Field {
+        name: "blob",
+        field_type: "string",
+        facet: true,
+    },
+    Field {
+        name: "duplicate_of",
+        field_type: "string",
+        facet: true,
+    },
+];
+
+pub enum Drift {
+    MissingCollection(String),
+    MissingField(&'static Field),
+    ChangedField(&'static Field, String),
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::MissingCollection(name) => write!(f, "missing collection '{}'", name),
+            Drift::MissingField(field) => write!(f, "missing field '{}'", field.name),
+            Drift::ChangedField(field, current) => write!(
+                f,
+                "field '{}' is {}, expected {} (facet: {})",
+                field.name, current, field.field_type, field.facet
+            ),
+        }
+    }
+}
+
+fn definition(field: &Field) -> Value {
+    json!({ "name": field.name, "type": field.field_type, "facet": field.facet })
+}
+
+pub async fn check(
+    base_url: &str,
+    collection: &str,
+    api_key: Option<&str>,
+) -> Result<Vec<Drift>, String> {
+    let path = format!("/collections/{}", collection);
+    let res = request(base_url, api_key, Method::GET, &path)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if res.status() == StatusCode::NOT_FOUND {
+        return Ok(vec![Drift::MissingCollection(collection.to_string())]);
+    }
+    let schema = res.json::<Value>().await.map_err(|e| e.to_string())?;
+    let current = schema["fields"].as_array().cloned().unwrap_or_default();
+
+    let mut drifts = Vec::new();
+    for field in FIELDS.iter() {
+        match current.iter().find(|f| f["name"] == field.name) {
+            None => drifts.push(Drift::MissingField(field)),
+            Some(f) if f["type"] != field.field_type || f["facet"] != field.facet => {
+                let current = format!("{} (facet: {})", f["type"], f["facet"]);
+                drifts.push(Drift::ChangedField(field, current));
+            }
+            _ => {}
+        }
+    }
+    Ok(drifts)
+}
+
+/// Create the collection, or add the missing fields and replace the changed ones.
+pub async fn apply(
+    base_url: &str,
+    collection: &str,
+    api_key: Option<&str>,
+) -> Result<Vec<Drift>, String> {
+    let drifts = check(base_url, collection, api_key).await?;
+    if drifts.is_empty() {
+        return Ok(drifts);
+    }
+
+    let mut fields = Vec::new();
+    for drift in &drifts {
+        match drift {
+            Drift::MissingCollection(_) => {
+                let fields: Vec<Value> = FIELDS.iter().map(definition).collect();
+                let body = json!({ "name": collection, "fields": fields });
+                let builder = request(base_url, api_key, Method::POST, "/collections");
+                send(builder.json(&body)).await?;
+                return Ok(drifts);
+            }
+            Drift::MissingField(field) => fields.push(definition(field)),
+            // Typesense alters a field by dropping and adding it in one request.
+            Drift::ChangedField(field, _) => {
+                fields.push(json!({ "name": field.name, "drop": true }));
+                fields.push(definition(field));
+            }
+        }
+    }
+
+    let path = format!("/collections/{}", collection);
+    let body = json!({ "fields": fields });
+    send(request(base_url, api_key, Method::PATCH, &path).json(&body)).await?;
+
+    Ok(drifts)
+}
+
+async fn send(builder: reqwest::RequestBuilder) -> Result<(), String> {
+    let res = builder.send().await.map_err(|e| e.to_string())?;
+    if !res.status().is_success() {
+        let msg = res.text().await.unwrap_or_default();
+        return Err(format!("Typesense request failed: {}", msg));
+    }
+    Ok(())
+}